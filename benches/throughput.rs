@@ -0,0 +1,298 @@
+//! Push and pop throughput, reported in MB/s of payload.
+//!
+//! Message counts and sizes can be set through the environment, e.g.
+//! `DISK_MPMC_BENCH_MESSAGES=50000000 DISK_MPMC_BENCH_SIZES=100 cargo bench`
+//! for the counts the end-to-end tests in `src/lib.rs` used to run with.
+
+use std::{
+    hint::black_box,
+    path::{Path, PathBuf},
+    sync::{Arc, Barrier},
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use disk_mpmc::{manager::DataPagesManager, GenReceiver, Receiver, Sender, MAX_MESSAGES_PER_PAGE};
+use rand::random;
+
+const STOP: &[u8] = b"stop";
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Messages pushed or popped per iteration, `DISK_MPMC_BENCH_MESSAGES`.
+fn messages() -> u64 {
+    env_or("DISK_MPMC_BENCH_MESSAGES", 1_000_000)
+}
+
+/// Message sizes in bytes, `DISK_MPMC_BENCH_SIZES` as a comma separated list.
+fn sizes() -> Vec<usize> {
+    std::env::var("DISK_MPMC_BENCH_SIZES")
+        .ok()
+        .map(|v| v.split(',').filter_map(|s| s.trim().parse().ok()).collect())
+        .unwrap_or_else(|| vec![16, 100, 1024])
+}
+
+/// Producer and consumer threads each for the MPMC case, `DISK_MPMC_BENCH_THREADS`.
+fn threads() -> usize {
+    env_or("DISK_MPMC_BENCH_THREADS", 4)
+}
+
+fn mkdir_random() -> PathBuf {
+    let num: u64 = random();
+    let dir = std::env::temp_dir().join(format!("disk-mpmc-bench-{num:X}"));
+
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+/// Runs `f` on a fresh queue directory per iteration and sums the time it
+/// reports, leaving out creating and removing the directory.
+fn iter_fresh(iters: u64, mut f: impl FnMut(&Path) -> Duration) -> Duration {
+    (0..iters)
+        .map(|_| {
+            let path = mkdir_random();
+            let elapsed = f(&path);
+            std::fs::remove_dir_all(path).unwrap();
+
+            elapsed
+        })
+        .sum()
+}
+
+fn bench_push(c: &mut Criterion) {
+    let messages = messages();
+    let mut group = c.benchmark_group("push");
+    group.sample_size(10);
+
+    for size in sizes() {
+        let msg = vec![b'a'; size];
+        group.throughput(Throughput::BytesDecimal(messages * size as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &msg, |b, msg| {
+            b.iter_custom(|iters| {
+                iter_fresh(iters, |path| {
+                    let mut tx = Sender::new(DataPagesManager::new(path).unwrap()).unwrap();
+
+                    let now = Instant::now();
+                    for _ in 0..messages {
+                        tx.push(msg).unwrap();
+                    }
+                    now.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_pop(c: &mut Criterion) {
+    let messages = messages();
+    let mut group = c.benchmark_group("pop");
+    group.sample_size(10);
+
+    for size in sizes() {
+        let msg = vec![b'a'; size];
+        group.throughput(Throughput::BytesDecimal(messages * size as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &msg, |b, msg| {
+            b.iter_custom(|iters| {
+                iter_fresh(iters, |path| {
+                    let manager = DataPagesManager::new(path).unwrap();
+                    let mut tx = Sender::new(manager.clone()).unwrap();
+                    for _ in 0..messages {
+                        tx.push(msg).unwrap();
+                    }
+
+                    let mut rx = Receiver::new(0, manager).unwrap();
+
+                    let now = Instant::now();
+                    for _ in 0..messages {
+                        black_box(rx.pop().unwrap());
+                    }
+                    now.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// Producers and a work sharing group of consumers running at the same
+/// time, from the first push until the last message is popped.
+fn bench_mpmc(c: &mut Criterion) {
+    let messages = messages();
+    let threads = threads();
+    let mut group = c.benchmark_group(format!("mpmc/{threads}x{threads}"));
+    group.sample_size(10);
+
+    for size in sizes() {
+        let msg = vec![b'a'; size];
+        group.throughput(Throughput::BytesDecimal(messages * size as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &msg, |b, msg| {
+            b.iter_custom(|iters| {
+                iter_fresh(iters, |path| {
+                    let manager = DataPagesManager::new(path).unwrap();
+                    let rx = Receiver::new(0, manager.clone()).unwrap();
+                    let mut tx = Sender::new(manager).unwrap();
+                    let barrier = Arc::new(Barrier::new(threads * 2 + 1));
+
+                    let consumers: Vec<_> = (0..threads)
+                        .map(|_| {
+                            let mut rx = rx.clone();
+                            let barrier = barrier.clone();
+
+                            thread::spawn(move || {
+                                barrier.wait();
+                                while rx.pop().unwrap() != STOP {}
+                            })
+                        })
+                        .collect();
+
+                    let producers: Vec<_> = (0..threads as u64)
+                        .map(|i| {
+                            let mut tx = tx.clone();
+                            let barrier = barrier.clone();
+                            let msg = msg.clone();
+                            // spread the remainder over the first producers
+                            let share = messages / threads as u64
+                                + u64::from(i < messages % threads as u64);
+
+                            thread::spawn(move || {
+                                barrier.wait();
+                                for _ in 0..share {
+                                    tx.push(&msg).unwrap();
+                                }
+                            })
+                        })
+                        .collect();
+
+                    barrier.wait();
+                    let now = Instant::now();
+
+                    for h in producers {
+                        h.join().unwrap();
+                    }
+                    // every message is ahead of these, one per consumer
+                    for _ in 0..threads {
+                        tx.push(STOP).unwrap();
+                    }
+                    for h in consumers {
+                        h.join().unwrap();
+                    }
+
+                    now.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// A producer each on two queues in separate directories pushing at the
+/// same time, to see that they don't slow each other down.
+fn bench_two_topics(c: &mut Criterion) {
+    let messages = messages();
+    let mut group = c.benchmark_group("two_topics");
+    group.sample_size(10);
+
+    for size in sizes() {
+        let msg = vec![b'a'; size];
+        group.throughput(Throughput::BytesDecimal(2 * messages * size as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &msg, |b, msg| {
+            b.iter_custom(|iters| {
+                iter_fresh(iters, |other| {
+                    iter_fresh(1, |path| {
+                        let barrier = Arc::new(Barrier::new(3));
+
+                        let producers: Vec<_> = [path, other]
+                            .into_iter()
+                            .map(|path| {
+                                let mut tx =
+                                    Sender::new(DataPagesManager::new(path).unwrap()).unwrap();
+                                let barrier = barrier.clone();
+                                let msg = msg.clone();
+
+                                thread::spawn(move || {
+                                    barrier.wait();
+                                    for _ in 0..messages {
+                                        tx.push(&msg).unwrap();
+                                    }
+                                })
+                            })
+                            .collect();
+
+                        barrier.wait();
+                        let now = Instant::now();
+                        for h in producers {
+                            h.join().unwrap();
+                        }
+                        now.elapsed()
+                    })
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// Rolling onto a new page and filling it, with the page's memory faulted
+/// in by the pushes as they reach it or touched when the page is created by
+/// `prefault`. Timing starts at the push that creates the page.
+fn bench_rollover(c: &mut Criterion) {
+    let per_page = MAX_MESSAGES_PER_PAGE as u64;
+    let mut group = c.benchmark_group("rollover");
+    group.sample_size(10);
+
+    let msg = vec![b'a'; 1024];
+    group.throughput(Throughput::BytesDecimal(per_page * msg.len() as u64));
+
+    for prefault in [false, true] {
+        let name = if prefault { "prefault" } else { "lazy" };
+
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                iter_fresh(iters, |path| {
+                    let manager = DataPagesManager::builder(path)
+                        .prefault(prefault)
+                        .build()
+                        .unwrap();
+                    let mut tx = Sender::new(manager).unwrap();
+                    for _ in 0..per_page {
+                        tx.push(&msg).unwrap();
+                    }
+
+                    let now = Instant::now();
+                    for _ in 0..per_page {
+                        tx.push(&msg).unwrap();
+                    }
+                    now.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_push,
+    bench_pop,
+    bench_mpmc,
+    bench_two_topics,
+    bench_rollover
+);
+criterion_main!(benches);
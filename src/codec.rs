@@ -0,0 +1,57 @@
+use crate::Error;
+
+/// How message payloads are stored on disk, see
+/// `DataPagesManagerBuilder::codec`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    #[default]
+    None,
+    /// LZ4 block compression, prefixed with the little endian `u32`
+    /// uncompressed length. Needs the `lz4` feature.
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl Codec {
+    /// Compresses `data` into `out`, replacing whatever was in it.
+    pub(crate) fn compress(self, data: &[u8], out: &mut Vec<u8>) {
+        out.clear();
+
+        match self {
+            Codec::None => out.extend_from_slice(data),
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => {
+                use lz4_flex::block::{compress_into, get_maximum_output_size};
+
+                out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                out.resize(4 + get_maximum_output_size(data.len()), 0);
+
+                let len = compress_into(data, &mut out[4..]).expect("output fits the worst case");
+                out.truncate(4 + len);
+            }
+        }
+    }
+
+    /// Decompresses `data` into `out`, replacing whatever was in it.
+    pub(crate) fn decompress(self, data: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        out.clear();
+
+        match self {
+            Codec::None => out.extend_from_slice(data),
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => {
+                let (len, block) = data.split_first_chunk::<4>().ok_or(Error::Decompress)?;
+                out.resize(u32::from_le_bytes(*len) as usize, 0);
+
+                let decompressed =
+                    lz4_flex::block::decompress_into(block, out).map_err(|_| Error::Decompress)?;
+
+                if decompressed != out.len() {
+                    return Err(Error::Decompress);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
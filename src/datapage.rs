@@ -1,16 +1,243 @@
 use std::{
+    mem::MaybeUninit,
     path::Path,
-    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
 };
 
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use memmap2::MmapOptions;
 use mmapcell::MmapCell;
 
+use crate::Codec;
+
+/// How long `DataPage::resolve_slot` waits for an unpublished slot.
+#[derive(Clone, Copy)]
+enum WaitStrategy {
+    NoWait,
+    Block,
+    Until(Instant),
+    Spin,
+}
+
+/// How often a reader parked on a slot no push has reserved yet checks
+/// whether the page filled up without it, see `DataPage::abandoned`.
+const ABANDONED_RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set once futex waits turn out not to work, e.g. blocked by a seccomp
+/// filter, see `probe_futex`. Waits then sleep in short polls instead.
+static FUTEX_FALLBACK: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// How long a wait sleeps between checks without futexes.
+const FALLBACK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Checks, once per process, that futex waits and wakes work, and switches
+/// every wait over to polling if they don't. Managers call it when they're
+/// built, before anything waits.
+///
+/// The `no-futex` feature skips the check and always polls.
+pub(crate) fn probe_futex() {
+    static PROBED: std::sync::Once = std::sync::Once::new();
+
+    PROBED.call_once(|| {
+        if !futex_available() {
+            tracing::warn!(
+                poll_interval = ?FALLBACK_POLL_INTERVAL,
+                "futex syscalls are unavailable, blocking waits fall back to polling",
+            );
+            FUTEX_FALLBACK.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+    });
+}
+
+/// Whether waits poll instead of using futexes, see `probe_futex`.
+pub(crate) fn futex_fallback() -> bool {
+    FUTEX_FALLBACK.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+#[cfg(target_os = "linux")]
+fn futex_available() -> bool {
+    if cfg!(feature = "no-futex") {
+        return false;
+    }
+
+    let scratch = std::sync::atomic::AtomicU32::new(0);
+
+    // a wait on a value that doesn't match returns EAGAIN straight away
+    let waited = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            &scratch,
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            1_u32,
+            std::ptr::null::<libc::timespec>(),
+        )
+    };
+    let wait_works = waited == 0
+        || matches!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EAGAIN | libc::EINTR)
+        );
+
+    let woken = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            &scratch,
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            i32::MAX,
+        )
+    };
+
+    wait_works && woken >= 0
+}
+
+// atomic_wait uses the platform's own wait on address elsewhere
+#[cfg(not(target_os = "linux"))]
+fn futex_available() -> bool {
+    !cfg!(feature = "no-futex")
+}
+
+/// Sleeps for up to `timeout`, in place of a futex wait, while `a` still
+/// holds `expected`.
+fn poll_wait(a: &std::sync::atomic::AtomicU32, expected: u32, timeout: Duration) {
+    if a.load(std::sync::atomic::Ordering::Acquire) == expected {
+        std::thread::sleep(timeout.min(FALLBACK_POLL_INTERVAL));
+    }
+}
+
+/// `atomic_wait::wait` that gives up after `timeout`. Can return early, like
+/// any futex wait.
+#[cfg(target_os = "linux")]
+pub(crate) fn wait_timeout(a: &std::sync::atomic::AtomicU32, expected: u32, timeout: Duration) {
+    if futex_fallback() {
+        return poll_wait(a, expected, timeout);
+    }
+
+    futex_wait(
+        a,
+        expected,
+        Some(timeout),
+        libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+    );
+}
+
+/// Futex `op`, a wait, on `a` while it holds `expected`, for up to `timeout`
+/// or until woken.
+#[cfg(target_os = "linux")]
+fn futex_wait(
+    a: &std::sync::atomic::AtomicU32,
+    expected: u32,
+    timeout: Option<Duration>,
+    op: libc::c_int,
+) {
+    let timeout = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    });
+    let timeout = timeout
+        .as_ref()
+        .map_or(std::ptr::null(), |t| t as *const libc::timespec);
+
+    unsafe {
+        libc::syscall(libc::SYS_futex, a, op, expected, timeout);
+    }
+}
+
+// no timed wait in atomic_wait, so poll elsewhere
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn wait_timeout(a: &std::sync::atomic::AtomicU32, expected: u32, timeout: Duration) {
+    poll_wait(a, expected, timeout)
+}
+
+/// Parks on an unpublished page slot until it's woken, or `timeout` passes.
+///
+/// Pages are shared with producers in other processes, so on Linux slots use
+/// shared futexes: a private one is keyed on this process's address space and
+/// never sees their wakes. Elsewhere the platform's wait on address is process
+/// private, and a reader only sees pushes from other processes once its wait
+/// times out, so readers there should use the timed pops.
+#[cfg(all(not(loom), target_os = "linux"))]
+fn wait_slot(slot: &AtomicU32, timeout: Option<Duration>) {
+    if futex_fallback() {
+        // readers wait in a loop, so a poll stands in for an untimed wait
+        return poll_wait(slot, 0, timeout.unwrap_or(FALLBACK_POLL_INTERVAL));
+    }
+
+    futex_wait(slot, 0, timeout, libc::FUTEX_WAIT);
+}
+
+#[cfg(all(not(loom), target_os = "linux"))]
+fn wake_slot(slot: &AtomicU32) {
+    if !futex_fallback() {
+        unsafe {
+            libc::syscall(libc::SYS_futex, slot, libc::FUTEX_WAKE, i32::MAX);
+        }
+    }
+}
+
+#[cfg(all(not(loom), not(target_os = "linux")))]
+fn wait_slot(slot: &AtomicU32, timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => wait_timeout(slot, 0, timeout),
+        // readers wait in a loop, so a poll stands in for an untimed wait
+        None if futex_fallback() => poll_wait(slot, 0, FALLBACK_POLL_INTERVAL),
+        None => atomic_wait::wait(slot, 0),
+    }
+}
+
+#[cfg(all(not(loom), not(target_os = "linux")))]
+fn wake_slot(slot: &AtomicU32) {
+    if !futex_fallback() {
+        atomic_wait::wake_all(slot)
+    }
+}
+
+// loom has no futexes, a waiter can only let the other threads run
+#[cfg(loom)]
+fn wait_slot(_slot: &AtomicU32, _timeout: Option<Duration>) {
+    loom::thread::yield_now()
+}
+
+#[cfg(loom)]
+fn wake_slot(_slot: &AtomicU32) {}
+
 type LenType = u32;
+type ShortLenType = u16;
 type IdxType = u32;
 
 const IDX_SALT: u32 = 1;
-pub const MAX_RECEIVER_GROUPS: usize = 64;
+#[cfg(not(loom))]
 pub const MAX_MESSAGES_PER_PAGE: u32 = 2_u32.pow(16) - 1;
+// few enough slots for loom to try every interleaving on them
+#[cfg(loom)]
+pub const MAX_MESSAGES_PER_PAGE: u32 = 3;
+
+const DP_BUILD_MAX_RECEIVER_GROUPS: &str = match option_env!("DP_BUILD_MAX_RECEIVER_GROUPS") {
+    Some(m) => m,
+    None => "64",
+};
+
+pub const MAX_RECEIVER_GROUPS: usize = const_str::parse!(DP_BUILD_MAX_RECEIVER_GROUPS, usize);
+const _: () = assert!(
+    MAX_RECEIVER_GROUPS > 0,
+    "MAX_RECEIVER_GROUPS must be at least 1"
+);
+
+// marks a page whose header has been written ("dpmx")
+const PAGE_MAGIC: u32 = 0x6470_6d78;
+
+const FLAG_TIMESTAMPS: u32 = 1;
+const FLAG_KEYS: u32 = 2;
+#[cfg(feature = "lz4")]
+const FLAG_LZ4: u32 = 4;
+const FLAG_ENCRYPTED: u32 = 8;
+const FLAG_SHORT_LENGTHS: u32 = 16;
+
+pub const TIMESTAMP_SIZE: usize = size_of::<u64>();
+pub const KEY_SIZE: usize = size_of::<u64>();
 
 const DP_BUILD_EMSG_SIZE: &str = match option_env!("DP_BUILD_EMSG_SIZE") {
     Some(m) => m,
@@ -25,6 +252,26 @@ const _: () = assert!(
 
 pub const MAX_BYTES_PER_PAGE: u32 = MAX_MESSAGES_PER_PAGE * EXPECTED_MESSAGE_SIZE_BYTES;
 
+// a published slot holds write_idx + IDX_SALT, so it's never 0 (unpublished) and,
+// since every message takes at least its length prefix, always stays below
+// MAX_BYTES_PER_PAGE (page full) even for zero-length messages
+const _: () = assert!(IDX_SALT > 0 && MAX_BYTES_PER_PAGE < u32::MAX - IDX_SALT);
+
+/// Largest message a page accepts, one that fills an empty page.
+pub const MAX_MESSAGE_SIZE: usize = MAX_BYTES_PER_PAGE as usize - 1 - DataPage::SIZE_OF_LEN;
+
+/// Largest message on pages with `PageFormat::short_lengths`.
+pub const MAX_SHORT_MESSAGE_SIZE: usize = ShortLenType::MAX as usize;
+#[cfg(not(loom))]
+const _: () = assert!(MAX_SHORT_MESSAGE_SIZE <= MAX_MESSAGE_SIZE);
+
+/// Size of a page file written before pages had a header: everything from
+/// the write index on, laid out as it still is.
+pub(crate) const LEGACY_PAGE_SIZE: usize =
+    size_of::<DataPage>() - std::mem::offset_of!(DataPage, count_write_idx);
+/// Group slots of every page written before pages had a header.
+const LEGACY_GROUP_SLOTS: u32 = 64;
+
 const WRITE_IDX_MASK: u64 = !(u32::MAX as u64);
 const COUNT_MASK: u64 = !WRITE_IDX_MASK;
 
@@ -36,6 +283,14 @@ union CountWriteIdx {
 }
 
 impl CountWriteIdx {
+    /// Adds `val` to the write_idx and 1 to the count, returning both from
+    /// before the add.
+    ///
+    /// Neither half is bounded here. Pushes leave a full page alone, and
+    /// readers of the counters clamp them (see `DataPage::usage`), so the
+    /// write_idx only wraps, or the count carries into it, if around
+    /// `u32::MAX / MAX_BYTES_PER_PAGE` pushes of page sized messages race
+    /// past that check at once.
     pub fn fetch_add(&self, val: u32) -> (u32, u32) {
         let val = val as u64;
         let write_idx_count =
@@ -47,16 +302,173 @@ impl CountWriteIdx {
 
         (write_idx, count)
     }
+
+    /// Back to no messages and no bytes reserved.
+    pub fn reset(&self) {
+        unsafe { self.write_idx.store(0, Ordering::Release) }
+    }
+
+    pub fn load(&self) -> (u32, u32) {
+        let write_idx_count = unsafe { self.write_idx.load(Ordering::Acquire) };
+
+        let write_idx = ((write_idx_count & WRITE_IDX_MASK) >> 32) as u32;
+        let count = (write_idx_count & COUNT_MASK) as u32;
+
+        (write_idx, count)
+    }
+}
+
+/// Settings that change how a page is laid out or framed.
+///
+/// They're recorded in the page header when a page is first created and
+/// every later open has to agree with them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFormat {
+    pub receiver_groups: u32,
+    /// Every message starts with a little endian `u64` of nanoseconds since
+    /// the unix epoch, taken when it was pushed.
+    pub timestamps: bool,
+    /// Every message carries a little endian `u64` key, after the timestamp
+    /// if there is one.
+    pub keys: bool,
+    /// Payloads (but not their timestamp or key) are compressed with this.
+    pub codec: Codec,
+    /// Payloads are encrypted (after compression) and followed by an
+    /// authentication tag that also covers the timestamp and key, see
+    /// `DataPagesManagerBuilder::encryption_key`. The key itself is never
+    /// stored.
+    pub encrypted: bool,
+    /// Messages are framed with a `u16` length instead of a `u32`, saving 2
+    /// bytes per message but capping them at `MAX_SHORT_MESSAGE_SIZE`.
+    pub short_lengths: bool,
+}
+
+impl PageFormat {
+    /// Whether messages are stored as just the payload, with no timestamp,
+    /// key, compression or encryption, behind a `u32` length.
+    pub fn is_plain(&self) -> bool {
+        !self.timestamps
+            && !self.keys
+            && self.codec == Codec::None
+            && !self.encrypted
+            && !self.short_lengths
+    }
+
+    /// Bytes of the length prefix in front of every stored message.
+    pub fn size_of_len(&self) -> usize {
+        if self.short_lengths {
+            DataPage::SIZE_OF_SHORT_LEN
+        } else {
+            DataPage::SIZE_OF_LEN
+        }
+    }
+
+    /// Largest message (everything after the length prefix) a page takes.
+    pub fn max_message_size(&self) -> usize {
+        if self.short_lengths {
+            MAX_SHORT_MESSAGE_SIZE
+        } else {
+            MAX_MESSAGE_SIZE
+        }
+    }
+
+    fn flags(&self) -> u32 {
+        let mut flags = 0;
+
+        if self.timestamps {
+            flags |= FLAG_TIMESTAMPS;
+        }
+
+        if self.keys {
+            flags |= FLAG_KEYS;
+        }
+
+        flags |= match self.codec {
+            Codec::None => 0,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => FLAG_LZ4,
+        };
+
+        if self.encrypted {
+            flags |= FLAG_ENCRYPTED;
+        }
+
+        if self.short_lengths {
+            flags |= FLAG_SHORT_LENGTHS;
+        }
+
+        flags
+    }
+}
+
+impl Default for PageFormat {
+    fn default() -> Self {
+        PageFormat {
+            receiver_groups: MAX_RECEIVER_GROUPS as u32,
+            timestamps: false,
+            keys: false,
+            codec: Codec::None,
+            encrypted: false,
+            short_lengths: false,
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct DataPageFull;
+pub struct FormatMismatch {
+    pub field: &'static str,
+    pub expected: u32,
+    pub found: u32,
+}
+
+#[repr(C)]
+struct PageHeader {
+    magic: AtomicU32,
+    receiver_groups: AtomicU32,
+    flags: AtomicU32,
+    /// Page number the file was created as, plus one. 0 on pages written
+    /// before it was recorded.
+    page: AtomicU32,
+    /// `MAX_RECEIVER_GROUPS` of the build that wrote the page. It sizes the
+    /// group counts and so places everything after them, a build with
+    /// another value would read the slots and messages at the wrong offsets.
+    group_slots: AtomicU32,
+    /// How many times the file was cleared for reuse as another page, see
+    /// `DataPage::clear`. Not part of the format: a reused page keeps it.
+    generation: AtomicU32,
+}
 
 #[derive(Debug)]
+pub struct DataPageFull;
+
+/// Something `DataPage::check_slots` found wrong with a page's slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotProblem {
+    /// Slot `count` isn't published although a later slot is.
+    Gap { count: u32 },
+    /// Slot `count` points outside the page, or its message runs past the end.
+    OutOfBounds { count: u32 },
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub struct EndOfDataPage;
 
+/// One page of the queue, laid out the same in memory and on disk.
+///
+/// Every field is an atomic or plain bytes and an all zero page is a valid,
+/// empty one: no messages reserved, every slot unpublished, every group count
+/// at 0 and no format in the header yet (`init_format` writes it on first
+/// use). That's what makes a freshly created (sparse) file a valid page, and
+/// also any other zeroed memory, see `from_raw` and `init_zeroed`.
+///
+/// Its geometry (page size, slot count, where the buffer starts) is fixed by
+/// the type at build time, so offsets into it are constants the compiler
+/// folds into every push and pop. The one per-page choice, the width of the
+/// length prefix, is read from the header, which `init_format` checks
+/// against the queue's format whenever a page is mapped.
 #[repr(C)]
 pub struct DataPage {
+    header: PageHeader,
     count_write_idx: CountWriteIdx,
     receiver_group_count: [AtomicU32; MAX_RECEIVER_GROUPS],
     idx_map_with_salt: [AtomicU32; MAX_MESSAGES_PER_PAGE as usize],
@@ -64,26 +476,484 @@ pub struct DataPage {
 }
 
 impl DataPage {
-    const SIZE_OF_LEN: usize = size_of::<LenType>();
+    pub const SIZE_OF_LEN: usize = size_of::<LenType>();
+    pub const SIZE_OF_SHORT_LEN: usize = size_of::<ShortLenType>();
+
+    /// Whether this page frames messages with a `u16` length, as recorded in
+    /// its header by `init_format` (pages without a format use `u32`).
+    fn short_lengths(&self) -> bool {
+        self.header.magic.load(Ordering::Acquire) == PAGE_MAGIC
+            && self.header.flags.load(Ordering::Relaxed) & FLAG_SHORT_LENGTHS != 0
+    }
+
+    /// Bytes of the length prefix in front of every message on this page.
+    pub fn size_of_len(&self) -> usize {
+        if self.short_lengths() {
+            Self::SIZE_OF_SHORT_LEN
+        } else {
+            Self::SIZE_OF_LEN
+        }
+    }
+
+    pub(crate) fn increment_group_count(&self, group: usize, val: u32) -> u32 {
+        // Relaxed is enough: a read-modify-write always sees the latest value,
+        // so every claim is unique whatever the ordering. The count publishes
+        // nothing either, the message a claim points at is synchronized by
+        // the Acquire load of its slot, and every load of a group count is
+        // Relaxed, so a Release here wouldn't pair with anything.
+        // `loom_test::competing_consumers` checks this under every
+        // interleaving: two receivers never claim the same message.
+        self.receiver_group_count[group].fetch_add(val, Ordering::Relaxed)
+    }
+
+    pub fn get_group_count(&self, group: usize) -> u32 {
+        self.receiver_group_count[group].load(Ordering::Relaxed)
+    }
+
+    /// Moves the group count from `current` to `new` only if no one else moved
+    /// it in the meantime, for claiming slots that are known to be published.
+    pub(crate) fn compare_exchange_group_count(
+        &self,
+        group: usize,
+        current: u32,
+        new: u32,
+    ) -> Result<u32, u32> {
+        // Relaxed for the same reasons as `increment_group_count`
+        self.receiver_group_count[group].compare_exchange(
+            current,
+            new,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        )
+    }
+
+    pub(crate) fn set_group_count(&self, group: usize, val: u32) {
+        self.receiver_group_count[group].store(val, Ordering::Relaxed)
+    }
+
+    /// Messages and bytes reserved on this page so far, clamped to the page limits.
+    ///
+    /// Reserved slots may still be mid-write by their producer.
+    pub fn usage(&self) -> (u32, u32) {
+        let (write_idx, count) = self.count_write_idx.load();
+
+        (
+            count.min(MAX_MESSAGES_PER_PAGE),
+            write_idx.min(MAX_BYTES_PER_PAGE),
+        )
+    }
+
+    /// Bytes of `buf` reserved so far, length prefixes included. Same as the
+    /// second half of `usage`.
+    pub fn bytes_used(&self) -> u32 {
+        self.usage().1
+    }
+
+    /// Messages reserved so far. Same as the first half of `usage`.
+    pub fn message_count(&self) -> u32 {
+        self.usage().0
+    }
+
+    /// Like `message_count` but leaves out the slots of pushes that failed
+    /// because the page ran out of bytes, so it's the number of messages the
+    /// page ends up holding. Some may still be mid-write by their producer.
+    pub fn stored_count(&self) -> u32 {
+        let (count, write_idx) = self.usage();
+        if write_idx < MAX_BYTES_PER_PAGE {
+            return count;
+        }
+
+        // every push from the first one that didn't fit marks its slot as the
+        // end of the page, and readers pass the mark on, so those slots are
+        // a run at the end of the reserved ones
+        self.idx_map_with_salt[..count as usize]
+            .partition_point(|slot| slot.load(Ordering::Acquire) != u32::MAX) as u32
+    }
+
+    /// Whether this page won't take any more messages. True once a push has
+    /// failed on it (and also when the last slot has been taken).
+    pub fn is_full(&self) -> bool {
+        let (write_idx, count) = self.count_write_idx.load();
 
-    pub fn increment_group_count(&self, group: usize, val: u32) -> u32 {
-        // maybe try waiting on the group count or something idk
-        // let count = self.receiver_group_count[group].load(Ordering::Relaxed);
-        // self.count_write_idx.count.wa
-        self.receiver_group_count[group].fetch_add(val, Ordering::Release)
+        count >= MAX_MESSAGES_PER_PAGE || write_idx >= MAX_BYTES_PER_PAGE
     }
 
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<MmapCell<DataPage>, std::io::Error> {
+    pub(crate) fn new<P: AsRef<Path>>(path: P) -> Result<MmapCell<DataPage>, std::io::Error> {
         unsafe { MmapCell::new_named(path) }
     }
 
-    pub fn push<T: AsRef<[u8]>>(&mut self, data: T) -> Result<(), DataPageFull> {
-        let (write_idx, count) = self
-            .count_write_idx
-            .fetch_add(data.as_ref().len() as u32 + Self::SIZE_OF_LEN as u32);
+    /// Like `new` but fails with `NotFound` instead of creating the file,
+    /// which has to be a full size page already.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MmapCell<DataPage>, std::io::Error> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        if file.metadata()?.len() < size_of::<DataPage>() as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "file is smaller than a data page",
+            ));
+        }
+
+        let m = unsafe {
+            MmapOptions::new()
+                .len(size_of::<DataPage>())
+                .map_mut(&file)?
+        };
+        Ok(unsafe { MmapCell::new(m) })
+    }
+
+    /// Uses memory that's already a page, e.g. shared memory mapped by
+    /// something other than `MmapCell`, without going through a file.
+    ///
+    /// # Safety
+    /// `ptr` must be non-null, aligned for `DataPage` and valid for reads and
+    /// writes of `size_of::<DataPage>()` bytes for all of `'a`. The memory must
+    /// hold a page: zeroed (see the `DataPage` docs) or previously written
+    /// through a `DataPage`.
+    ///
+    /// The page is only handed out shared, since other users of the memory
+    /// may be reading and writing it at the same time.
+    pub unsafe fn from_raw<'a>(ptr: *mut DataPage) -> &'a DataPage {
+        unsafe { &*ptr }
+    }
+
+    /// Zeroes `mem` into an empty page, ready for `init_format` and pushes.
+    pub fn init_zeroed(mem: &mut MaybeUninit<DataPage>) -> &mut DataPage {
+        // SAFETY: all zero is a valid DataPage, see the type docs
+        unsafe {
+            mem.as_mut_ptr().write_bytes(0, 1);
+            mem.assume_init_mut()
+        }
+    }
+
+    /// Writes one byte in every page of memory of `buf`, back to what it
+    /// was, so the kernel maps all of it writable now rather than faulting
+    /// each page in on the first push to reach it.
+    ///
+    /// Strides by 4 KiB, the smallest page size in common use; bigger pages
+    /// just get touched more than once. The writes go to the file like any
+    /// other, so only call this on a page nobody else is using yet. They also
+    /// dirty all of `buf`, which writeback then allocates on disk and writes
+    /// out in full.
+    pub(crate) fn prefault(&mut self) {
+        const STRIDE: usize = 4096;
+
+        // `buf` needn't start on a page boundary, so its last page may lie
+        // past the last stride
+        let last = self.buf.len() - 1;
+        for i in (0..last).step_by(STRIDE).chain([last]) {
+            let byte = &mut self.buf[i];
+            // volatile so the no-op write isn't optimized away
+            unsafe { std::ptr::write_volatile(byte, std::ptr::read_volatile(byte)) }
+        }
+    }
+
+    /// Maps an existing page file without a manager, e.g. to inspect it.
+    ///
+    /// The file is opened read only and mapped copy-on-write, so nothing done
+    /// through the returned page ever reaches the file.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<MmapCell<DataPage>, std::io::Error> {
+        let file = std::fs::File::open(path)?;
+
+        if file.metadata()?.len() < size_of::<DataPage>() as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "file is smaller than a data page",
+            ));
+        }
+
+        let m = unsafe {
+            MmapOptions::new()
+                .len(size_of::<DataPage>())
+                .map_copy(&file)?
+        };
+        Ok(unsafe { MmapCell::new(m) })
+    }
+
+    /// Maps an empty, closed page `page` in anonymous memory, which stands in
+    /// for a page deleted from the middle of the ring so readers reaching it
+    /// move straight on to the next one.
+    pub(crate) fn closed_anon(
+        page: usize,
+        format: &PageFormat,
+    ) -> Result<MmapCell<DataPage>, std::io::Error> {
+        let m = MmapOptions::new().len(size_of::<DataPage>()).map_anon()?;
+        let datapage = unsafe { MmapCell::new(m) };
+
+        datapage
+            .get()
+            .init_format(page, format)
+            .expect("a zeroed page takes any format");
+        datapage.get().close();
+
+        Ok(datapage)
+    }
+
+    /// Writes the header for `page` and `format` into a fresh (all zero) page
+    /// and fills the rest with `legacy`, the `LEGACY_PAGE_SIZE` bytes of a
+    /// page file from before pages had a header.
+    ///
+    /// Those pages all had 64 group slots and plain messages behind a `u32`
+    /// length, so this build and `format` have to agree with that.
+    pub(crate) fn migrate_legacy(
+        &mut self,
+        legacy: &[u8],
+        page: usize,
+        format: &PageFormat,
+    ) -> Result<(), FormatMismatch> {
+        assert_eq!(legacy.len(), LEGACY_PAGE_SIZE);
+
+        if MAX_RECEIVER_GROUPS as u32 != LEGACY_GROUP_SLOTS {
+            return Err(FormatMismatch {
+                field: "group_slots",
+                expected: MAX_RECEIVER_GROUPS as u32,
+                found: LEGACY_GROUP_SLOTS,
+            });
+        }
+
+        if format.flags() != 0 {
+            return Err(FormatMismatch {
+                field: "flags",
+                expected: format.flags(),
+                found: 0,
+            });
+        }
+
+        self.init_format(page, format)?;
+
+        // SAFETY: past the header the page is atomics and bytes, for which any
+        // contents are valid, and `&mut self` keeps everyone else off it
+        unsafe {
+            let body = (self as *mut DataPage as *mut u8)
+                .add(std::mem::offset_of!(DataPage, count_write_idx));
+            std::ptr::copy_nonoverlapping(legacy.as_ptr(), body, LEGACY_PAGE_SIZE);
+        }
+
+        Ok(())
+    }
+
+    /// Empties the page in place so its file can be reused for another page:
+    /// the header, counts and slots go back to zero, as on a fresh page, and
+    /// `init_format` can then record the new page number.
+    ///
+    /// `buf` keeps the old bytes, but with every slot unpublished none of
+    /// them can be read anymore. Nothing else may be using the page, but a
+    /// reader of the old page that's still parked on one of its slots, e.g.
+    /// in another process, gets `EndOfDataPage` once the slot is published
+    /// again instead of the new page's message: the generation is bumped
+    /// first and the wait checks it.
+    pub(crate) fn clear(&self) {
+        let header = &self.header;
+        header.generation.fetch_add(1, Ordering::Release);
+
+        for field in [
+            &header.magic,
+            &header.receiver_groups,
+            &header.flags,
+            &header.page,
+            &header.group_slots,
+        ] {
+            field.store(0, Ordering::Relaxed);
+        }
+
+        self.count_write_idx.reset();
+        for count in self
+            .receiver_group_count
+            .iter()
+            .chain(&self.idx_map_with_salt)
+        {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Marks the page full the way a push that doesn't fit would, so readers
+    /// done with the messages already on it move on to the next page instead
+    /// of waiting for more, and pushes fail with `DataPageFull`.
+    pub(crate) fn close(&self) {
+        let (_, count) = self.count_write_idx.fetch_add(MAX_BYTES_PER_PAGE);
+
+        if count < MAX_MESSAGES_PER_PAGE {
+            self.idx_map_with_salt[count as usize].store(u32::MAX, Ordering::Release);
+            wake_slot(&self.idx_map_with_salt[count as usize]);
+        }
+    }
+
+    /// Writes `format` and the page number `page` into the header of a fresh
+    /// (all zero) page, or checks that an already initialized page was created
+    /// with the same format and as the same page.
+    ///
+    /// A page with messages but no header, e.g. written before pages had one,
+    /// is refused rather than initialized over its group counts, see
+    /// `check_format`.
+    ///
+    /// The page number catches a file that comes back under another number,
+    /// e.g. renamed or restored by hand, which would otherwise hand its group
+    /// counts and slots to receivers expecting a different page. A file the
+    /// manager reuses for a new page (`reuse_pages`) is cleared first, which
+    /// lets it take the new number, and a receiver still parked on the old
+    /// page is caught by the generation instead, see `clear`.
+    pub(crate) fn init_format(
+        &self,
+        page: usize,
+        format: &PageFormat,
+    ) -> Result<(), FormatMismatch> {
+        let header = &self.header;
+        let page = page as u32;
+
+        if header.magic.load(Ordering::Acquire) != PAGE_MAGIC && self.is_blank() {
+            header
+                .receiver_groups
+                .store(format.receiver_groups, Ordering::Relaxed);
+            header.flags.store(format.flags(), Ordering::Relaxed);
+            header.page.store(page.wrapping_add(1), Ordering::Relaxed);
+            header
+                .group_slots
+                .store(MAX_RECEIVER_GROUPS as u32, Ordering::Relaxed);
+            header.magic.store(PAGE_MAGIC, Ordering::Release);
+
+            return Ok(());
+        }
+
+        self.check_format(page as usize, format)
+    }
+
+    /// Whether nothing was ever written to the page: no header and no
+    /// messages reserved. Pages from before the header held their message
+    /// count where the magic now is, so any of them with messages isn't.
+    fn is_blank(&self) -> bool {
+        let header = &self.header;
 
-        let full_msg_len = (data.as_ref().len() + Self::SIZE_OF_LEN) as u32;
+        [
+            &header.magic,
+            &header.receiver_groups,
+            &header.flags,
+            &header.page,
+            &header.group_slots,
+        ]
+        .iter()
+        .all(|field| field.load(Ordering::Acquire) == 0)
+            && self.count_write_idx.load() == (0, 0)
+    }
+
+    /// The checking half of `init_format`, which writes nothing: a blank page
+    /// passes, one with messages but no header fails on its `magic`.
+    pub(crate) fn check_format(
+        &self,
+        page: usize,
+        format: &PageFormat,
+    ) -> Result<(), FormatMismatch> {
+        let header = &self.header;
+        let page = page as u32;
+
+        let magic = header.magic.load(Ordering::Acquire);
+        if magic != PAGE_MAGIC {
+            if self.is_blank() {
+                return Ok(());
+            }
+
+            return Err(FormatMismatch {
+                field: "magic",
+                expected: PAGE_MAGIC,
+                found: magic,
+            });
+        }
+
+        let group_slots = header.group_slots.load(Ordering::Relaxed);
+        if group_slots != MAX_RECEIVER_GROUPS as u32 {
+            return Err(FormatMismatch {
+                field: "group_slots",
+                expected: MAX_RECEIVER_GROUPS as u32,
+                found: group_slots,
+            });
+        }
 
+        let found = header.page.load(Ordering::Relaxed);
+        if found != 0 && found != page.wrapping_add(1) {
+            return Err(FormatMismatch {
+                field: "page",
+                expected: page,
+                found: found.wrapping_sub(1),
+            });
+        }
+
+        let fields = [
+            (
+                "receiver_groups",
+                format.receiver_groups,
+                header.receiver_groups.load(Ordering::Relaxed),
+            ),
+            (
+                "flags",
+                format.flags(),
+                header.flags.load(Ordering::Relaxed),
+            ),
+        ];
+
+        for (field, expected, found) in fields {
+            if expected != found {
+                return Err(FormatMismatch {
+                    field,
+                    expected,
+                    found,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn push<T: AsRef<[u8]>>(&mut self, data: T) -> Result<u32, DataPageFull> {
+        self.push_parts(&[data.as_ref()])
+    }
+
+    /// Pushes the concatenation of `parts` as a single message, returning the
+    /// count it was stored at (what `get` takes to read it back).
+    pub(crate) fn push_parts(&mut self, parts: &[&[u8]]) -> Result<u32, DataPageFull> {
+        self.push_parts_with(parts, self.short_lengths(), |_count, _msg| {})
+    }
+
+    /// Like `push_parts` but hands the stored message, along with its count,
+    /// to `seal` after copying it in and before publishing it, so it can be
+    /// rewritten in place with what only becomes known once the slot is
+    /// reserved (e.g. encrypted with a nonce derived from the count).
+    ///
+    /// `short_lengths` is the page's framing, which senders know from the
+    /// queue's format rather than loading it from the header on every push.
+    pub(crate) fn push_parts_with(
+        &mut self,
+        parts: &[&[u8]],
+        short_lengths: bool,
+        seal: impl FnOnce(u32, &mut [u8]),
+    ) -> Result<u32, DataPageFull> {
+        let data_len: usize = parts.iter().map(|p| p.len()).sum();
+        let size_of_len = if short_lengths {
+            Self::SIZE_OF_SHORT_LEN
+        } else {
+            Self::SIZE_OF_LEN
+        };
+
+        // wouldn't fit on any page, don't even reserve space for it
+        if data_len > MAX_MESSAGE_SIZE || short_lengths && data_len > MAX_SHORT_MESSAGE_SIZE {
+            return Err(DataPageFull);
+        }
+
+        // a full page stays full, and pushes onto it mustn't keep growing the
+        // counters towards wrapping
+        if self.is_full() {
+            return Err(DataPageFull);
+        }
+
+        let full_msg_len = (data_len + size_of_len) as u32;
+
+        let (write_idx, count) = self.count_write_idx.fetch_add(full_msg_len);
+
+        // no sentinel needed when the count runs out: every slot up to the last
+        // one was reserved by a push that publishes it, and reading any count
+        // past the last slot is `EndOfDataPage` without waiting, so no reader
+        // can be parked on a slot that never gets written
         if count >= MAX_MESSAGES_PER_PAGE {
             return Err(DataPageFull);
         }
@@ -95,75 +965,186 @@ impl DataPage {
         // let readers recursively wake the next reader until no more
         // readers are waiting. (there might still be a race condition here
         // but i'm kinda over it)
-        if write_idx + full_msg_len >= MAX_BYTES_PER_PAGE {
+        if write_idx.saturating_add(full_msg_len) >= MAX_BYTES_PER_PAGE {
             self.idx_map_with_salt[count as usize].store(u32::MAX, Ordering::Release);
-            atomic_wait::wake_all(&self.idx_map_with_salt[count as usize]);
+            wake_slot(&self.idx_map_with_salt[count as usize]);
 
             return Err(DataPageFull);
         }
 
-        self.buf[write_idx as usize..write_idx as usize + Self::SIZE_OF_LEN]
-            .copy_from_slice(&(data.as_ref().len() as LenType).to_le_bytes());
+        let len_bytes = &mut self.buf[write_idx as usize..write_idx as usize + size_of_len];
+        if short_lengths {
+            len_bytes.copy_from_slice(&(data_len as ShortLenType).to_le_bytes());
+        } else {
+            len_bytes.copy_from_slice(&(data_len as LenType).to_le_bytes());
+        }
+
+        let start = write_idx as usize + size_of_len;
+        let mut idx = start;
+        for part in parts {
+            self.buf[idx..idx + part.len()].copy_from_slice(part);
+            idx += part.len();
+        }
 
-        self.buf[write_idx as usize + Self::SIZE_OF_LEN
-            ..write_idx as usize + Self::SIZE_OF_LEN + data.as_ref().len()]
-            .copy_from_slice(data.as_ref());
+        seal(count, &mut self.buf[start..idx]);
 
         self.idx_map_with_salt[count as usize]
             .store(write_idx as IdxType + IDX_SALT, Ordering::Release);
 
-        atomic_wait::wake_all(&self.idx_map_with_salt[count as usize]);
+        // every waiter on this slot wants this very message: receivers of a
+        // group each claim their own slot before parking, so there's at most
+        // one per group here, plus anonymous receivers. Waking just one would
+        // strand the others, and consumers competing within a group are
+        // parked on other slots, which this doesn't touch.
+        wake_slot(&self.idx_map_with_salt[count as usize]);
 
-        Ok(())
+        Ok(count)
     }
 
     pub fn try_get(&self, count: u32) -> Result<Option<&[u8]>, EndOfDataPage> {
-        if count >= MAX_MESSAGES_PER_PAGE {
-            return Err(EndOfDataPage);
-        }
+        let size_of_len = self.size_of_len();
 
-        let idx_with_salt = match self.idx_map_with_salt[count as usize].load(Ordering::Acquire) {
-            0 => return Ok(None),
-            i => i,
-        };
+        self.try_get_framed_as(count, size_of_len)
+            .map(|framed| framed.map(|framed| &framed[size_of_len..]))
+    }
 
-        if idx_with_salt >= MAX_BYTES_PER_PAGE {
-            let next_count = count.saturating_add(1);
+    /// Like `try_get` but keeps the length prefix, see `get_framed`.
+    pub fn try_get_framed(&self, count: u32) -> Result<Option<&[u8]>, EndOfDataPage> {
+        self.try_get_framed_as(count, self.size_of_len())
+    }
 
-            self.idx_map_with_salt[next_count as usize].store(u32::MAX, Ordering::Release);
+    /// Like `try_get_framed` for callers that already know the page's
+    /// `size_of_len`, e.g. from the queue's format, so the hot path doesn't
+    /// load it from the header on every read. The `_as` variants below are
+    /// the same for the other waits.
+    pub(crate) fn try_get_framed_as(
+        &self,
+        count: u32,
+        size_of_len: usize,
+    ) -> Result<Option<&[u8]>, EndOfDataPage> {
+        self.resolve_slot(count, WaitStrategy::NoWait, size_of_len)
+    }
 
-            atomic_wait::wake_all(&self.idx_map_with_salt[next_count as usize]);
+    pub fn get(&self, count: u32) -> Result<&[u8], EndOfDataPage> {
+        let size_of_len = self.size_of_len();
 
-            return Err(EndOfDataPage);
-        }
+        self.get_framed_as(count, size_of_len)
+            .map(|framed| &framed[size_of_len..])
+    }
 
-        let idx = idx_with_salt.saturating_sub(IDX_SALT);
+    /// Like `get` but keeps the little-endian `u32` length prefix the message
+    /// is stored with.
+    pub fn get_framed(&self, count: u32) -> Result<&[u8], EndOfDataPage> {
+        self.get_framed_as(count, self.size_of_len())
+    }
 
-        let len = LenType::from_le_bytes(
-            self.buf[idx as usize..idx as usize + Self::SIZE_OF_LEN]
-                .try_into()
-                .expect("u32 is 4 bytes"),
-        );
+    pub(crate) fn get_framed_as(
+        &self,
+        count: u32,
+        size_of_len: usize,
+    ) -> Result<&[u8], EndOfDataPage> {
+        self.resolve_slot(count, WaitStrategy::Block, size_of_len)
+            .map(|framed| framed.expect("blocking waits until the slot is published"))
+    }
 
-        Ok(Some(
-            &self.buf
-                [idx as usize + Self::SIZE_OF_LEN..idx as usize + Self::SIZE_OF_LEN + len as usize],
-        ))
+    /// Like `get_framed` but busy waits instead of sleeping on a futex.
+    pub fn get_framed_spin(&self, count: u32) -> Result<&[u8], EndOfDataPage> {
+        self.get_framed_spin_as(count, self.size_of_len())
     }
 
-    pub fn get(&self, count: u32) -> Result<&[u8], EndOfDataPage> {
+    pub(crate) fn get_framed_spin_as(
+        &self,
+        count: u32,
+        size_of_len: usize,
+    ) -> Result<&[u8], EndOfDataPage> {
+        self.resolve_slot(count, WaitStrategy::Spin, size_of_len)
+            .map(|framed| framed.expect("spinning waits until the slot is published"))
+    }
+
+    /// Like `get` but gives up with `Ok(None)` if nothing was published
+    /// within `timeout`.
+    ///
+    /// `Ok(None)` always means the whole `timeout` passed: a wait cut short by
+    /// a signal or a spurious wakeup goes back to waiting for what's left.
+    pub fn get_with_timeout(
+        &self,
+        count: u32,
+        timeout: Duration,
+    ) -> Result<Option<&[u8]>, EndOfDataPage> {
+        let size_of_len = self.size_of_len();
+
+        self.get_framed_until_as(count, Instant::now() + timeout, size_of_len)
+            .map(|framed| framed.map(|framed| &framed[size_of_len..]))
+    }
+
+    /// Like `get_framed` but gives up with `Ok(None)` once `deadline` has
+    /// passed, see `get_with_timeout`.
+    pub fn get_framed_until(
+        &self,
+        count: u32,
+        deadline: Instant,
+    ) -> Result<Option<&[u8]>, EndOfDataPage> {
+        self.get_framed_until_as(count, deadline, self.size_of_len())
+    }
+
+    pub(crate) fn get_framed_until_as(
+        &self,
+        count: u32,
+        deadline: Instant,
+        size_of_len: usize,
+    ) -> Result<Option<&[u8]>, EndOfDataPage> {
+        self.resolve_slot(count, WaitStrategy::Until(deadline), size_of_len)
+    }
+
+    /// Waits for slot `count` as `wait` allows and decodes it into the length
+    /// prefixed message, `Ok(None)` if it wasn't published in time.
+    fn resolve_slot(
+        &self,
+        count: u32,
+        wait: WaitStrategy,
+        size_of_len: usize,
+    ) -> Result<Option<&[u8]>, EndOfDataPage> {
         if count >= MAX_MESSAGES_PER_PAGE {
             return Err(EndOfDataPage);
         }
 
-        let idx_with_salt = match self.idx_map_with_salt[count as usize].load(Ordering::Acquire) {
-            0 => {
-                atomic_wait::wait(&self.idx_map_with_salt[count as usize], 0);
-                self.idx_map_with_salt[count as usize].load(Ordering::Acquire)
+        let slot = &self.idx_map_with_salt[count as usize];
+        let generation = self.header.generation.load(Ordering::Acquire);
+
+        // wait can return spuriously (or on EINTR) so keep going until the slot is
+        // actually published, otherwise we'd decode index 0 and hand out the first
+        // message on the page again. Timed waits recheck against the deadline, not
+        // the time they were asked to wait.
+        let idx_with_salt = loop {
+            match slot.load(Ordering::Acquire) {
+                0 if self.abandoned(count) => return Err(EndOfDataPage),
+                0 => match wait {
+                    WaitStrategy::NoWait => return Ok(None),
+                    WaitStrategy::Block => wait_slot(slot, self.recheck_interval(count)),
+                    WaitStrategy::Spin => std::hint::spin_loop(),
+                    WaitStrategy::Until(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return Ok(None);
+                        }
+
+                        let timeout = deadline - now;
+                        let timeout = match self.recheck_interval(count) {
+                            Some(interval) => timeout.min(interval),
+                            None => timeout,
+                        };
+                        wait_slot(slot, Some(timeout));
+                    }
+                },
+                i => break i,
             }
-            i => i,
         };
 
+        // cleared while we waited, the slot now belongs to another page
+        if self.header.generation.load(Ordering::Acquire) != generation {
+            return Err(EndOfDataPage);
+        }
+
         if idx_with_salt >= MAX_BYTES_PER_PAGE {
             let next_count = count.saturating_add(1);
 
@@ -173,45 +1154,494 @@ impl DataPage {
 
             self.idx_map_with_salt[next_count as usize].store(u32::MAX, Ordering::Release);
 
-            atomic_wait::wake_all(&self.idx_map_with_salt[next_count as usize]);
+            wake_slot(&self.idx_map_with_salt[next_count as usize]);
             return Err(EndOfDataPage);
         }
 
-        let idx = idx_with_salt.saturating_sub(IDX_SALT);
+        Ok(Some(
+            self.framed_at(idx_with_salt, size_of_len)
+                .expect("published slot points inside the page"),
+        ))
+    }
 
-        let len = LenType::from_le_bytes(
-            self.buf[idx as usize..idx as usize + Self::SIZE_OF_LEN]
-                .try_into()
-                .expect("u32 is 4 bytes"),
-        );
+    /// Whether slot `count` will never be published: the page is full and no
+    /// push reserved it. A push that reserves it later fails and marks it as
+    /// the end of the page anyway.
+    ///
+    /// Readers only get there by being placed past the page full marker, e.g.
+    /// by `seek`. Otherwise the marker wakes them: every slot a group claims
+    /// before theirs is read by one of its receivers, which passes the marker
+    /// on, see `resolve_slot`.
+    fn abandoned(&self, count: u32) -> bool {
+        let (write_idx, reserved) = self.count_write_idx.load();
 
-        Ok(&self.buf
-            [idx as usize + Self::SIZE_OF_LEN..idx as usize + Self::SIZE_OF_LEN + len as usize])
+        count >= reserved && (write_idx >= MAX_BYTES_PER_PAGE || reserved >= MAX_MESSAGES_PER_PAGE)
+    }
+
+    /// How long a reader waiting for slot `count` may park before checking
+    /// whether it's `abandoned`. `None` when the next push reserves the slot,
+    /// or already has, since that push wakes it either way.
+    fn recheck_interval(&self, count: u32) -> Option<Duration> {
+        let (_, reserved) = self.count_write_idx.load();
+
+        (count > reserved).then_some(ABANDONED_RECHECK_INTERVAL)
+    }
+
+    /// Where slot `count` sits in a page file, for tests that corrupt it.
+    #[cfg(all(test, not(loom)))]
+    pub(crate) fn slot_offset(count: u32) -> u64 {
+        (std::mem::offset_of!(DataPage, idx_map_with_salt) + count as usize * size_of::<IdxType>())
+            as u64
+    }
+
+    /// The length prefixed message a published slot points at, or `None` if
+    /// it points outside the page.
+    fn framed_at(&self, idx_with_salt: u32, size_of_len: usize) -> Option<&[u8]> {
+        let idx = idx_with_salt.saturating_sub(IDX_SALT) as usize;
+
+        let len_bytes = self.buf.get(idx..idx + size_of_len)?;
+
+        let len = if size_of_len == Self::SIZE_OF_SHORT_LEN {
+            ShortLenType::from_le_bytes(len_bytes.try_into().expect("u16 is 2 bytes")) as usize
+        } else {
+            LenType::from_le_bytes(len_bytes.try_into().expect("u32 is 4 bytes")) as usize
+        };
+
+        self.buf.get(idx..idx + size_of_len + len)
+    }
+
+    /// Checks that the published slots form a prefix of the page, each
+    /// pointing at a message that lies inside it, up to the slot that marks
+    /// the page full if there is one. Only reads the page, like `iter`.
+    ///
+    /// A push still in progress leaves a gap until it publishes, so gaps are
+    /// only meaningful on pages producers are done with.
+    pub fn check_slots(&self) -> Vec<SlotProblem> {
+        let mut problems = Vec::new();
+        let mut gap_start = None;
+        let size_of_len = self.size_of_len();
+
+        for (count, slot) in (0..).zip(&self.idx_map_with_salt) {
+            match slot.load(Ordering::Acquire) {
+                0 => {
+                    gap_start.get_or_insert(count);
+                }
+                u32::MAX => break,
+                idx_with_salt => {
+                    if let Some(start) = gap_start.take() {
+                        problems.extend((start..count).map(|count| SlotProblem::Gap { count }));
+                    }
+
+                    if self.framed_at(idx_with_salt, size_of_len).is_none() {
+                        problems.push(SlotProblem::OutOfBounds { count });
+                    }
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Walks the messages published on this page in order, stopping at the
+    /// first slot that isn't published yet or marks the page as full.
+    ///
+    /// Purely reads the page: no group counts are claimed and no waiting
+    /// readers are woken. Messages are yielded as stored, including any
+    /// timestamp or key the queue's format puts in front of the payload.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        let size_of_len = self.size_of_len();
+
+        self.iter_framed().map(move |framed| &framed[size_of_len..])
+    }
+
+    /// Like `iter` but keeps the length prefix, see `get_framed`. The n-th
+    /// message yielded is the one at count n.
+    pub fn iter_framed(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        let size_of_len = self.size_of_len();
+
+        self.idx_map_with_salt
+            .iter()
+            .map(|slot| slot.load(Ordering::Acquire))
+            .take_while(|&idx_with_salt| idx_with_salt != 0 && idx_with_salt < MAX_BYTES_PER_PAGE)
+            .map_while(move |idx_with_salt| self.framed_at(idx_with_salt, size_of_len))
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod test {
-    use std::{
-        path::{Path, PathBuf},
-        sync::Arc,
-        thread,
-    };
-
-    use rand::random;
+    use std::{sync::Arc, thread};
 
     use super::*;
+    use crate::test_util::mkdir_random;
+
+    #[test]
+    fn bytes_used_and_message_count() {
+        let path = mkdir_random();
+        let p = DataPage::new(path.join("0")).unwrap();
+
+        assert_eq!((p.get().message_count(), p.get().bytes_used()), (0, 0));
+
+        for msg in ["a", "", "ccc", "dddddddd"] {
+            p.get_mut().push(msg).unwrap();
+        }
+
+        let expected = 1 + 3 + 8 + 4 * DataPage::SIZE_OF_LEN as u32;
+        assert_eq!(p.get().message_count(), 4);
+        assert_eq!(p.get().bytes_used(), expected);
+        assert_eq!(p.get().usage(), (4, expected));
+
+        // a rejected push doesn't count beyond the page
+        p.get_mut().push(vec![0; MAX_MESSAGE_SIZE]).unwrap_err();
+        assert_eq!(p.get().bytes_used(), MAX_BYTES_PER_PAGE);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn page_over_own_memory() {
+        let layout = std::alloc::Layout::new::<DataPage>();
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) }.cast::<DataPage>();
+        assert!(!ptr.is_null());
+
+        // zeroed memory is an empty page
+        let page = unsafe { DataPage::from_raw(ptr) };
+        assert_eq!(page.usage(), (0, 0));
+        assert_eq!(page.try_get(0), Ok(None));
+        page.init_format(0, &PageFormat::default()).unwrap();
+
+        // pushing takes the page exclusively, as `init_zeroed` gives it
+        let page = DataPage::init_zeroed(unsafe { &mut *ptr.cast::<MaybeUninit<DataPage>>() });
+        page.init_format(0, &PageFormat::default()).unwrap();
+        page.push("hello").unwrap();
+        assert_eq!(page.try_get(0), Ok(Some(&b"hello"[..])));
+        assert_eq!(page.try_get(1), Ok(None));
+
+        // the same memory seen again keeps what was written
+        let again = unsafe { DataPage::from_raw(ptr) };
+        assert_eq!(again.get(0), Ok(&b"hello"[..]));
+        assert!(again.init_format(1, &PageFormat::default()).is_err());
+
+        unsafe { std::alloc::dealloc(ptr.cast(), layout) };
+    }
+
+    #[test]
+    fn foreign_pages_are_refused() {
+        let path = mkdir_random();
+        let format = PageFormat::default();
+
+        // messages but no header
+        let p = DataPage::new(path.join("0")).unwrap();
+        p.get_mut().push("old").unwrap();
+        let e = p.get().init_format(0, &format).unwrap_err();
+        assert_eq!((e.field, e.found), ("magic", 0));
+        assert!(p.get().check_format(0, &format).is_err());
+        assert_eq!(p.get().get(0), Ok(&b"old"[..]));
+
+        // a page from before pages had a header, with one 3 byte message: its
+        // message count and write index sit where the magic and group count
+        // now are
+        let p = DataPage::new(path.join("old")).unwrap();
+        p.get().header.magic.store(1, Ordering::Relaxed);
+        p.get()
+            .header
+            .receiver_groups
+            .store(3 + 4, Ordering::Relaxed);
+        let e = p.get().init_format(0, &format).unwrap_err();
+        assert_eq!((e.field, e.found), ("magic", 1));
+        assert_eq!(p.get().header.receiver_groups.load(Ordering::Relaxed), 7);
+
+        // written by a build with another number of group slots
+        let p = DataPage::new(path.join("1")).unwrap();
+        p.get().init_format(1, &format).unwrap();
+        p.get().header.group_slots.store(8, Ordering::Relaxed);
+        let e = p.get().init_format(1, &format).unwrap_err();
+        assert_eq!((e.field, e.found), ("group_slots", 8));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn zero_length_messages() {
+        let path = mkdir_random();
+        let p = DataPage::new(path.join("0")).unwrap();
+
+        assert_eq!(p.get().try_get(0).unwrap(), None);
+
+        p.get_mut().push([]).unwrap();
+        p.get_mut().push("tick").unwrap();
+        p.get_mut().push([]).unwrap();
+
+        assert_eq!(p.get().try_get(0).unwrap(), Some(&[][..]));
+        assert_eq!(p.get().try_get(1).unwrap(), Some(&b"tick"[..]));
+        assert_eq!(p.get().try_get(2).unwrap(), Some(&[][..]));
+        assert_eq!(p.get().try_get(3).unwrap(), None);
+        assert_eq!(p.get().get(2).unwrap(), &[]);
+
+        // empty messages all the way up to the end of the page
+        for _ in 3..MAX_MESSAGES_PER_PAGE {
+            p.get_mut().push([]).unwrap();
+        }
+        assert_eq!(
+            p.get().try_get(MAX_MESSAGES_PER_PAGE - 1).unwrap(),
+            Some(&[][..])
+        );
+        assert!(p.get_mut().push([]).is_err());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn count_limit_releases_waiters() {
+        let path = mkdir_random();
+        let p = Arc::new(DataPage::new(path.join("0")).unwrap());
+
+        // tiny messages run out of slots long before the page runs out of bytes
+        for _ in 0..MAX_MESSAGES_PER_PAGE - 1 {
+            p.get_mut().push("x").unwrap();
+        }
+        assert!(p.get().bytes_used() < MAX_BYTES_PER_PAGE / 2);
+
+        // parked on the last slot and past it
+        let waiters = [MAX_MESSAGES_PER_PAGE - 1, MAX_MESSAGES_PER_PAGE].map(|count| {
+            let p = p.clone();
+            thread::spawn(move || p.get().get(count).map(<[u8]>::to_vec))
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiters[0].is_finished());
+
+        p.get_mut().push("last").unwrap();
+        assert!(p.get_mut().push("x").is_err());
+        assert!(p.get().is_full());
+
+        let [last, past] = waiters.map(|w| w.join().unwrap());
+        assert_eq!(last, Ok(b"last".to_vec()));
+        assert_eq!(past, Err(EndOfDataPage));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn concurrent_group_claims() {
+        const MESSAGES: u32 = 50_000;
+        const CLAIMERS: usize = 8;
+
+        let path = mkdir_random();
+        let p = Arc::new(DataPage::new(path.join("0")).unwrap());
+
+        // half claim blindly and wait for the message, like `pop`, half only
+        // take published ones, like `try_pop`
+        let claimers: Vec<_> = (0..CLAIMERS)
+            .map(|i| {
+                let p = p.clone();
+                thread::spawn(move || {
+                    let p = p.get();
+                    let mut claimed = Vec::new();
+
+                    loop {
+                        let count = if i % 2 == 0 {
+                            p.increment_group_count(0, 1)
+                        } else {
+                            let count = p.get_group_count(0);
+                            let missed = count < MESSAGES
+                                && (p.try_get(count).unwrap().is_none()
+                                    || p.compare_exchange_group_count(0, count, count + 1)
+                                        .is_err());
+                            if missed {
+                                continue;
+                            }
+                            count
+                        };
+
+                        if count >= MESSAGES {
+                            return claimed;
+                        }
+
+                        assert_eq!(p.get(count).unwrap(), count.to_le_bytes());
+                        claimed.push(count);
+                    }
+                })
+            })
+            .collect();
+
+        for i in 0..MESSAGES {
+            p.get_mut().push(i.to_le_bytes()).unwrap();
+        }
+
+        let mut claimed: Vec<u32> = claimers
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect();
+        claimed.sort_unstable();
+        assert_eq!(claimed, (0..MESSAGES).collect::<Vec<_>>());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn read_only_iter() {
+        let path = mkdir_random();
+        let p = DataPage::new(path.join("0")).unwrap();
+
+        p.get_mut().push("a").unwrap();
+        p.get_mut().push("").unwrap();
+        p.get_mut().push("ccc").unwrap();
+
+        let ro = DataPage::open_read_only(path.join("0")).unwrap();
+        let msgs: Vec<&[u8]> = ro.get().iter().collect();
+        assert_eq!(msgs, [&b"a"[..], b"", b"ccc"]);
+
+        // writes through the read only mapping never reach the file
+        ro.get_mut().push("ddd").unwrap();
+        assert_eq!(p.get().iter().count(), 3);
+        assert_eq!(ro.get().iter().count(), 4);
+
+        // stops at the page full sentinel
+        p.get_mut().push(vec![0; MAX_MESSAGE_SIZE]).unwrap_err();
+        p.get_mut().push("eee").unwrap_err();
+        assert_eq!(p.get().iter().count(), 3);
+
+        assert!(DataPage::open_read_only(path.join("missing")).is_err());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn get_with_timeout_survives_signals() {
+        use std::os::unix::thread::JoinHandleExt;
+
+        extern "C" fn ignore(_: libc::c_int) {}
+
+        const TIMEOUT: Duration = Duration::from_millis(300);
+
+        // a handler without SA_RESTART, so the futex wait returns EINTR. The
+        // old one is put back at the end, the disposition is process wide.
+        let mut old_action: libc::sigaction = unsafe { std::mem::zeroed() };
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            assert_eq!(libc::sigaction(libc::SIGUSR1, &action, &mut old_action), 0);
+        }
+
+        let path = mkdir_random();
+        let p = Arc::new(DataPage::new(path.join("0")).unwrap());
+
+        let p_clone = p.clone();
+        let t = thread::spawn(move || {
+            let start = Instant::now();
+            let msg = p_clone
+                .get()
+                .get_with_timeout(0, TIMEOUT)
+                .map(|m| m.is_some());
+
+            (msg, start.elapsed())
+        });
+
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(20));
+            unsafe { libc::pthread_kill(t.as_pthread_t(), libc::SIGUSR1) };
+        }
+
+        let (msg, elapsed) = t.join().unwrap();
+        unsafe {
+            assert_eq!(
+                libc::sigaction(libc::SIGUSR1, &old_action, std::ptr::null_mut()),
+                0
+            );
+        }
+        assert_eq!(msg, Ok(false));
+        assert!(elapsed >= TIMEOUT, "gave up after {elapsed:?}");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn get_with_timeout() {
+        let path = mkdir_random();
+        let p = Arc::new(DataPage::new(path.join("0")).unwrap());
+
+        let start = Instant::now();
+        assert_eq!(
+            p.get().get_with_timeout(0, Duration::from_millis(50)),
+            Ok(None)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let p_clone = p.clone();
+        let t = thread::spawn(move || {
+            p_clone
+                .get()
+                .get_with_timeout(0, Duration::from_secs(10))
+                .map(|msg| msg.map(<[u8]>::to_vec))
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        p.get_mut().push("in time").unwrap();
+
+        assert_eq!(t.join().unwrap(), Ok(Some(b"in time".to_vec())));
+        assert_eq!(
+            p.get()
+                .get_with_timeout(MAX_MESSAGES_PER_PAGE, Duration::ZERO),
+            Err(EndOfDataPage)
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn full_pages_stop_counting() {
+        let path = mkdir_random();
+        let p = DataPage::new(path.join("0")).unwrap();
+        p.get().close();
+        let closed = p.get().count_write_idx.load();
+
+        // enough page sized pushes to wrap the write_idx if they were counted
+        let msg = vec![0; MAX_MESSAGE_SIZE];
+        for _ in 0..u32::MAX / MAX_BYTES_PER_PAGE * 2 {
+            assert!(p.get_mut().push(&msg).is_err());
+        }
+        assert_eq!(p.get().count_write_idx.load(), closed);
+        assert_eq!(p.get().usage(), (1, MAX_BYTES_PER_PAGE));
+
+        // too large for any page, rejected without touching the counters
+        let p = DataPage::new(path.join("1")).unwrap();
+        p.get_mut().push("a").unwrap();
+        assert!(p.get_mut().push(vec![0; MAX_MESSAGE_SIZE + 1]).is_err());
+        assert_eq!(p.get().count_write_idx.load(), (5, 1));
 
-    fn mkdir_random() -> PathBuf {
-        const TEST_DIR: &str = "/tmp/";
-        let num: u64 = random();
-        let rand_file_name = format!("disk-mpmc-test-{:X}", num);
+        p.get_mut().push(vec![1; MAX_MESSAGE_SIZE - 5]).unwrap();
+        assert_eq!(p.get().get(1).unwrap().len(), MAX_MESSAGE_SIZE - 5);
 
-        let dir = Path::new(TEST_DIR).join(rand_file_name);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn recycled_page_ends_parked_readers() {
+        let path = mkdir_random();
+        let p = Arc::new(DataPage::new(path.join("0")).unwrap());
+        let format = PageFormat::default();
+
+        for page in (0..16).step_by(2) {
+            p.get().init_format(page, &format).unwrap();
+
+            let reader = {
+                let p = p.clone();
+                thread::spawn(move || p.get().get(0).map(<[u8]>::to_vec))
+            };
+            thread::sleep(Duration::from_millis(50));
 
-        std::fs::create_dir_all(&dir).unwrap();
+            // reused as the next page under the parked reader
+            p.get().clear();
+            p.get().init_format(page + 1, &format).unwrap();
+            p.get_mut().push("next page").unwrap();
 
-        dir
+            assert_eq!(reader.join().unwrap(), Err(EndOfDataPage));
+            assert_eq!(p.get().get(0).unwrap(), b"next page");
+            p.get().clear();
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
     }
 
     #[test]
@@ -237,3 +1667,152 @@ mod test {
         e.unwrap();
     }
 }
+
+/// Model checks pushes and reads racing on a page with loom, which tries
+/// every interleaving of the page's atomics. Build with the `loom` cfg, which
+/// also shrinks pages to a few slots:
+///
+/// `RUSTFLAGS="--cfg loom" cargo test --release --lib loom_test`
+#[cfg(all(test, loom))]
+mod loom_test {
+    use std::{collections::HashSet, mem::ManuallyDrop};
+
+    use loom::thread;
+
+    use super::*;
+
+    /// A page on the heap, shared the way `MmapCell` shares a mapped one:
+    /// every producer pushes through its own `&mut`.
+    #[derive(Clone, Copy)]
+    struct SharedPage(*mut DataPage);
+
+    unsafe impl Send for SharedPage {}
+
+    impl SharedPage {
+        fn new() -> Self {
+            let page = DataPage {
+                header: PageHeader {
+                    magic: AtomicU32::new(0),
+                    receiver_groups: AtomicU32::new(0),
+                    flags: AtomicU32::new(0),
+                    page: AtomicU32::new(0),
+                    group_slots: AtomicU32::new(0),
+                    generation: AtomicU32::new(0),
+                },
+                count_write_idx: CountWriteIdx {
+                    write_idx: ManuallyDrop::new(AtomicU64::new(0)),
+                },
+                receiver_group_count: std::array::from_fn(|_| AtomicU32::new(0)),
+                idx_map_with_salt: std::array::from_fn(|_| AtomicU32::new(0)),
+                buf: [0; MAX_BYTES_PER_PAGE as usize],
+            };
+
+            SharedPage(Box::into_raw(Box::new(page)))
+        }
+
+        fn get(&self) -> &DataPage {
+            unsafe { &*self.0 }
+        }
+
+        #[allow(clippy::mut_from_ref)]
+        fn get_mut(&self) -> &mut DataPage {
+            unsafe { &mut *self.0 }
+        }
+
+        /// Once every thread using the page has been joined.
+        fn free(self) {
+            drop(unsafe { Box::from_raw(self.0) });
+        }
+    }
+
+    /// Waiting readers spin through their slot, which adds up to more branches
+    /// than loom allows by default, and bounding preemptions keeps the number
+    /// of interleavings to go through manageable.
+    fn model(f: impl Fn() + Sync + Send + 'static) {
+        let mut builder = loom::model::Builder::new();
+        builder.max_branches = 100_000;
+        builder.preemption_bound.get_or_insert(2);
+        builder.check(f);
+    }
+
+    /// Claims the group's next slot and reads it, like `Receiver::pop`.
+    fn claim(page: &DataPage) -> (u32, Result<Vec<u8>, EndOfDataPage>) {
+        let count = page.increment_group_count(0, 1);
+        (count, page.get(count).map(<[u8]>::to_vec))
+    }
+
+    #[test]
+    fn racing_producers() {
+        model(|| {
+            let page = SharedPage::new();
+
+            let producers: Vec<_> = (0..2_u8)
+                .map(|i| thread::spawn(move || page.get_mut().push([i]).unwrap()))
+                .collect();
+
+            let mut read = HashSet::new();
+            for _ in 0..2 {
+                let (_, msg) = claim(page.get());
+                assert!(read.insert(msg.unwrap()), "message read twice");
+            }
+
+            let counts: HashSet<u32> = producers.into_iter().map(|t| t.join().unwrap()).collect();
+            assert_eq!(counts, HashSet::from([0, 1]));
+            assert_eq!(read, HashSet::from([vec![0], vec![1]]));
+
+            page.free();
+        });
+    }
+
+    #[test]
+    fn competing_consumers() {
+        model(|| {
+            let page = SharedPage::new();
+
+            let consumers: Vec<_> = (0..2)
+                .map(|_| thread::spawn(move || claim(page.get()).1.unwrap()))
+                .collect();
+
+            for i in 0..2_u8 {
+                page.get_mut().push([i]).unwrap();
+            }
+
+            let read: HashSet<_> = consumers.into_iter().map(|t| t.join().unwrap()).collect();
+            assert_eq!(read, HashSet::from([vec![0], vec![1]]));
+
+            page.free();
+        });
+    }
+
+    #[test]
+    fn page_full_marker() {
+        // only one of these fits on a page
+        const LEN: usize = MAX_BYTES_PER_PAGE as usize / 2;
+
+        model(|| {
+            let page = SharedPage::new();
+
+            let producers: Vec<_> = (0..2_u8)
+                .map(|i| thread::spawn(move || page.get_mut().push([i; LEN]).ok()))
+                .collect();
+            // placed past the marker, e.g. by a seek, so nobody passes the
+            // marker on to it and it has to find out the page is done itself
+            let ahead = thread::spawn(move || page.get().get(2).map(<[u8]>::to_vec));
+
+            let (first, msg) = claim(page.get());
+            let msg = msg.unwrap();
+            assert_eq!(first, 0);
+            assert_eq!(claim(page.get()), (1, Err(EndOfDataPage)));
+
+            let pushed: Vec<_> = producers
+                .into_iter()
+                .filter_map(|t| t.join().unwrap())
+                .collect();
+            assert_eq!(pushed, [0]);
+            assert_eq!(msg.len(), LEN);
+            assert_eq!(ahead.join().unwrap(), Err(EndOfDataPage));
+
+            page.free();
+        });
+    }
+}
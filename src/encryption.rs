@@ -0,0 +1,110 @@
+#[cfg(feature = "encryption")]
+use std::sync::Arc;
+
+#[cfg(feature = "encryption")]
+use aes_gcm::{
+    aead::{AeadInPlace, KeyInit, Nonce},
+    Aes256Gcm, Tag,
+};
+
+use crate::Error;
+
+/// Bytes the authentication tag adds after every encrypted payload.
+pub const TAG_SIZE: usize = 16;
+
+/// Encrypts payloads at rest with AES-256-GCM, see
+/// `DataPagesManagerBuilder::encryption_key`.
+///
+/// The nonce is the message's `(page, count)`, which is unique within a
+/// queue, so no nonce is stored. A key must still only ever be used for one
+/// queue: another topic or directory with the same key, or the same
+/// directory once it's emptied and starts over from page 0, would reuse
+/// nonces.
+#[derive(Clone, Default)]
+pub(crate) struct Cipher {
+    #[cfg(feature = "encryption")]
+    aead: Option<Arc<Aes256Gcm>>,
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never print anything derived from the key
+        f.debug_struct("Cipher").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Cipher {
+    pub(crate) fn new(key: &[u8; 32]) -> Self {
+        Cipher {
+            aead: Some(Arc::new(Aes256Gcm::new(key.into()))),
+        }
+    }
+
+    fn aead(&self) -> &Aes256Gcm {
+        self.aead.as_deref().expect("queue has an encryption key")
+    }
+
+    fn nonce(page: usize, count: u32) -> Nonce<Aes256Gcm> {
+        let mut nonce = Nonce::<Aes256Gcm>::default();
+        nonce[..8].copy_from_slice(&(page as u64).to_le_bytes());
+        nonce[8..].copy_from_slice(&count.to_le_bytes());
+
+        nonce
+    }
+
+    /// Encrypts, in place, the record stored at `count` on `page`: its first
+    /// `aad_len` bytes (timestamp and key) are only authenticated, the rest is
+    /// the payload followed by `TAG_SIZE` bytes the tag is written into.
+    pub(crate) fn seal(&self, page: usize, count: u32, aad_len: usize, record: &mut [u8]) {
+        let (aad, rest) = record.split_at_mut(aad_len);
+        let (payload, tag) = rest.split_at_mut(rest.len() - TAG_SIZE);
+
+        let sealed = self
+            .aead()
+            .encrypt_in_place_detached(&Self::nonce(page, count), aad, payload)
+            .expect("payload fits in a page, far below the GCM limit");
+        tag.copy_from_slice(&sealed);
+    }
+
+    /// Decrypts a payload sealed by `seal` into `out`, replacing whatever was
+    /// in it. Fails if the key is wrong or anything authenticated changed.
+    pub(crate) fn open(
+        &self,
+        page: usize,
+        count: u32,
+        aad: &[u8],
+        sealed: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        out.clear();
+
+        let len = sealed.len().checked_sub(TAG_SIZE).ok_or(Error::Decrypt)?;
+        let (payload, tag) = sealed.split_at(len);
+        out.extend_from_slice(payload);
+
+        self.aead()
+            .decrypt_in_place_detached(&Self::nonce(page, count), aad, out, Tag::from_slice(tag))
+            .map_err(|_| Error::Decrypt)
+    }
+}
+
+// without the feature no queue can be built with a key, and pages recorded
+// as encrypted fail to open, so these are never reached
+#[cfg(not(feature = "encryption"))]
+impl Cipher {
+    pub(crate) fn seal(&self, _page: usize, _count: u32, _aad_len: usize, _record: &mut [u8]) {
+        unreachable!("encryption needs the `encryption` feature")
+    }
+
+    pub(crate) fn open(
+        &self,
+        _page: usize,
+        _count: u32,
+        _aad: &[u8],
+        _sealed: &[u8],
+        _out: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        unreachable!("encryption needs the `encryption` feature")
+    }
+}
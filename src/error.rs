@@ -0,0 +1,106 @@
+use std::{ops::Range, path::PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(std::io::Error),
+    /// The filesystem ran out of space creating or sizing a page file. Worth
+    /// backing off and retrying once space is freed, unlike most `Io` errors.
+    ///
+    /// Only covers file operations: pushes write to mapped memory, and a write
+    /// to a sparse page's block the filesystem can't allocate kills the
+    /// process with `SIGBUS` instead. Use
+    /// `DataPagesManagerBuilder::preallocate` where a full disk has to be
+    /// survivable.
+    #[error("disk is full")]
+    DiskFull(#[source] std::io::Error),
+    #[error("receiver group {0} is out of range")]
+    GroupOutOfRange(usize),
+    #[error("all receiver groups are in use")]
+    NoGroupsAvailable,
+    #[error("message of {0} bytes doesn't fit in a page")]
+    MessageTooLarge(usize),
+    #[error("queue is full and the oldest page hasn't been consumed")]
+    Full,
+    #[error("queue was not created with keys enabled")]
+    KeysDisabled,
+    #[error("queue records carry a timestamp or key, or are compressed or encrypted")]
+    MetadataEnabled,
+    #[error("message failed to decompress")]
+    Decompress,
+    #[error("message failed to decrypt, the key is wrong or the page was tampered with")]
+    Decrypt,
+    #[error("partition range {0:?} is empty or out of range")]
+    InvalidPartitions(Range<u32>),
+    #[error("frame length prefix says {expected} bytes but the frame holds {found}")]
+    InvalidFrame { expected: usize, found: usize },
+    #[error("invalid manager configuration: {0}")]
+    InvalidConfig(&'static str),
+    #[error("page {path:?} is truncated to {len} bytes, expected {expected} (see DataPagesManager::repair)")]
+    TruncatedPage {
+        path: PathBuf,
+        len: u64,
+        expected: u64,
+    },
+    #[error("page {path:?} was created with {field} = {found}, expected {expected}")]
+    FormatMismatch {
+        path: PathBuf,
+        field: &'static str,
+        expected: u32,
+        found: u32,
+    },
+    #[error("manager was shut down")]
+    Closed,
+    #[error("page {0} isn't retained, or is among the newest min_datapages pages")]
+    PageNotDeletable(usize),
+    /// Pages are being added faster than
+    /// `DataPagesManagerBuilder::page_creation_limit` allows.
+    #[error("pages are being created faster than the configured limit")]
+    RolloverThrottled,
+    /// The next message doesn't fit in the buffer passed to
+    /// `Receiver::read_message`, which needs `needed` bytes.
+    #[error("message of {needed} bytes doesn't fit in a buffer of {len}")]
+    BufferTooSmall { needed: usize, len: usize },
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::StorageFull => Error::DiskFull(e),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+/// For `std::io::Read` on receivers. I/O errors come back as they were, a
+/// too small buffer as `InvalidInput`.
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) | Error::DiskFull(e) => e,
+            e @ Error::BufferTooSmall { .. } => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+            }
+            e => std::io::Error::other(e),
+        }
+    }
+}
+
+/// A `Sender::push_all` that failed part way, after pushing `items[..pushed]`.
+#[derive(Debug, thiserror::Error)]
+#[error("push failed after {pushed} messages")]
+pub struct PushAllError {
+    pub pushed: usize,
+    #[source]
+    pub error: Error,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TryPushError {
+    #[error("current page is full")]
+    PageFull,
+    #[error("message of {0} bytes doesn't fit in a page")]
+    TooLarge(usize),
+    #[error("manager was shut down")]
+    Closed,
+}
@@ -1,185 +1,3904 @@
-use std::{marker::PhantomData, sync::Arc};
+use std::{
+    future::poll_fn,
+    marker::PhantomData,
+    ops::Range,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use mmapcell::MmapCell;
 
+mod codec;
 mod datapage;
+mod encryption;
+mod error;
 pub mod manager;
+mod offload;
+mod read;
+mod sharded;
+mod tee;
+#[cfg(all(test, not(loom)))]
+mod test_util;
 
-use datapage::DataPage;
-use manager::DataPagesManager;
+pub use codec::Codec;
+pub use datapage::{
+    DataPage, EndOfDataPage, SlotProblem, KEY_SIZE, MAX_BYTES_PER_PAGE, MAX_MESSAGES_PER_PAGE,
+    MAX_MESSAGE_SIZE, MAX_RECEIVER_GROUPS, MAX_SHORT_MESSAGE_SIZE, TIMESTAMP_SIZE,
+};
+pub use encryption::TAG_SIZE;
+pub use error::{Error, PushAllError, TryPushError};
+pub use offload::BlockingOffload;
+pub use sharded::{ShardedReceiver, ShardedSender};
+pub use tee::TeeReceiver;
+
+use datapage::PageFormat;
+use encryption::Cipher;
+use manager::{AckHold, ConsumerGuard, DataPagesManager};
 
 #[derive(Clone)]
 pub struct Grouped;
 
-#[derive(Clone)]
-pub struct Anonymous;
+#[derive(Clone)]
+pub struct Anonymous;
+
+/// Reads messages off a queue.
+///
+/// Cloning a `Receiver<Grouped>` adds another consumer to the same group, so
+/// the clones split the messages between them. To get a copy that reads the
+/// whole stream on its own use `Receiver::<Grouped>::fork_independent`, or
+/// `Receiver::<Grouped>::tee` to watch what the group consumes.
+/// Clones of a `Receiver<Anonymous>` are already independent.
+#[derive(Clone)]
+pub struct Receiver<T> {
+    group: usize,
+    anon_count: u32,
+    manager: DataPagesManager,
+    datapage_count: usize,
+    datapage: Arc<MmapCell<DataPage>>,
+    /// The length prefix width of the queue's pages, known from its format so
+    /// reads don't load it from every page's header.
+    size_of_len: usize,
+    partitions: Option<PartitionFilter>,
+    scratch: Vec<u8>,
+    /// Decrypted but still compressed payload, on queues with both.
+    opened: Vec<u8>,
+    claim: Claim,
+    /// Counts on the current page an anonymous receiver's `pop_lifo` still
+    /// has to return, newest at the end.
+    lifo: Range<u32>,
+    unacked: Unacked,
+    _consumer: Option<ConsumerGuard>,
+    _type: std::marker::PhantomData<T>,
+}
+
+/// A slot on the current page a grouped receiver claimed in `pop_async` but
+/// hasn't returned yet. It's kept across cancellation so the message isn't
+/// lost to the group, and isn't copied into clones so it's delivered once.
+///
+/// A claim past the end of the page is kept too, until the receiver moves on
+/// to the next page. Otherwise every `pop_bounded` or `pop_async` poll waiting
+/// for that page would claim another slot, until the page's group count
+/// wrapped around to messages the group already had.
+#[derive(Debug, Default)]
+struct Claim(Option<u32>);
+
+impl Clone for Claim {
+    fn clone(&self) -> Self {
+        Claim(None)
+    }
+}
+
+/// What `ack` commits: the receiver's hold on its group's offset at the oldest
+/// message `pop_commit_after` returned since the last `ack`, and the offset
+/// past the newest. Not copied into clones, which ack their own messages.
+#[derive(Default)]
+struct Unacked(Option<(AckHold, u64)>);
+
+impl Clone for Unacked {
+    fn clone(&self) -> Self {
+        Unacked(None)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PartitionFilter {
+    partitions: u32,
+    assigned: Range<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiverStats {
+    pub datapage_count: usize,
+    /// Slots the group has claimed on its current page.
+    pub consumed_count: u32,
+    /// Messages reserved on the current page that the group hasn't claimed yet.
+    pub lag: u32,
+}
+
+/// Where a message is stored, from `Receiver::pop_located`: slot `count` of
+/// page `page`, the page file ending in `.<page>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageLoc {
+    pub page: usize,
+    pub count: u32,
+}
+
+/// A receiver's position, from `Receiver::cursor`, to recreate it elsewhere
+/// (e.g. in another process) with `from_cursor`.
+///
+/// Only a value: unlike `DataPagesManager::commit` nothing is written to
+/// disk. `to_bytes` gives a fixed wire format, and with the `serde` feature
+/// it can be serialized directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cursor {
+    /// The receiver's group, `None` for an anonymous receiver.
+    pub group: Option<usize>,
+    pub datapage_count: usize,
+    /// Next message the receiver reads on page `datapage_count`.
+    pub count: u32,
+}
+
+impl Cursor {
+    /// Little-endian group plus one (0 for none) and page as `u64`s, then
+    /// the count as a `u32`.
+    pub fn to_bytes(&self) -> [u8; 20] {
+        let group = self.group.map_or(0, |g| g as u64 + 1);
+
+        let mut bytes = [0; 20];
+        bytes[..8].copy_from_slice(&group.to_le_bytes());
+        bytes[8..16].copy_from_slice(&(self.datapage_count as u64).to_le_bytes());
+        bytes[16..].copy_from_slice(&self.count.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; 20]) -> Self {
+        let group = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let page = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+
+        Cursor {
+            group: group.checked_sub(1).map(|g| g as usize),
+            datapage_count: page as usize,
+            count: u32::from_le_bytes(bytes[16..].try_into().unwrap()),
+        }
+    }
+}
+
+pub trait GenReceiver {
+    /// Pops the next message, blocking until one is published.
+    ///
+    /// The slice borrows the receiver's current page. The receiver keeps that
+    /// page mapped for as long as the borrow lives, even if the manager
+    /// reclaims it from the ring and deletes its file meanwhile, but it can't
+    /// be kept past the next `pop`. Use `pop_owned` to hold on to messages.
+    ///
+    /// On queues with a `Codec` or encryption the slice points into a buffer
+    /// owned by the receiver instead, which the next `pop` overwrites.
+    fn pop(&mut self) -> Result<&[u8], Error>;
+
+    /// Like `pop` but copies the message out so it can outlive the receiver's
+    /// borrow and the page it came from.
+    fn pop_owned(&mut self) -> Result<Vec<u8>, Error> {
+        self.pop().map(<[u8]>::to_vec)
+    }
+
+    /// Pops the next message along with the time it was pushed, `None` on
+    /// queues built without timestamps, which don't record one.
+    ///
+    /// Plain `pop` on queues with timestamps strips the timestamp.
+    fn pop_with_meta(&mut self) -> Result<(Option<SystemTime>, &[u8]), Error>;
+
+    /// Pops the next message along with the key it was pushed with.
+    ///
+    /// Only available on queues built with keys enabled.
+    fn pop_keyed(&mut self) -> Result<(u64, &[u8]), Error>;
+
+    /// Pops the next message still framed the way it's stored on disk: a
+    /// little-endian `u32` length followed by the payload. This is what
+    /// `tokio_util::codec::LengthDelimitedCodec` expects when built with
+    /// `.little_endian()` (its default is big-endian), so frames can be
+    /// forwarded as is.
+    ///
+    /// Not available on queues built with timestamps, keys, a codec or
+    /// encryption.
+    fn pop_framed(&mut self) -> Result<&[u8], Error> {
+        self.pop_framed_as(Endian::Little)
+    }
+
+    /// Like `pop_framed` but with the length prefix in `endian` byte order.
+    ///
+    /// `Endian::Little` is `pop_framed`. With `Endian::Big`, which matches a
+    /// default `LengthDelimitedCodec`, the frame is copied into a buffer
+    /// owned by the receiver with its prefix rewritten, and the next `pop`
+    /// overwrites it.
+    fn pop_framed_as(&mut self, endian: Endian) -> Result<&[u8], Error>;
+}
+
+/// Byte order of the `u32` length prefix of a frame, see
+/// `GenReceiver::pop_framed_as` and `Sender::push_framed_as`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endian {
+    /// How messages are stored on disk, `LengthDelimitedCodec` built with
+    /// `.little_endian()`.
+    #[default]
+    Little,
+    /// `LengthDelimitedCodec`'s default.
+    Big,
+}
+
+impl Endian {
+    fn len_to_bytes(self, len: u32) -> [u8; 4] {
+        match self {
+            Endian::Little => len.to_le_bytes(),
+            Endian::Big => len.to_be_bytes(),
+        }
+    }
+
+    fn len_from_bytes(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+/// Which of `partitions` a message key belongs to.
+///
+/// Keys are hashed first so sequential keys still spread evenly.
+pub fn key_partition(key: u64, partitions: u32) -> u32 {
+    let hash = key.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+
+    ((hash * partitions as u64) >> 32) as u32
+}
+
+/// A stored message (without its length prefix) split into its parts.
+struct Record<'a> {
+    timestamp: Option<SystemTime>,
+    key: Option<u64>,
+    data: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    fn parse(format: &PageFormat, mut record: &'a [u8]) -> Self {
+        let mut timestamp = None;
+        if format.timestamps {
+            let (ts, rest) = record.split_at(TIMESTAMP_SIZE);
+            let nanos = u64::from_le_bytes(ts.try_into().expect("timestamp is 8 bytes"));
+
+            timestamp = Some(UNIX_EPOCH + Duration::from_nanos(nanos));
+            record = rest;
+        }
+
+        let mut key = None;
+        if format.keys {
+            let (k, rest) = record.split_at(KEY_SIZE);
+
+            key = Some(u64::from_le_bytes(k.try_into().expect("key is 8 bytes")));
+            record = rest;
+        }
+
+        Record {
+            timestamp,
+            key,
+            data: record,
+        }
+    }
+}
+
+fn strip_frame<'a>(format: &PageFormat, framed: &'a [u8]) -> &'a [u8] {
+    &framed[format.size_of_len()..]
+}
+
+/// A stored message (with its length prefix) and where it's stored.
+struct Stored<'a> {
+    page: usize,
+    count: u32,
+    framed: &'a [u8],
+}
+
+impl Stored<'_> {
+    /// Decrypts and decompresses the payload into `out`, with `opened`
+    /// holding it in between if the queue does both.
+    fn decode_payload(
+        &self,
+        format: &PageFormat,
+        cipher: &Cipher,
+        out: &mut Vec<u8>,
+        opened: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let stored = strip_frame(format, self.framed);
+        let data = Record::parse(format, stored).data;
+
+        if !format.encrypted {
+            return format.codec.decompress(data, out);
+        }
+
+        let aad = &stored[..stored.len() - data.len()];
+        if format.codec == Codec::None {
+            return cipher.open(self.page, self.count, aad, data, out);
+        }
+
+        cipher.open(self.page, self.count, aad, data, opened)?;
+        format.codec.decompress(opened, out)
+    }
+}
+
+impl std::fmt::Debug for Receiver<Grouped> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("group", &self.group)
+            .field("datapage_count", &self.datapage_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for Receiver<Anonymous> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("datapage_count", &self.datapage_count)
+            .field("anon_count", &self.anon_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Only deliver messages whose key (see `key_partition`) falls in the
+    /// `assigned` range out of `partitions`. Messages with the same key stay
+    /// in the order they were pushed.
+    ///
+    /// Messages outside the range are skipped by claiming them like any other,
+    /// so for a grouped receiver they're gone for the whole group. Every
+    /// receiver in a group needs the same filter: give each partition range
+    /// its own group, otherwise receivers sharing a group swallow each other's
+    /// messages.
+    pub fn with_partitions(mut self, partitions: u32, assigned: Range<u32>) -> Result<Self, Error> {
+        if !self.manager.format().keys {
+            return Err(Error::KeysDisabled);
+        }
+
+        if assigned.is_empty() || assigned.end > partitions {
+            return Err(Error::InvalidPartitions(assigned));
+        }
+
+        self.partitions = Some(PartitionFilter {
+            partitions,
+            assigned,
+        });
+
+        Ok(self)
+    }
+
+    /// Pops with `pop_record` and splits the message into its parts,
+    /// decrypting and decompressing the payload into the scratch buffer if
+    /// the queue has a codec or encryption.
+    fn pop_decoded(
+        &mut self,
+        pop_record: fn(&mut Self) -> Result<Stored<'_>, Error>,
+    ) -> Result<Record<'_>, Error> {
+        let format = *self.manager.format();
+
+        if format.codec == Codec::None && !format.encrypted {
+            let stored = pop_record(self)?;
+            return Ok(Record::parse(&format, strip_frame(&format, stored.framed)));
+        }
+
+        let cipher = self.manager.cipher().clone();
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let mut opened = std::mem::take(&mut self.opened);
+
+        let stored = pop_record(self)?;
+        let record = Record::parse(&format, strip_frame(&format, stored.framed));
+        let (timestamp, key) = (record.timestamp, record.key);
+
+        stored.decode_payload(&format, &cipher, &mut scratch, &mut opened)?;
+        self.scratch = scratch;
+        self.opened = opened;
+
+        Ok(Record {
+            timestamp,
+            key,
+            data: &self.scratch,
+        })
+    }
+
+    /// Pops the next stored frame, with its length prefix rewritten into
+    /// `scratch` unless `endian` is how it's stored already.
+    fn pop_reframed(
+        &mut self,
+        pop_record: fn(&mut Self) -> Result<Stored<'_>, Error>,
+        endian: Endian,
+    ) -> Result<&[u8], Error> {
+        if !self.manager.format().is_plain() {
+            return Err(Error::MetadataEnabled);
+        }
+
+        if endian == Endian::Little {
+            return pop_record(self).map(|stored| stored.framed);
+        }
+
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let stored = pop_record(self)?;
+        let data = &stored.framed[DataPage::SIZE_OF_LEN..];
+
+        scratch.clear();
+        scratch.extend_from_slice(&endian.len_to_bytes(data.len() as u32));
+        scratch.extend_from_slice(data);
+        self.scratch = scratch;
+
+        Ok(&self.scratch)
+    }
+
+    /// Moves onto the page after the current one, waiting for a producer to
+    /// create it.
+    fn roll_page(&mut self) -> Result<(), Error> {
+        let (dp_count, datapage) = self
+            .manager
+            .wait_datapage(self.datapage_count.wrapping_add(1))?;
+        self.enter_page(dp_count, datapage);
+
+        Ok(())
+    }
+
+    /// Like `roll_page` but returns `false` instead of waiting if the next
+    /// page doesn't exist yet.
+    fn try_roll_page(&mut self) -> Result<bool, Error> {
+        let Some((dp_count, datapage)) = self
+            .manager
+            .get_datapage(self.datapage_count.wrapping_add(1))?
+        else {
+            return Ok(false);
+        };
+        self.enter_page(dp_count, datapage);
+
+        Ok(true)
+    }
+
+    /// Like `roll_page` but returns `false` if the next page doesn't show up
+    /// before `deadline`.
+    fn roll_page_until(&mut self, deadline: Instant) -> Result<bool, Error> {
+        let Some((dp_count, datapage)) = self
+            .manager
+            .wait_datapage_until(self.datapage_count.wrapping_add(1), deadline)?
+        else {
+            return Ok(false);
+        };
+        self.enter_page(dp_count, datapage);
+
+        Ok(true)
+    }
+
+    /// Starts reading page `dp_count` from its first message.
+    fn enter_page(&mut self, dp_count: usize, datapage: Arc<MmapCell<DataPage>>) {
+        self.claim.0 = None;
+        self.anon_count = 0;
+        self.lifo = 0..0;
+        self.datapage_count = dp_count;
+        self.datapage = datapage;
+    }
+
+    /// Whether the stored message `framed` passes this receiver's partition filter.
+    fn accepts(&self, framed: &[u8]) -> bool {
+        let Some(filter) = &self.partitions else {
+            return true;
+        };
+
+        let format = self.manager.format();
+        let record = Record::parse(format, strip_frame(format, framed));
+        let key = record.key.expect("filters need keys enabled");
+
+        filter
+            .assigned
+            .contains(&key_partition(key, filter.partitions))
+    }
+}
+
+impl Receiver<Grouped> {
+    /// Creates a receiver that consumes on behalf of `group`.
+    ///
+    /// Every receiver in a group (including clones) shares the group's position
+    /// on each page, so together they act as a work queue: each message is
+    /// delivered to exactly one of them. Use a different group per subscriber
+    /// to have every subscriber see every message.
+    ///
+    /// Groups never affect each other's position: each one starts every page
+    /// at its first message, however far the other groups have read.
+    pub fn new(group: usize, manager: DataPagesManager) -> Result<Self, Error> {
+        if group >= manager.receiver_groups() {
+            return Err(Error::GroupOutOfRange(group));
+        }
+
+        let consumer = manager.attach_consumer(group);
+        Self::with_consumer(consumer, manager)
+    }
+
+    /// Like `new` but returns `Ok(None)` if nothing was pushed to the queue
+    /// yet (see `DataPagesManager::is_empty`), telling an empty queue apart
+    /// from one whose producers are just slow.
+    pub fn new_if_nonempty(group: usize, manager: DataPagesManager) -> Result<Option<Self>, Error> {
+        if manager.is_empty() {
+            return Ok(None);
+        }
+
+        Self::new(group, manager).map(Some)
+    }
+
+    pub(crate) fn with_consumer(
+        consumer: ConsumerGuard,
+        manager: DataPagesManager,
+    ) -> Result<Self, Error> {
+        let (datapage_count, datapage) = manager.consumer_datapage(0)?;
+        let size_of_len = manager.format().size_of_len();
+
+        Ok(Receiver {
+            group: consumer.group(),
+            anon_count: 0,
+            manager,
+            datapage_count,
+            datapage,
+            size_of_len,
+            partitions: None,
+            scratch: Vec::new(),
+            opened: Vec::new(),
+            claim: Claim::default(),
+            lifo: 0..0,
+            unacked: Unacked::default(),
+            _consumer: Some(consumer),
+            _type: PhantomData,
+        })
+    }
+
+    /// Resumes `group` from the offset last committed with
+    /// `DataPagesManager::commit`, or from the start if nothing was committed.
+    ///
+    /// Any progress the group made past the committed offset is discarded, so
+    /// messages consumed but not committed before a crash are delivered again.
+    /// If the committed page was already reclaimed the receiver starts at the
+    /// oldest retained page instead. No other receiver of the group should be
+    /// running while this is called.
+    pub fn resume_committed(group: usize, manager: DataPagesManager) -> Result<Self, Error> {
+        let offset = manager.committed(group)?.unwrap_or(0);
+        let page = (offset / MAX_MESSAGES_PER_PAGE as u64) as usize;
+        let count = (offset % MAX_MESSAGES_PER_PAGE as u64) as u32;
+
+        Self::resume_at(group, manager, page, count).map(|(rx, _)| rx)
+    }
+
+    /// Recreates a receiver at the position `cursor` took from one of
+    /// `cursor.group`, returning it along with where it actually resumed.
+    ///
+    /// Sets the group's position, like `resume_committed`, so no other
+    /// receiver of the group should be running meanwhile; a message the
+    /// source had claimed but not returned is delivered again. A page that
+    /// was already reclaimed is replaced by the oldest retained one, from its
+    /// first message, which shows in the returned cursor. Fails with
+    /// `InvalidConfig` on a cursor of an anonymous receiver.
+    pub fn from_cursor(cursor: Cursor, manager: DataPagesManager) -> Result<(Self, Cursor), Error> {
+        let group = cursor
+            .group
+            .ok_or(Error::InvalidConfig("cursor is from an anonymous receiver"))?;
+
+        Self::resume_at(group, manager, cursor.datapage_count, cursor.count)
+    }
+
+    fn resume_at(
+        group: usize,
+        manager: DataPagesManager,
+        page: usize,
+        count: u32,
+    ) -> Result<(Self, Cursor), Error> {
+        let mut rx = Receiver::new(group, manager)?;
+        let (dp_count, datapage) = rx.manager.consumer_datapage(page)?;
+        let count = if dp_count == page { count } else { 0 };
+
+        rx.manager.reset_group(group, dp_count, count);
+        rx.datapage_count = dp_count;
+        rx.datapage = datapage;
+
+        let cursor = rx.cursor();
+        Ok((rx, cursor))
+    }
+
+    /// Where this receiver is: the next message it pops, or the one it
+    /// claimed but hasn't returned yet.
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            group: Some(self.group),
+            datapage_count: self.datapage_count,
+            count: self.next_count(),
+        }
+    }
+
+    /// Count on the current page of the next message this receiver returns.
+    fn next_count(&self) -> u32 {
+        match self.claim.0 {
+            Some(count) => count,
+            None => self.datapage.get().get_group_count(self.group),
+        }
+        .min(MAX_MESSAGES_PER_PAGE)
+    }
+
+    /// Global offset of the next message this receiver's group will claim,
+    /// suitable for `DataPagesManager::commit`.
+    pub fn offset(&self) -> u64 {
+        let count = self
+            .datapage
+            .get()
+            .get_group_count(self.group)
+            .min(MAX_MESSAGES_PER_PAGE);
+
+        self.datapage_count as u64 * MAX_MESSAGES_PER_PAGE as u64 + count as u64
+    }
+
+    /// Creates an anonymous receiver that reads the full stream on its own,
+    /// starting at the next message this receiver would pop.
+    ///
+    /// Unlike `clone`, which shares the group's position so the two split the
+    /// messages, the fork keeps its own position and sees every message from
+    /// here on, while this receiver and its group are unaffected. A message
+    /// this receiver claimed but hasn't returned yet is seen by both. Messages
+    /// other receivers of the group claimed but haven't returned yet aren't:
+    /// they're behind the group's count, so the fork starts past them. Like
+    /// any anonymous receiver the fork doesn't hold back reclamation.
+    pub fn fork_independent(&self) -> Receiver<Anonymous> {
+        self.fork()
+    }
+
+    /// Creates a read-only receiver that sees the messages this receiver's
+    /// group consumes from here on, starting at the next one this receiver
+    /// would pop, without consuming anything itself.
+    ///
+    /// Meant for auditing and monitoring: the group's consumers get exactly
+    /// what they would without it. Best effort only, see `TeeReceiver`.
+    pub fn tee(&self) -> TeeReceiver {
+        TeeReceiver::new(self.group, self.fork())
+    }
+
+    fn fork(&self) -> Receiver<Anonymous> {
+        Receiver {
+            group: 0,
+            anon_count: self.next_count(),
+            manager: self.manager.clone(),
+            datapage_count: self.datapage_count,
+            datapage: self.datapage.clone(),
+            size_of_len: self.size_of_len,
+            partitions: self.partitions.clone(),
+            scratch: Vec::new(),
+            opened: Vec::new(),
+            claim: Claim::default(),
+            lifo: 0..0,
+            unacked: Unacked::default(),
+            _consumer: None,
+            _type: PhantomData,
+        }
+    }
+
+    /// Marks this receiver's group as alive, see `DataPagesManagerBuilder::heartbeat_ttl`.
+    pub fn heartbeat(&self) {
+        self.manager.heartbeat(self.group);
+    }
+
+    pub fn stats(&self) -> ReceiverStats {
+        let datapage = self.datapage.get();
+        let consumed_count = datapage.get_group_count(self.group);
+        let (published_count, _) = datapage.usage();
+
+        ReceiverStats {
+            datapage_count: self.datapage_count,
+            consumed_count,
+            lag: published_count.saturating_sub(consumed_count),
+        }
+    }
+
+    /// How many messages on the current page producers have stored, or are
+    /// still writing, and this receiver's group hasn't consumed yet,
+    /// including one this receiver already claimed but hasn't returned.
+    ///
+    /// Only looks at the current page, not the backlog on the pages after it,
+    /// so 0 doesn't mean the queue is empty, just that this page is used up
+    /// for now. A message whose push is still in progress counts, so `pop`
+    /// can still wait on it briefly.
+    pub fn available(&self) -> u32 {
+        let datapage = self.datapage.get();
+        let consumed_count = datapage.get_group_count(self.group);
+        let claimed = match self.claim.0 {
+            Some(count)
+                if datapage
+                    .try_get_framed_as(count, self.size_of_len)
+                    .is_ok_and(|m| m.is_some()) =>
+            {
+                1
+            }
+            _ => 0,
+        };
+
+        // slots of pushes that didn't fit aren't messages, see `stored_count`
+        datapage
+            .stored_count()
+            .saturating_sub(consumed_count)
+            .saturating_add(claimed)
+    }
+
+    /// How many messages this receiver's group has yet to consume, on its
+    /// current page and every retained page after it, including one this
+    /// receiver claimed but hasn't returned. Unlike `available` this is the
+    /// group's whole backlog, counted the same way on each page.
+    ///
+    /// Reads each of those pages' counts under the ring's read lock, and
+    /// like `available` counts messages whose push is still in progress.
+    /// Pages reclaimed between the group's current page and the oldest
+    /// retained one aren't counted: their messages are lost to the group,
+    /// which moves on to the oldest retained page once it's done with its
+    /// current one.
+    pub fn backlog(&self) -> Result<u64, Error> {
+        let after = self
+            .manager
+            .backlog_after(self.group, self.datapage_count)?;
+
+        Ok(self.available() as u64 + after)
+    }
+
+    /// Non-blocking `pop`. Returns `Ok(None)` when caught up with the producers.
+    ///
+    /// Unlike `pop` this only claims a slot for the group once its message is
+    /// published, so giving up at the live tail never costs the group a message.
+    pub fn try_pop(&mut self) -> Result<Option<&[u8]>, Error> {
+        if self.try_claim()? {
+            self.pop().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Like `pop` but gives up with `Ok(None)` once `deadline` has passed,
+    /// moving on to later pages while there's time left.
+    ///
+    /// Like `try_pop` this only claims a slot for the group once its message
+    /// is published, so giving up never costs the group a message.
+    pub fn pop_until(&mut self, deadline: Instant) -> Result<Option<&[u8]>, Error> {
+        if self.claim_until(deadline)? {
+            self.pop().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// `try_claim` that waits for the group's next message, or the next page,
+    /// until `deadline`.
+    fn claim_until(&mut self, deadline: Instant) -> Result<bool, Error> {
+        loop {
+            if self.try_claim()? {
+                return Ok(true);
+            }
+
+            // caught up: wait for whatever the group takes next to show up
+            let count = self.next_count();
+            match self
+                .datapage
+                .get()
+                .get_framed_until_as(count, deadline, self.size_of_len)
+            {
+                Ok(Some(_)) => {}
+                Ok(None) => return Ok(false),
+                Err(_end_of_datapage) => {
+                    if !self.roll_page_until(deadline)? {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pops up to `max` messages that are already published, stopping early at
+    /// the live tail instead of waiting for more.
+    pub fn drain(&mut self, max: usize) -> Result<Vec<Vec<u8>>, Error> {
+        let mut msgs = Vec::new();
+
+        while msgs.len() < max {
+            match self.try_pop()? {
+                Some(msg) => msgs.push(msg.to_vec()),
+                None => break,
+            }
+        }
+
+        Ok(msgs)
+    }
+
+    /// Makes `claim` hold a published message this receiver accepts, if
+    /// there is one, without waiting.
+    fn try_claim(&mut self) -> Result<bool, Error> {
+        loop {
+            let datapage = self.datapage.get();
+            let count = match self.claim.0 {
+                Some(count) => count,
+                None => datapage.get_group_count(self.group),
+            };
+
+            match datapage.try_get_framed_as(count, self.size_of_len) {
+                Ok(Some(framed)) => {
+                    // only take the slot from the group once its message is there
+                    if self.claim.0.is_none() {
+                        if datapage
+                            .compare_exchange_group_count(self.group, count, count + 1)
+                            .is_err()
+                        {
+                            continue;
+                        }
+
+                        self.claim.0 = Some(count);
+                    }
+
+                    if self.accepts(framed) {
+                        return Ok(true);
+                    }
+
+                    self.claim.0 = None;
+                    continue;
+                }
+                Ok(None) => return Ok(false),
+                Err(_end_of_datapage) => {}
+            }
+
+            if !self.try_roll_page()? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Drops up to `n` of the group's messages without reading them, moving on
+    /// to later pages as needed. Returns how many were skipped, fewer than `n`
+    /// if it caught up with the producers.
+    ///
+    /// Only published messages are skipped, so the group never lands past a
+    /// page's last message into a slot a producer hasn't written. Every message
+    /// counts, whether or not it passes the partition filter.
+    pub fn skip(&mut self, n: usize) -> Result<usize, Error> {
+        let mut skipped = 0;
+
+        // unless the claim is past the end of the page, there's no message there
+        let claimed = self.claim.0.map(|count| {
+            self.datapage
+                .get()
+                .try_get_framed_as(count, self.size_of_len)
+        });
+        if n > 0 && claimed.is_some_and(|framed| framed.is_ok()) {
+            self.claim.0 = None;
+            skipped += 1;
+        }
+
+        while skipped < n {
+            let datapage = self.datapage.get();
+            let count = datapage.get_group_count(self.group);
+            let want = (n - skipped).min(MAX_MESSAGES_PER_PAGE as usize) as u32;
+
+            let mut end = count;
+            while end - count < want
+                && matches!(
+                    datapage.try_get_framed_as(end, self.size_of_len),
+                    Ok(Some(_))
+                )
+            {
+                end += 1;
+            }
+
+            if end > count {
+                if datapage
+                    .compare_exchange_group_count(self.group, count, end)
+                    .is_ok()
+                {
+                    skipped += (end - count) as usize;
+                }
+
+                continue;
+            }
+
+            match datapage.try_get_framed_as(count, self.size_of_len) {
+                Ok(None) => break,
+                // claimed by another receiver of the group in the meantime
+                Ok(Some(_)) => continue,
+                Err(_end_of_datapage) => {}
+            }
+
+            if !self.try_roll_page()? {
+                break;
+            }
+        }
+
+        Ok(skipped)
+    }
+
+    /// Like `pop` but parks the task instead of the thread while waiting.
+    ///
+    /// Cancellation safe: a message claimed by a dropped `pop_async` is
+    /// returned by the next `pop` or `pop_async` on this receiver. Only
+    /// pushes from this process wake the task.
+    pub async fn pop_async(&mut self) -> Result<&[u8], Error> {
+        poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.pop()
+    }
+
+    /// Ready once `claim` holds a published message this receiver accepts,
+    /// so `pop` returns without blocking.
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        loop {
+            let count = *self
+                .claim
+                .0
+                .get_or_insert_with(|| self.datapage.get().increment_group_count(self.group, 1));
+
+            match self
+                .datapage
+                .get()
+                .try_get_framed_as(count, self.size_of_len)
+            {
+                Ok(Some(framed)) if self.accepts(framed) => return Poll::Ready(Ok(())),
+                Ok(Some(_)) => {
+                    self.claim.0 = None;
+                    continue;
+                }
+                Ok(None) => {
+                    self.manager.register_waker(self.datapage_count, cx.waker());
+
+                    if let Ok(None) = self
+                        .datapage
+                        .get()
+                        .try_get_framed_as(count, self.size_of_len)
+                    {
+                        return Poll::Pending;
+                    }
+
+                    continue;
+                }
+                Err(_end_of_datapage) => {}
+            };
+
+            match self.try_roll_page() {
+                Ok(true) => {}
+                Ok(false) => {
+                    // the first push onto the next page wakes us
+                    self.manager
+                        .register_waker(self.datapage_count.wrapping_add(1), cx.waker());
+
+                    match self.try_roll_page() {
+                        Ok(true) => {}
+                        Ok(false) => return Poll::Pending,
+                        Err(e) => return Poll::Ready(Err(e)),
+                    }
+                }
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    /// Like `pop` but keeps popping until a message `pred` accepts comes
+    /// along, blocking like `pop` while waiting for one.
+    ///
+    /// Rejected messages are consumed like any other, so in a group with
+    /// several consumers they're gone for the whole group, not left for the
+    /// others. `pred` sees the payload as `pop` returns it, and may keep
+    /// state across calls, e.g. to drop duplicates.
+    pub fn pop_filtered<F: FnMut(&[u8]) -> bool>(&mut self, mut pred: F) -> Result<&[u8], Error> {
+        let format = *self.manager.format();
+
+        loop {
+            let count = self
+                .claim_next(usize::MAX, DataPage::get_framed_as)?
+                .expect("unbounded rolls");
+
+            if pred(self.pop()?) {
+                // decoded into `scratch` by the pop above, don't do it twice
+                if format.codec != Codec::None || format.encrypted {
+                    return Ok(&self.scratch);
+                }
+
+                // hand the slot back to ourselves and pop it again, returning
+                // the first pop's slice would keep `self` borrowed across loops
+                self.claim.0 = Some(count);
+                return self.pop();
+            }
+        }
+    }
+
+    /// Like `pop` but also returns the message's global index,
+    /// `page * MAX_MESSAGES_PER_PAGE + count`, the same one `Sender::push_at`
+    /// returned for it.
+    ///
+    /// `count` is the group count *before* this receiver incremented it, i.e.
+    /// the slot it claimed, so the index is one less than `offset` right after.
+    pub fn pop_indexed(&mut self) -> Result<(u64, &[u8]), Error> {
+        let index = self.claim_index()?;
+
+        // picks up the claim made above
+        Ok((index, self.pop()?))
+    }
+
+    /// Like `pop` but also returns where the message is stored, the same
+    /// position `pop_indexed` folds into one number.
+    pub fn pop_located(&mut self) -> Result<(PageLoc, &[u8]), Error> {
+        let count = self
+            .claim_next(usize::MAX, DataPage::get_framed_as)?
+            .expect("unbounded rolls");
+        let loc = PageLoc {
+            page: self.datapage_count,
+            count,
+        };
+
+        // picks up the claim made above
+        Ok((loc, self.pop()?))
+    }
+
+    /// Pops the next message like `pop` and hands it to `f`, returning what
+    /// `f` returns.
+    ///
+    /// The callback style complement to `pop` and `pop_owned`: the message is
+    /// processed in place without copying it out, and since the borrow can't
+    /// escape `f` the receiver is free again for the next call as soon as
+    /// this one returns.
+    pub fn with_next<R, F: FnOnce(&[u8]) -> R>(&mut self, f: F) -> Result<R, Error> {
+        self.pop().map(f)
+    }
+
+    /// Like `pop` but commits the offset past the message, see
+    /// `DataPagesManager::commit`, before returning it: at-most-once delivery.
+    ///
+    /// A crash while the message is being handled loses it, since
+    /// `resume_committed` starts after it. Offsets are per group, so with
+    /// several receivers in the group committing, the last commit wins.
+    pub fn pop_commit_before(&mut self) -> Result<&[u8], Error> {
+        let index = self.claim_index()?;
+        self.manager.commit(self.group, index + 1)?;
+
+        // picks up the claim made above
+        self.pop()
+    }
+
+    /// Like `pop` but leaves committing the offset past the message to `ack`,
+    /// called once the message has been handled: at-least-once delivery.
+    ///
+    /// A crash before `ack` has `resume_committed` deliver the message
+    /// again, along with anything else popped since the last `ack`. Several
+    /// receivers of a group can use this together: an `ack` only commits up
+    /// to the oldest message another receiver of the group in this process
+    /// popped with `pop_commit_after` and hasn't acked yet. Messages claimed
+    /// any other way, or by receivers in other processes, aren't held back.
+    /// Dropping a receiver gives up its hold on the messages it didn't ack.
+    pub fn pop_commit_after(&mut self) -> Result<&[u8], Error> {
+        if self.unacked.0.is_some() {
+            // the hold on the oldest unacked message covers this one too
+            let index = self.claim_index()?;
+            let (_, offset) = self.unacked.0.as_mut().expect("checked above");
+            *offset = index + 1;
+        } else {
+            // held from before the claim, so another receiver's `ack` can't
+            // commit past the message before this one holds it
+            let mut hold = self.manager.hold_ack(self.group, self.next_index_bound());
+            let index = self.claim_index()?;
+            hold.advance(index);
+            self.unacked.0 = Some((hold, index + 1));
+        }
+
+        // picks up the claim made above
+        self.pop()
+    }
+
+    /// Commits the offset past the messages `pop_commit_after` returned since
+    /// the last `ack`, once they've been handled, as far as other receivers
+    /// of the group allow. Does nothing if they were already acked.
+    pub fn ack(&mut self) -> Result<(), Error> {
+        match self.unacked.0.take() {
+            Some((hold, offset)) => self.manager.ack(hold, offset),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `pop` but gives up with `Ok(None)` instead of moving past
+    /// `max_page_rolls` more pages, so `0` only looks at the current page.
+    ///
+    /// Only page rolls are bounded: waiting for a message on a page it's
+    /// allowed to read still blocks like `pop`. Giving up leaves the receiver
+    /// at the end of its page, the next call rolls from there.
+    pub fn pop_bounded(&mut self, max_page_rolls: usize) -> Result<Option<&[u8]>, Error> {
+        if self
+            .claim_next(max_page_rolls, DataPage::get_framed_as)?
+            .is_none()
+        {
+            return Ok(None);
+        }
+
+        // picks up the claim made above
+        self.pop().map(Some)
+    }
+
+    /// Like `pop` but busy waits for the next message instead of sleeping,
+    /// trading a whole core for never waiting on a futex.
+    ///
+    /// Only use it on a core dedicated to this receiver (e.g. isolated and
+    /// pinned): it spins at 100% for as long as the queue is empty. Reaching
+    /// the end of a page still takes the ring lock, waits for the producer to
+    /// create the next page file, and wakes any blocking receivers parked past
+    /// the end of the page, so rolling pages isn't syscall free.
+    pub fn pop_spin(&mut self) -> Result<&[u8], Error> {
+        self.claim_next(usize::MAX, DataPage::get_framed_spin_as)?
+            .expect("unbounded rolls");
+
+        // picks up the claim made above
+        self.pop()
+    }
+
+    fn pop_record(&mut self) -> Result<Stored<'_>, Error> {
+        let count = self
+            .claim_next(usize::MAX, DataPage::get_framed_as)?
+            .expect("unbounded rolls");
+        self.claim.0 = None;
+
+        let framed = self
+            .datapage
+            .get()
+            .try_get_framed_as(count, self.size_of_len)
+            .ok()
+            .flatten()
+            .expect("claimed message is published");
+
+        Ok(Stored {
+            page: self.datapage_count,
+            count,
+            framed,
+        })
+    }
+
+    /// Claims the next message for `pop` to pick up and returns its global
+    /// index, `page * MAX_MESSAGES_PER_PAGE + count`.
+    fn claim_index(&mut self) -> Result<u64, Error> {
+        let count = self
+            .claim_next(usize::MAX, DataPage::get_framed_as)?
+            .expect("unbounded rolls");
+
+        Ok(self.datapage_count as u64 * MAX_MESSAGES_PER_PAGE as u64 + count as u64)
+    }
+
+    /// The lowest global index `claim_index` can return next: that of the
+    /// slot this receiver already claimed, if any, or else its group's
+    /// position.
+    fn next_index_bound(&self) -> u64 {
+        match self.claim.0 {
+            Some(count) => {
+                self.datapage_count as u64 * MAX_MESSAGES_PER_PAGE as u64
+                    + count.min(MAX_MESSAGES_PER_PAGE) as u64
+            }
+            None => self.offset(),
+        }
+    }
+
+    /// Waits with `get_framed` until `claim` holds a published message this
+    /// receiver accepts and returns its count on the current page, or `None`
+    /// once that would take more than `max_page_rolls` page rolls.
+    fn claim_next(
+        &mut self,
+        max_page_rolls: usize,
+        get_framed: fn(&DataPage, u32, usize) -> Result<&[u8], EndOfDataPage>,
+    ) -> Result<Option<u32>, Error> {
+        let mut rolls = 0;
+
+        loop {
+            let count = *self
+                .claim
+                .0
+                .get_or_insert_with(|| self.datapage.get().increment_group_count(self.group, 1));
+
+            match get_framed(self.datapage.get(), count, self.size_of_len) {
+                Ok(data) if self.accepts(data) => return Ok(Some(count)),
+                Ok(_) => {
+                    self.claim.0 = None;
+                    continue;
+                }
+                // WARN: if you add more errors in the future make sure to match on them!!!
+                Err(_e) => {}
+            };
+
+            // the claim past the end is held until `roll_page` drops it
+            if rolls == max_page_rolls {
+                return Ok(None);
+            }
+            rolls += 1;
+
+            self.roll_page()?;
+        }
+    }
+}
+
+impl GenReceiver for Receiver<Grouped> {
+    fn pop(&mut self) -> Result<&[u8], Error> {
+        Ok(self.pop_decoded(Self::pop_record)?.data)
+    }
+
+    fn pop_with_meta(&mut self) -> Result<(Option<SystemTime>, &[u8]), Error> {
+        let record = self.pop_decoded(Self::pop_record)?;
+        Ok((record.timestamp, record.data))
+    }
+
+    fn pop_keyed(&mut self) -> Result<(u64, &[u8]), Error> {
+        if !self.manager.format().keys {
+            return Err(Error::KeysDisabled);
+        }
+
+        let record = self.pop_decoded(Self::pop_record)?;
+        Ok((record.key.expect("keys enabled"), record.data))
+    }
+
+    fn pop_framed_as(&mut self, endian: Endian) -> Result<&[u8], Error> {
+        self.pop_reframed(Self::pop_record, endian)
+    }
+}
+
+impl Receiver<Anonymous> {
+    pub fn new_anon(manager: DataPagesManager) -> Result<Self, Error> {
+        Ok(Receiver::new(0, manager)?.into())
+    }
+
+    /// Like `Receiver::<Grouped>::from_cursor` but for anonymous receivers,
+    /// moving to the cursor's position with `seek`. Takes cursors of grouped
+    /// receivers as well, which then only give the position.
+    pub fn from_cursor(cursor: Cursor, manager: DataPagesManager) -> Result<(Self, Cursor), Error> {
+        let mut rx = Self::new_anon(manager)?;
+        rx.seek(cursor.datapage_count, cursor.count)?;
+
+        let cursor = rx.cursor();
+        Ok((rx, cursor))
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            group: None,
+            datapage_count: self.datapage_count,
+            count: self.anon_count,
+        }
+    }
+
+    /// Moves the receiver so the next `pop` reads message `count` of `page`,
+    /// and returns where it actually ended up.
+    ///
+    /// A page that was already reclaimed is replaced by the oldest retained
+    /// one and a page past the newest by the next page to be written, both
+    /// starting from their first message. That next page is created if it
+    /// doesn't exist yet, or waited for with
+    /// `DataPagesManagerBuilder::consumer_creates_pages` off.
+    pub fn seek(&mut self, page: usize, count: u32) -> Result<(usize, u32), Error> {
+        let (dp_count, datapage) = self.manager.consumer_datapage(page)?;
+        let count = if dp_count == page { count } else { 0 };
+
+        self.datapage_count = dp_count;
+        self.datapage = datapage;
+        self.anon_count = count;
+        self.lifo = 0..0;
+
+        Ok((dp_count, count))
+    }
+
+    /// Durably records this receiver's position under `name`, see
+    /// `DataPagesManager::commit_named`, for `resume` to pick up after a
+    /// restart.
+    ///
+    /// The position is the next message `pop` reads, or the oldest one
+    /// `pop_lifo` took but hasn't returned yet, so those are read again.
+    pub fn commit(&self, name: &str) -> Result<(), Error> {
+        let count = if self.lifo.is_empty() {
+            self.anon_count
+        } else {
+            self.lifo.start
+        };
+        let offset = self.datapage_count as u64 * MAX_MESSAGES_PER_PAGE as u64
+            + count.min(MAX_MESSAGES_PER_PAGE) as u64;
+
+        self.manager.commit_named(name, offset)
+    }
+
+    /// Creates an anonymous receiver at the position last committed under
+    /// `name` with `commit`, or at the start if nothing was.
+    ///
+    /// If the committed page was already reclaimed the receiver starts at the
+    /// oldest retained page instead, like `Receiver::<Grouped>::resume_committed`.
+    pub fn resume(name: &str, manager: DataPagesManager) -> Result<Self, Error> {
+        let offset = manager.committed_named(name)?.unwrap_or(0);
+        let page = (offset / MAX_MESSAGES_PER_PAGE as u64) as usize;
+        let count = (offset % MAX_MESSAGES_PER_PAGE as u64) as u32;
+
+        let mut rx = Self::new_anon(manager)?;
+        rx.seek(page, count)?;
+
+        Ok(rx)
+    }
+
+    /// Like `pop` but parks the task instead of the thread while waiting.
+    ///
+    /// Cancellation safe, and only pushes from this process wake the task.
+    pub async fn pop_async(&mut self) -> Result<&[u8], Error> {
+        poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.pop()
+    }
+
+    /// Ready once the slot at `anon_count` holds a published message this
+    /// receiver accepts, so `pop` returns without blocking.
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        loop {
+            match self
+                .datapage
+                .get()
+                .try_get_framed_as(self.anon_count, self.size_of_len)
+            {
+                Ok(Some(framed)) if self.accepts(framed) => return Poll::Ready(Ok(())),
+                Ok(Some(_)) => {
+                    self.anon_count += 1;
+                    continue;
+                }
+                Ok(None) => {
+                    self.manager.register_waker(self.datapage_count, cx.waker());
+
+                    if let Ok(None) = self
+                        .datapage
+                        .get()
+                        .try_get_framed_as(self.anon_count, self.size_of_len)
+                    {
+                        return Poll::Pending;
+                    }
+
+                    continue;
+                }
+                Err(_end_of_datapage) => {}
+            };
+
+            match self.try_roll_page() {
+                Ok(true) => {}
+                Ok(false) => {
+                    // the first push onto the next page wakes us
+                    self.manager
+                        .register_waker(self.datapage_count.wrapping_add(1), cx.waker());
+
+                    match self.try_roll_page() {
+                        Ok(true) => {}
+                        Ok(false) => return Poll::Pending,
+                        Err(e) => return Poll::Ready(Err(e)),
+                    }
+                }
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    fn pop_record(&mut self) -> Result<Stored<'_>, Error> {
+        loop {
+            let count = self.anon_count;
+            self.anon_count += 1;
+
+            match self.datapage.get().get_framed_as(count, self.size_of_len) {
+                Ok(framed) if self.accepts(framed) => {
+                    return Ok(Stored {
+                        page: self.datapage_count,
+                        count,
+                        framed,
+                    })
+                }
+                Ok(_) => continue,
+                // WARN: if you add more errors in the future make sure to match on them!!!
+                Err(_e) => {}
+            };
+
+            self.roll_page()?;
+        }
+    }
+
+    /// Like `pop` but busy waits for the next message instead of sleeping,
+    /// see `Receiver::<Grouped>::pop_spin`.
+    pub fn pop_spin(&mut self) -> Result<&[u8], Error> {
+        // stop in front of the next accepted message, `pop` then reads it
+        loop {
+            match self
+                .datapage
+                .get()
+                .get_framed_spin_as(self.anon_count, self.size_of_len)
+            {
+                Ok(data) if self.accepts(data) => return self.pop(),
+                Ok(_) => {
+                    self.anon_count += 1;
+                    continue;
+                }
+                Err(_end_of_datapage) => {}
+            };
+
+            self.roll_page()?;
+        }
+    }
+
+    /// Like `pop` but gives up with `Ok(None)` instead of moving past
+    /// `max_page_rolls` more pages, so `0` only looks at the current page.
+    ///
+    /// Only page rolls are bounded: waiting for a message on a page it's
+    /// allowed to read still blocks like `pop`.
+    pub fn pop_bounded(&mut self, max_page_rolls: usize) -> Result<Option<&[u8]>, Error> {
+        let mut rolls = 0;
+
+        // stop in front of the next accepted message, `pop` then reads it
+        loop {
+            match self
+                .datapage
+                .get()
+                .get_framed_as(self.anon_count, self.size_of_len)
+            {
+                Ok(data) if self.accepts(data) => return self.pop().map(Some),
+                Ok(_) => {
+                    self.anon_count += 1;
+                    continue;
+                }
+                Err(_end_of_datapage) => {}
+            };
+
+            if rolls == max_page_rolls {
+                return Ok(None);
+            }
+            rolls += 1;
+
+            self.roll_page()?;
+        }
+    }
+
+    /// Non-blocking `pop` that returns the newest unread message on the
+    /// current page first, for handling the most recent messages first.
+    ///
+    /// LIFO within a page, FIFO across pages: once every message published on
+    /// the current page so far was returned it looks for newer ones there,
+    /// and only moves on to the next page once the current one is finished.
+    /// Messages published while it walks back down come after the ones it's
+    /// on. Returns `Ok(None)` when caught up with the producers.
+    ///
+    /// Shares its position with `pop`: messages `pop_lifo` has seen but not
+    /// returned yet are dropped once `pop` or `seek` moves to another page.
+    pub fn pop_lifo(&mut self) -> Result<Option<&[u8]>, Error> {
+        if !self.lifo_ready()? {
+            return Ok(None);
+        }
+
+        self.pop_decoded(Self::pop_lifo_record)
+            .map(|record| Some(record.data))
+    }
+
+    /// Makes sure the end of `lifo` is a published message this receiver
+    /// accepts, taking the messages published since `anon_count` once it's
+    /// used up.
+    fn lifo_ready(&mut self) -> Result<bool, Error> {
+        loop {
+            let datapage = self.datapage.get();
+
+            while let Some(count) = self.lifo.clone().next_back() {
+                match datapage.get_framed_as(count, self.size_of_len) {
+                    Ok(framed) if !self.accepts(framed) => self.lifo.end -= 1,
+                    _ => return Ok(true),
+                }
+            }
+
+            // only ever take a run of published messages, so nothing in
+            // `lifo` has to be waited for
+            let start = self.anon_count;
+            let mut end = start;
+            let finished = loop {
+                match datapage.try_get_framed_as(end, self.size_of_len) {
+                    Ok(Some(_)) => end += 1,
+                    Ok(None) => break false,
+                    Err(_end_of_datapage) => break true,
+                }
+            };
+
+            if end > start {
+                self.lifo = start..end;
+                self.anon_count = end;
+                continue;
+            }
+
+            if !finished || !self.try_roll_page()? {
+                return Ok(false);
+            }
+        }
+    }
+
+    fn pop_lifo_record(&mut self) -> Result<Stored<'_>, Error> {
+        let count = self.lifo.next_back().expect("lifo_ready found a message");
+        let framed = self
+            .datapage
+            .get()
+            .get_framed_as(count, self.size_of_len)
+            .expect("lifo only holds published messages");
+
+        Ok(Stored {
+            page: self.datapage_count,
+            count,
+            framed,
+        })
+    }
+
+    /// Like `pop` but gives up with `Ok(None)` once `deadline` has passed,
+    /// moving on to later pages while there's time left. Giving up leaves the
+    /// receiver at the slot it was waiting on, so the next call picks up
+    /// from there.
+    pub fn pop_until(&mut self, deadline: Instant) -> Result<Option<&[u8]>, Error> {
+        if self.ready_until(deadline)? {
+            self.pop().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Moves up to the next published message this receiver accepts, waiting
+    /// for it until `deadline`, so `pop` returns it straight away.
+    fn ready_until(&mut self, deadline: Instant) -> Result<bool, Error> {
+        loop {
+            match self.datapage.get().get_framed_until_as(
+                self.anon_count,
+                deadline,
+                self.size_of_len,
+            ) {
+                Ok(Some(framed)) if self.accepts(framed) => return Ok(true),
+                Ok(Some(_)) => {
+                    self.anon_count += 1;
+                    continue;
+                }
+                Ok(None) => return Ok(false),
+                Err(_end_of_datapage) => {}
+            };
+
+            if !self.roll_page_until(deadline)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Non-blocking `pop`. Returns `Ok(None)` when caught up with the producers,
+    /// leaving the receiver where it is so the next call retries the same slot.
+    ///
+    /// Only moves on to the next page once the current one is known to be
+    /// finished, never just because nothing has been published yet.
+    pub fn try_pop(&mut self) -> Result<Option<&[u8]>, Error> {
+        let format = *self.manager.format();
+
+        loop {
+            let count = self.anon_count;
+
+            match self
+                .datapage
+                .get()
+                .try_get_framed_as(count, self.size_of_len)
+            {
+                Ok(Some(framed)) => {
+                    self.anon_count += 1;
+
+                    if !self.accepts(framed) {
+                        continue;
+                    }
+
+                    if format.codec == Codec::None && !format.encrypted {
+                        return Ok(Some(
+                            Record::parse(&format, strip_frame(&format, framed)).data,
+                        ));
+                    }
+
+                    let stored = Stored {
+                        page: self.datapage_count,
+                        count,
+                        framed,
+                    };
+                    stored.decode_payload(
+                        &format,
+                        self.manager.cipher(),
+                        &mut self.scratch,
+                        &mut self.opened,
+                    )?;
+                    return Ok(Some(&self.scratch));
+                }
+                Ok(None) => return Ok(None),
+                Err(_end_of_datapage) => {}
+            };
+
+            if !self.try_roll_page()? {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+impl GenReceiver for Receiver<Anonymous> {
+    fn pop(&mut self) -> Result<&[u8], Error> {
+        Ok(self.pop_decoded(Self::pop_record)?.data)
+    }
+
+    fn pop_with_meta(&mut self) -> Result<(Option<SystemTime>, &[u8]), Error> {
+        let record = self.pop_decoded(Self::pop_record)?;
+        Ok((record.timestamp, record.data))
+    }
+
+    fn pop_keyed(&mut self) -> Result<(u64, &[u8]), Error> {
+        if !self.manager.format().keys {
+            return Err(Error::KeysDisabled);
+        }
+
+        let record = self.pop_decoded(Self::pop_record)?;
+        Ok((record.key.expect("keys enabled"), record.data))
+    }
+
+    fn pop_framed_as(&mut self, endian: Endian) -> Result<&[u8], Error> {
+        self.pop_reframed(Self::pop_record, endian)
+    }
+}
+
+impl From<Receiver<Grouped>> for Receiver<Anonymous> {
+    fn from(value: Receiver<Grouped>) -> Self {
+        Receiver {
+            group: 0,
+            anon_count: 0,
+            manager: value.manager,
+            datapage_count: value.datapage_count,
+            datapage: value.datapage,
+            size_of_len: value.size_of_len,
+            partitions: value.partitions,
+            scratch: value.scratch,
+            opened: value.opened,
+            claim: Claim::default(),
+            lifo: 0..0,
+            unacked: Unacked::default(),
+            _consumer: None,
+            _type: PhantomData,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Sender {
+    manager: DataPagesManager,
+    datapage_count: usize,
+    datapage: Arc<MmapCell<DataPage>>,
+    scratch: Vec<u8>,
+    flush_every: u32,
+    /// Pushes since the last flush, see `flush_every`.
+    unflushed: u32,
+}
+
+impl std::fmt::Debug for Sender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("datapage_count", &self.datapage_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sender {
+    pub fn new(manager: DataPagesManager) -> Result<Self, Error> {
+        let (datapage_count, datapage) = manager.get_or_create_datapage(0)?;
+        //let datapage_count = RefCell::new(datapage_count);
+        //let datapage = RefCell::new(datapage);
+
+        Ok(Sender {
+            manager,
+            datapage_count,
+            datapage,
+            scratch: Vec::new(),
+            flush_every: 0,
+            unflushed: 0,
+        })
+    }
+
+    /// Flush the current page to disk (see `flush`) after every `n` messages
+    /// pushed with `push` and its variants, and whenever the sender moves on
+    /// to a new page. `0`, the default, never flushes on its own.
+    ///
+    /// Bounds what a crash of the machine can lose to the last `n` messages
+    /// of this sender, at the cost of a synchronous write every `n` pushes.
+    /// The count is per sender, each clone counts its own pushes, and
+    /// `try_push` isn't counted so it stays off the disk. An error from the
+    /// flush is returned by the push that triggered it, after its message was
+    /// pushed.
+    pub fn flush_every(mut self, n: u32) -> Self {
+        self.flush_every = n;
+        self
+    }
+
+    /// Writes the current page back to disk, waiting until it's durable.
+    ///
+    /// Messages are readable as soon as they're pushed, this only matters for
+    /// surviving a crash of the machine rather than of the process.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.unflushed = 0;
+        self.manager.sync(self.datapage_count, &self.datapage)
+    }
+
+    /// Flushes the current page before the sender leaves it, if it's set to
+    /// flush on its own.
+    fn flush_before_roll(&mut self) -> Result<(), Error> {
+        if self.unflushed > 0 {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Messages and bytes left on the sender's current page before it rolls,
+    /// e.g. to start a batch of messages on a fresh page rather than split
+    /// it across two.
+    ///
+    /// Bytes include each message's length prefix (and timestamp and key if
+    /// the queue has them), and a message only fits if it leaves at least one
+    /// byte over. Only a snapshot: other senders on the same queue keep
+    /// taking room from the page, so the next push may still roll. Once the
+    /// page is full, or another sender has rolled past it, both are 0 until
+    /// this sender pushes again.
+    pub fn remaining_on_page(&self) -> (u32, u32) {
+        let datapage = self.datapage.get();
+        if datapage.is_full() {
+            return (0, 0);
+        }
+
+        let (count, write_idx) = datapage.usage();
+
+        (
+            MAX_MESSAGES_PER_PAGE - count,
+            MAX_BYTES_PER_PAGE - write_idx,
+        )
+    }
+
+    /// Pushes a message. On queues with keys enabled it gets key 0.
+    pub fn push<T: AsRef<[u8]>>(&mut self, data: T) -> Result<(), Error> {
+        self.push_record(0, None, data.as_ref()).map(drop)
+    }
+
+    /// Like `push` but returns the message's global index,
+    /// `page * MAX_MESSAGES_PER_PAGE + count`, see `Receiver::pop_indexed`.
+    pub fn push_at<T: AsRef<[u8]>>(&mut self, data: T) -> Result<u64, Error> {
+        self.push_record(0, None, data.as_ref())
+    }
+
+    /// Pushes a message with `key`, see `Receiver::with_partitions`.
+    ///
+    /// Only available on queues built with keys enabled.
+    pub fn push_keyed<T: AsRef<[u8]>>(&mut self, key: u64, data: T) -> Result<(), Error> {
+        if !self.manager.format().keys {
+            return Err(Error::KeysDisabled);
+        }
+
+        self.push_record(key, None, data.as_ref()).map(drop)
+    }
+
+    /// Pushes `data` with `key` and `timestamp`, in nanoseconds since the
+    /// Unix epoch, where the queue stores them. `None` stamps it with the
+    /// current time.
+    fn push_record(&mut self, key: u64, timestamp: Option<u64>, data: &[u8]) -> Result<u64, Error> {
+        loop {
+            match self.try_push_record(key, timestamp, data) {
+                Ok(count) => {
+                    if self.flush_every > 0 {
+                        self.unflushed += 1;
+                        if self.unflushed >= self.flush_every {
+                            self.flush()?;
+                        }
+                    }
+
+                    return Ok(
+                        self.datapage_count as u64 * MAX_MESSAGES_PER_PAGE as u64 + count as u64
+                    );
+                }
+                Err(TryPushError::PageFull) => {}
+                Err(TryPushError::TooLarge(len)) => return Err(Error::MessageTooLarge(len)),
+                Err(TryPushError::Closed) => return Err(Error::Closed),
+            }
+
+            // the failed push already released readers parked on this page,
+            // through the page full marker or, out of slots, by there being
+            // nothing past the last one to wait on
+            self.flush_before_roll()?;
+            let (dp_count, datapage) = self
+                .manager
+                .get_or_create_datapage(self.datapage_count.wrapping_add(1))?;
+
+            self.datapage_count = dp_count;
+            self.datapage = datapage;
+        }
+    }
+
+    /// Pushes a message that's already framed as a little-endian `u32` length
+    /// followed by the payload, e.g. straight from a
+    /// `tokio_util::codec::LengthDelimitedCodec` built with `.little_endian()`.
+    ///
+    /// Fails with `InvalidFrame` if the length prefix doesn't match the frame.
+    /// Not available on queues built with timestamps, keys, a codec or
+    /// encryption.
+    pub fn push_framed(&mut self, framed: &[u8]) -> Result<(), Error> {
+        self.push_framed_as(framed, Endian::Little)
+    }
+
+    /// Like `push_framed` but with the length prefix in `endian` byte order,
+    /// `Endian::Big` for a default `LengthDelimitedCodec`.
+    pub fn push_framed_as(&mut self, framed: &[u8], endian: Endian) -> Result<(), Error> {
+        if !self.manager.format().is_plain() {
+            return Err(Error::MetadataEnabled);
+        }
+
+        let Some((len, data)) = framed.split_first_chunk::<{ DataPage::SIZE_OF_LEN }>() else {
+            return Err(Error::InvalidFrame {
+                expected: DataPage::SIZE_OF_LEN,
+                found: framed.len(),
+            });
+        };
+
+        let expected = endian.len_from_bytes(*len) as usize;
+        if expected != data.len() {
+            return Err(Error::InvalidFrame {
+                expected,
+                found: data.len(),
+            });
+        }
+
+        self.push(data)
+    }
+
+    /// Pushes `items` in order, moving on to new pages as needed, and flushes
+    /// them to disk: each page when the sender leaves it and the last one
+    /// once at the end, rather than after every message.
+    ///
+    /// Unlike `try_push_batch` it doesn't stop at the end of the current
+    /// page. Not atomic either: on failure `PushAllError::pushed` says how
+    /// many went in, so calling it again with the rest picks up where it
+    /// stopped. Those may not be flushed yet, the retry (or `flush`) takes
+    /// care of that.
+    pub fn push_all<T: AsRef<[u8]>>(&mut self, items: &[T]) -> Result<(), PushAllError> {
+        // counts every push, so leaving a page flushes it
+        let flush_every = std::mem::replace(&mut self.flush_every, u32::MAX);
+
+        let pushed = items
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, item)| self.push(item).map_err(|error| (i, error)))
+            .and_then(|()| self.flush().map_err(|error| (items.len(), error)));
+
+        self.flush_every = flush_every;
+        pushed.map_err(|(pushed, error)| PushAllError { pushed, error })
+    }
+
+    /// Pushes onto the current page only, never creating or mapping a new one.
+    ///
+    /// Once this returns `PageFull` every further call does too, until
+    /// `ensure_capacity` moves the sender onto the next page.
+    pub fn try_push<T: AsRef<[u8]>>(&mut self, data: T) -> Result<(), TryPushError> {
+        self.try_push_record(0, None, data.as_ref()).map(drop)
+    }
+
+    /// Pushes `items` in order with `try_push` until the current page is full,
+    /// returning how many made it. The rest are left to the caller, e.g. for
+    /// the next page after `ensure_capacity`, so batches can be lined up with
+    /// page boundaries.
+    ///
+    /// Not atomic: on an error the items before the failing one are already
+    /// pushed.
+    pub fn try_push_batch<T: AsRef<[u8]>>(&mut self, items: &[T]) -> Result<usize, Error> {
+        for (pushed, item) in items.iter().enumerate() {
+            match self.try_push(item) {
+                Ok(()) => {}
+                Err(TryPushError::PageFull) => return Ok(pushed),
+                Err(TryPushError::TooLarge(len)) => return Err(Error::MessageTooLarge(len)),
+                Err(TryPushError::Closed) => return Err(Error::Closed),
+            }
+        }
+
+        Ok(items.len())
+    }
+
+    fn try_push_record(
+        &mut self,
+        key: u64,
+        timestamp: Option<u64>,
+        data: &[u8],
+    ) -> Result<u32, TryPushError> {
+        if self.manager.is_closed() {
+            return Err(TryPushError::Closed);
+        }
+
+        let format = self.manager.format();
+        let payload_len = data.len();
+
+        let data = if format.codec != Codec::None {
+            format.codec.compress(data, &mut self.scratch);
+            &self.scratch
+        } else {
+            data
+        };
+
+        let key = key.to_le_bytes();
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp,
+            None if format.timestamps => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            None => 0,
+        };
+        let timestamp = timestamp.to_le_bytes();
+
+        let mut parts: [&[u8]; 4] = [&[]; 4];
+        let mut len = 0;
+
+        if format.timestamps {
+            parts[len] = &timestamp;
+            len += 1;
+        }
+
+        if format.keys {
+            parts[len] = &key;
+            len += 1;
+        }
+
+        let aad_len = parts[..len].iter().map(|p| p.len()).sum();
+
+        parts[len] = data;
+        len += 1;
+
+        // room for the tag, filled in once the message is sealed
+        if format.encrypted {
+            parts[len] = &[0; TAG_SIZE];
+            len += 1;
+        }
+
+        // a full page is the only other reason a push fails, so rolling would
+        // just leave a trail of empty pages behind
+        let record_len = parts[..len].iter().map(|p| p.len()).sum();
+        if record_len > format.max_message_size() {
+            return Err(TryPushError::TooLarge(record_len));
+        }
+
+        let pushed = if format.encrypted {
+            let (page, cipher) = (self.datapage_count, self.manager.cipher());
+
+            self.datapage.get_mut().push_parts_with(
+                &parts[..len],
+                format.short_lengths,
+                |count, record| cipher.seal(page, count, aad_len, record),
+            )
+        } else {
+            self.datapage.get_mut().push_parts_with(
+                &parts[..len],
+                format.short_lengths,
+                |_count, _record| {},
+            )
+        };
+
+        // also on failure, the page full marker lets waiting readers move on
+        self.manager.notify_async(self.datapage_count);
+
+        if pushed.is_ok() {
+            self.manager.record_size(payload_len);
+        }
+
+        pushed.map_err(|_| TryPushError::PageFull)
+    }
+
+    /// Checks that the next page could be created right now: that the data
+    /// directory takes new files and, on Linux, has room for a whole page
+    /// (`Error::DiskFull` if not).
+    ///
+    /// Advisory only, meant for alerting before a `push` fails: space can
+    /// still run out between the check and the page actually being created.
+    /// Stays off the push path, so call it from a health check or timer.
+    pub fn check(&self) -> Result<(), Error> {
+        self.manager.check_next_page()
+    }
+
+    /// Makes sure the page after the current one exists, creating it if needed,
+    /// and moves onto it if the current page is full.
+    ///
+    /// Lets the expensive file creation happen off the `try_push` hot path,
+    /// e.g. from a cloned sender on a background thread.
+    pub fn ensure_capacity(&mut self) -> Result<(), Error> {
+        let (dp_count, datapage) = self
+            .manager
+            .get_or_create_datapage(self.datapage_count.wrapping_add(1))?;
+
+        if self.datapage.get().is_full() {
+            self.flush_before_roll()?;
+            self.datapage_count = dp_count;
+            self.datapage = datapage;
+        }
+
+        Ok(())
+    }
+}
+
+// TODO: Move these out to a test dir
+// they take up wayyyy too much space
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Barrier,
+        },
+        thread,
+    };
+
+    use tracing::info;
+
+    use super::*;
+    use crate::{
+        datapage::{MAX_BYTES_PER_PAGE, MAX_MESSAGE_SIZE, MAX_SHORT_MESSAGE_SIZE},
+        test_util::{block_on, mkdir_random},
+    };
+
+    #[test]
+    fn group_consumer_count() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+
+        let rx = Receiver::new(3, manager.clone()).unwrap();
+        let rx_clone = rx.clone();
+        assert_eq!(manager.group_consumer_count(3), 2);
+        assert_eq!(manager.group_consumer_count(0), 0);
+
+        let anon: Receiver<Anonymous> = rx_clone.into();
+        assert_eq!(manager.group_consumer_count(3), 1);
+
+        drop(rx);
+        drop(anon);
+        assert_eq!(manager.group_consumer_count(3), 0);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn debug_output() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let rx = Receiver::new(3, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+        tx.push("msg").unwrap();
+        anon.pop().unwrap();
+
+        assert_eq!(
+            format!("{manager:?}"),
+            format!(
+                "DataPagesManager {{ path: {path:?}, datapage_count: 0, \
+                max_datapages: {}, ring_len: 1, .. }}",
+                usize::MAX
+            )
+        );
+        assert_eq!(format!("{tx:?}"), "Sender { datapage_count: 0, .. }");
+        assert_eq!(
+            format!("{rx:?}"),
+            "Receiver { group: 3, datapage_count: 0, .. }"
+        );
+        assert_eq!(
+            format!("{anon:?}"),
+            "Receiver { datapage_count: 0, anon_count: 1, .. }"
+        );
+
+        // reclaim hooks run with the ring locked
+        let printed = Arc::new(parking_lot::Mutex::new(String::new()));
+        let (hook_manager, hook_printed) = (manager.clone(), printed.clone());
+        manager.on_reclaim(move |_, _| *hook_printed.lock() = format!("{hook_manager:?}"));
+        manager.set_max_datapages(1);
+        for _ in 0..MAX_MESSAGES_PER_PAGE {
+            tx.push("msg").unwrap();
+        }
+        assert!(printed.lock().contains("ring_len: <locked>"));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn work_sharing_group() {
+        const TOTAL_MESSAGES: u64 = 200_000;
+        const NUM_CONSUMERS: usize = 4;
+        const STOP: &[u8] = b"stop";
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let rx = Receiver::new(0, manager.clone()).unwrap();
+        let finished = Arc::new(AtomicUsize::new(0));
+
+        let mut handles = Vec::new();
+        for _ in 0..NUM_CONSUMERS {
+            let mut rx_clone = rx.clone();
+            let finished_clone = finished.clone();
+
+            handles.push(thread::spawn(move || {
+                let mut seen = Vec::new();
+
+                loop {
+                    let msg = rx_clone.pop().unwrap();
+                    if msg == STOP {
+                        break;
+                    }
+
+                    seen.push(u64::from_le_bytes(msg.try_into().unwrap()));
+                }
+
+                finished_clone.fetch_add(1, Ordering::Relaxed);
+                seen
+            }));
+        }
+
+        let mut tx = Sender::new(manager).unwrap();
+        for i in 0..TOTAL_MESSAGES {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        // keep handing out stop messages until every consumer has claimed one
+        while finished.load(Ordering::Relaxed) < NUM_CONSUMERS {
+            tx.push(STOP).unwrap();
+            thread::yield_now();
+        }
+
+        let mut all: Vec<u64> = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+        all.sort_unstable();
+
+        assert_eq!(all, (0..TOTAL_MESSAGES).collect::<Vec<_>>());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn push_wakes_one_group_consumer() {
+        const NUM_CONSUMERS: usize = 8;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let rx = Receiver::new(0, manager.clone()).unwrap();
+        let popped = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..NUM_CONSUMERS)
+            .map(|_| {
+                let mut rx = rx.clone();
+                let popped = popped.clone();
+
+                thread::spawn(move || {
+                    rx.pop().unwrap();
+                    popped.fetch_add(1, Ordering::Relaxed);
+                })
+            })
+            .collect();
+
+        // each consumer parks on the slot it claimed, not on a shared one
+        while rx.stats().consumed_count < NUM_CONSUMERS as u32 {
+            thread::yield_now();
+        }
+        thread::sleep(std::time::Duration::from_millis(50));
+
+        let mut tx = Sender::new(manager).unwrap();
+        for pushed in 1..=NUM_CONSUMERS {
+            tx.push("one").unwrap();
+            while popped.load(Ordering::Relaxed) < pushed {
+                thread::yield_now();
+            }
+
+            // and nobody else got anything
+            thread::sleep(std::time::Duration::from_millis(20));
+            assert_eq!(popped.load(Ordering::Relaxed), pushed);
+        }
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn parked_consumers_follow_byte_limit_roll() {
+        const MSG_SIZE: usize = 16 << 20;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+
+        // the page runs out of bytes with most of its slots unused
+        let fitting = (MAX_BYTES_PER_PAGE - 1) / (MSG_SIZE + DataPage::SIZE_OF_LEN) as u32;
+        assert!(fitting < MAX_MESSAGES_PER_PAGE / 2);
+
+        let msg = vec![7; MSG_SIZE];
+        for _ in 0..fitting {
+            tx.push(&msg).unwrap();
+        }
+
+        let grouped = thread::spawn(move || {
+            for _ in 0..fitting {
+                rx.pop().unwrap();
+            }
+            rx.pop().unwrap().to_vec()
+        });
+        let anonymous = thread::spawn(move || {
+            for _ in 0..fitting {
+                anon.pop().unwrap();
+            }
+            anon.pop().unwrap().to_vec()
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(!grouped.is_finished() && !anonymous.is_finished());
+
+        let next = vec![8; MSG_SIZE];
+        tx.push(&next).unwrap();
+        assert_eq!(tx.datapage_count, 1);
+        assert!(grouped.join().unwrap() == next);
+        assert!(anonymous.join().unwrap() == next);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn receivers_past_page_end_marker_move_on() {
+        const MSG_SIZE: usize = 16 << 20;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+
+        // placed past where the page will run out of bytes, so no receiver
+        // reads the marker and passes it on to them
+        let fitting = (MAX_BYTES_PER_PAGE - 1) / (MSG_SIZE + DataPage::SIZE_OF_LEN) as u32;
+        let past_end = Cursor {
+            group: Some(0),
+            datapage_count: 0,
+            count: fitting + 5,
+        };
+        let (mut rx, _) = Receiver::<Grouped>::from_cursor(past_end, manager.clone()).unwrap();
+        let (mut anon, _) = Receiver::<Anonymous>::from_cursor(past_end, manager.clone()).unwrap();
+
+        let grouped = thread::spawn(move || rx.pop().unwrap().to_vec());
+        let anonymous = thread::spawn(move || anon.pop().unwrap().to_vec());
+        thread::sleep(Duration::from_millis(100));
+        assert!(!grouped.is_finished() && !anonymous.is_finished());
+
+        let msg = vec![7; MSG_SIZE];
+        for _ in 0..fitting {
+            tx.push(&msg).unwrap();
+        }
+        let next = vec![8; MSG_SIZE];
+        tx.push(&next).unwrap();
+        assert_eq!(tx.datapage_count, 1);
+
+        assert!(grouped.join().unwrap() == next);
+        assert!(anonymous.join().unwrap() == next);
+
+        // and so do receivers placed there after the fact
+        let (mut rx, _) = Receiver::<Grouped>::from_cursor(
+            Cursor {
+                group: Some(1),
+                ..past_end
+            },
+            manager.clone(),
+        )
+        .unwrap();
+        assert!(rx.try_pop().unwrap() == Some(&next[..]));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn broadcast_groups() {
+        const TOTAL_MESSAGES: u32 = 2 * MAX_MESSAGES_PER_PAGE + 10;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+
+        let handles: Vec<_> = (0..2)
+            .map(|group| {
+                let mut rx = Receiver::new(group, manager.clone()).unwrap();
+
+                thread::spawn(move || {
+                    (0..TOTAL_MESSAGES)
+                        .map(|_| u32::from_le_bytes(rx.pop().unwrap().try_into().unwrap()))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..TOTAL_MESSAGES {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        for handle in handles {
+            assert_eq!(
+                handle.join().unwrap(),
+                (0..TOTAL_MESSAGES).collect::<Vec<_>>()
+            );
+        }
+
+        // a group that only joins now still starts every page from its first message
+        let mut late = Receiver::new(2, manager).unwrap();
+        late.skip(MAX_MESSAGES_PER_PAGE as usize).unwrap();
+        assert_eq!(late.pop().unwrap(), MAX_MESSAGES_PER_PAGE.to_le_bytes());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn empty_queue() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+
+        assert!(manager.is_empty());
+        assert!(Receiver::new_if_nonempty(0, manager.clone())
+            .unwrap()
+            .is_none());
+        assert_eq!(manager.group_consumer_count(0), 0);
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        tx.push("first").unwrap();
+        assert!(!manager.is_empty());
+
+        let mut rx = Receiver::new_if_nonempty(0, manager.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(rx.pop().unwrap(), b"first");
+        // consumed isn't empty, the message is still retained
+        assert!(!manager.is_empty());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(3);
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE + 10 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        for _ in 0..MAX_MESSAGES_PER_PAGE + 3 {
+            rx.pop().unwrap();
+        }
+        let cursor = rx.cursor();
+        assert_eq!(
+            cursor,
+            Cursor {
+                group: Some(0),
+                datapage_count: 1,
+                count: 3
+            }
+        );
+        assert_eq!(Cursor::from_bytes(cursor.to_bytes()), cursor);
+        drop(rx);
+
+        let (mut rx, resumed) = Receiver::<Grouped>::from_cursor(cursor, manager.clone()).unwrap();
+        assert_eq!(resumed, cursor);
+        assert_eq!(rx.pop().unwrap(), (MAX_MESSAGES_PER_PAGE + 3).to_le_bytes());
+
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+        anon.seek(1, 7).unwrap();
+        let anon_cursor = anon.cursor();
+        assert_eq!(anon_cursor.group, None);
+        assert_eq!(Cursor::from_bytes(anon_cursor.to_bytes()), anon_cursor);
+
+        let (mut anon, resumed) =
+            Receiver::<Anonymous>::from_cursor(anon_cursor, manager.clone()).unwrap();
+        assert_eq!(resumed, anon_cursor);
+        assert_eq!(
+            anon.pop().unwrap(),
+            (MAX_MESSAGES_PER_PAGE + 7).to_le_bytes()
+        );
+        assert!(matches!(
+            Receiver::<Grouped>::from_cursor(anon_cursor, manager.clone()),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        // once page 0 is gone both clamp forward to page 1
+        manager.set_max_datapages(2);
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+        let stale = Cursor {
+            group: Some(1),
+            datapage_count: 0,
+            count: 5,
+        };
+        let (mut rx, resumed) = Receiver::<Grouped>::from_cursor(stale, manager.clone()).unwrap();
+        assert_eq!((resumed.datapage_count, resumed.count), (1, 0));
+        assert_eq!(rx.pop().unwrap(), MAX_MESSAGES_PER_PAGE.to_le_bytes());
+
+        let (_, resumed) = Receiver::<Anonymous>::from_cursor(stale, manager).unwrap();
+        assert_eq!((resumed.datapage_count, resumed.count), (1, 0));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn anon_seek() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(2);
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new_anon(manager.clone()).unwrap();
+
+        for i in 0..MAX_MESSAGES_PER_PAGE + 5 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        assert_eq!(rx.seek(1, 2).unwrap(), (1, 2));
+        assert_eq!(rx.pop().unwrap(), (MAX_MESSAGES_PER_PAGE + 2).to_le_bytes());
+        assert_eq!(rx.seek(0, 10).unwrap(), (0, 10));
+        assert_eq!(rx.pop().unwrap(), 10_u32.to_le_bytes());
+
+        // page 0 gets reclaimed once page 2 is created
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        assert_eq!(rx.seek(0, 10).unwrap(), (1, 0));
+        assert_eq!(rx.pop().unwrap(), MAX_MESSAGES_PER_PAGE.to_le_bytes());
+        assert_eq!(rx.seek(9, 10).unwrap(), (3, 0));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn anon_try_pop_tail() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new_anon(manager.clone()).unwrap();
+
+        assert_eq!(rx.try_pop().unwrap(), None);
+        assert_eq!(rx.datapage_count, 0);
+
+        // fill the first page exactly so the tail sits right at the page boundary
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            assert_eq!(rx.try_pop().unwrap(), Some(&i.to_le_bytes()[..]));
+        }
+        // the first page is used up but the next one is the producer's to create
+        assert_eq!(rx.try_pop().unwrap(), None);
+        assert_eq!(rx.datapage_count, 0);
+        assert!(!manager.page_exists(1));
+
+        tx.push(b"tick").unwrap();
+        assert_eq!(rx.try_pop().unwrap(), Some(&b"tick"[..]));
+        assert_eq!(rx.try_pop().unwrap(), None);
+        assert_eq!(rx.datapage_count, 1);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn pop_lifo() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new_anon(manager).unwrap();
+        let mut pop_lifo = || {
+            rx.pop_lifo()
+                .unwrap()
+                .map(|m| u32::from_le_bytes(m.try_into().unwrap()))
+        };
+
+        assert_eq!(pop_lifo(), None);
+        for i in 0..5_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(pop_lifo(), Some(4));
+        assert_eq!(pop_lifo(), Some(3));
+
+        // newer messages wait until the older ones are done
+        tx.push(5_u32.to_le_bytes()).unwrap();
+        tx.push(6_u32.to_le_bytes()).unwrap();
+        let rest: Vec<_> = std::iter::from_fn(&mut pop_lifo).collect();
+        assert_eq!(rest, [2, 1, 0, 6, 5]);
+
+        // and pages are still taken in order
+        for i in 7..MAX_MESSAGES_PER_PAGE + 2 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+        let rest: Vec<_> = std::iter::from_fn(&mut pop_lifo).collect();
+        let mut expected: Vec<_> = (7..MAX_MESSAGES_PER_PAGE).rev().collect();
+        expected.extend([MAX_MESSAGES_PER_PAGE + 1, MAX_MESSAGES_PER_PAGE]);
+        assert_eq!(rest, expected);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn framed_messages() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        tx.push("hello").unwrap();
+        tx.push_framed(&[0, 0, 0, 0]).unwrap();
+        tx.push_framed(b"\x03\0\0\0abc").unwrap();
+
+        assert!(matches!(
+            tx.push_framed(b"\x05\0\0\0abc"),
+            Err(Error::InvalidFrame {
+                expected: 5,
+                found: 3
+            })
+        ));
+        assert!(matches!(
+            tx.push_framed(b"\x05\0"),
+            Err(Error::InvalidFrame { .. })
+        ));
+
+        assert_eq!(rx.pop_framed().unwrap(), b"\x05\0\0\0hello");
+        assert_eq!(rx.pop_framed().unwrap(), [0, 0, 0, 0]);
+        assert_eq!(rx.pop().unwrap(), b"abc");
+
+        // big-endian frames are rewritten on the way in and out
+        tx.push_framed_as(b"\0\0\0\x02hi", Endian::Big).unwrap();
+        assert!(matches!(
+            tx.push_framed_as(b"\x02\0\0\0hi", Endian::Big),
+            Err(Error::InvalidFrame { .. })
+        ));
+        tx.push("hey").unwrap();
+
+        assert_eq!(rx.pop_framed().unwrap(), b"\x02\0\0\0hi");
+        assert_eq!(rx.pop_framed_as(Endian::Big).unwrap(), b"\0\0\0\x03hey");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn pop_across_reclamation() {
+        const STOP: &[u8] = b"stop";
+        const TOTAL_MESSAGES: u32 = MAX_MESSAGES_PER_PAGE * 8;
+
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(2);
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        tx.push(TOTAL_MESSAGES.to_le_bytes()).unwrap();
+        let borrowed = rx.pop().unwrap();
+
+        // reclaim the page the borrowed slice lives on while it's still held
+        let mut tx_clone = tx.clone();
+        thread::spawn(move || {
+            for i in 0..MAX_MESSAGES_PER_PAGE * 3 {
+                tx_clone.push(i.to_le_bytes()).unwrap();
+            }
+        })
+        .join()
+        .unwrap();
+
+        assert!(!path.join(".dp.data.maxi.0").exists());
+        assert_eq!(borrowed, TOTAL_MESSAGES.to_le_bytes());
+
+        // and keep reclaiming under a consumer holding owned copies
+        let mut rx_clone = rx.clone();
+        let consumer = thread::spawn(move || {
+            let mut owned = Vec::new();
+
+            loop {
+                let msg = rx_clone.pop_owned().unwrap();
+                if msg == STOP {
+                    break owned;
+                }
+
+                owned.push(msg);
+            }
+        });
+
+        for i in MAX_MESSAGES_PER_PAGE * 3..TOTAL_MESSAGES {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+        tx.push(STOP).unwrap();
+
+        // pages may be dropped out from under the consumer, but whatever it
+        // did get must be intact
+        let owned = consumer.join().unwrap();
+        let values: Vec<u32> = owned
+            .iter()
+            .map(|msg| u32::from_le_bytes(msg[..].try_into().unwrap()))
+            .collect();
+        assert!(!values.is_empty());
+        assert!(values.windows(2).all(|w| w[0] < w[1]));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn keyed_partitions() {
+        const PARTITIONS: u32 = 2;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path).keys(true).build().unwrap();
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut expected = vec![Vec::new(); PARTITIONS as usize];
+        for i in 0..1000_u32 {
+            let key = (i % 17) as u64;
+
+            tx.push_keyed(key, i.to_le_bytes()).unwrap();
+            expected[key_partition(key, PARTITIONS) as usize].push((key, i));
+        }
+        assert!(expected.iter().all(|e| !e.is_empty()));
+
+        for (partition, expected) in (0..PARTITIONS).zip(expected) {
+            let mut rx = Receiver::new(partition as usize, manager.clone())
+                .unwrap()
+                .with_partitions(PARTITIONS, partition..partition + 1)
+                .unwrap();
+
+            for (key, i) in expected {
+                let (k, msg) = rx.pop_keyed().unwrap();
+                assert_eq!((k, msg), (key, &i.to_le_bytes()[..]));
+            }
+        }
+
+        let rx = Receiver::new_anon(manager.clone()).unwrap();
+        assert!(matches!(
+            rx.with_partitions(PARTITIONS, 1..3),
+            Err(Error::InvalidPartitions(_))
+        ));
+        assert!(matches!(
+            tx.push_framed(b"\0\0\0\0"),
+            Err(Error::MetadataEnabled)
+        ));
+
+        let plain = DataPagesManager::builder(mkdir_random())
+            .cleanup_on_drop(true)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            Sender::new(plain.clone()).unwrap().push_keyed(1, b"x"),
+            Err(Error::KeysDisabled)
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_compressed_messages() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .codec(Codec::Lz4)
+            .keys(true)
+            .build()
+            .unwrap();
+
+        let msg = |i: u64| format!("{{\"id\": {i}, \"payload\": \"{}\"}}", "abcd".repeat(64));
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..100 {
+            tx.push_keyed(i, msg(i)).unwrap();
+        }
+        tx.push("").unwrap();
+
+        let bytes_used = manager.stats().bytes_used as usize;
+        assert!(bytes_used < msg(0).len() * 100 / 4);
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+        for i in 0..100 {
+            assert_eq!(rx.pop_keyed().unwrap(), (i, msg(i).as_bytes()));
+            assert_eq!(anon.try_pop().unwrap(), Some(msg(i).as_bytes()));
+        }
+        assert_eq!(rx.pop().unwrap(), b"");
+        assert!(matches!(rx.pop_framed(), Err(Error::MetadataEnabled)));
+
+        drop((tx, rx, anon, manager));
+        assert!(matches!(
+            DataPagesManager::builder(&path).keys(true).build(),
+            Err(Error::FormatMismatch { field: "flags", .. })
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn short_lengths() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .short_lengths(true)
+            .build()
+            .unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        for msg in ["a", "", "ccc"] {
+            tx.push(msg).unwrap();
+        }
+        let max = vec![7; MAX_SHORT_MESSAGE_SIZE];
+        tx.push(&max).unwrap();
+
+        // 2 bytes of framing per message instead of 4
+        let expected = 1 + 3 + MAX_SHORT_MESSAGE_SIZE + 4 * DataPage::SIZE_OF_SHORT_LEN;
+        assert_eq!(manager.stats().bytes_used as usize, expected);
+
+        assert!(matches!(
+            tx.push(vec![0; MAX_SHORT_MESSAGE_SIZE + 1]),
+            Err(Error::MessageTooLarge(len)) if len == MAX_SHORT_MESSAGE_SIZE + 1
+        ));
+
+        for msg in [&b"a"[..], b"", b"ccc", &max] {
+            assert_eq!(rx.pop().unwrap(), msg);
+        }
+        assert!(matches!(rx.pop_framed(), Err(Error::MetadataEnabled)));
+
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+        assert_eq!(anon.try_pop().unwrap(), Some(&b"a"[..]));
+        assert_eq!(manager.tail(1).unwrap(), [max]);
+
+        drop((tx, rx, anon, manager));
+        assert!(matches!(
+            DataPagesManager::new(&path),
+            Err(Error::FormatMismatch { field: "flags", .. })
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_messages() {
+        let path = mkdir_random();
+        let build = |key: [u8; 32]| {
+            DataPagesManager::builder(&path)
+                .timestamps(true)
+                .keys(true)
+                .encryption_key(key)
+                .build()
+        };
+        let manager = build([7; 32]).unwrap();
+
+        let msg = |i: u64| format!("secret number {i}");
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..100 {
+            tx.push_keyed(i, msg(i)).unwrap();
+        }
+        tx.push("").unwrap();
+
+        // nothing readable lands on disk, and equal payloads don't look equal
+        let stored = manager.tail(101).unwrap();
+        assert!(stored.iter().all(|s| !s.windows(6).any(|w| w == b"secret")));
+        assert_eq!(
+            stored[0].len(),
+            TIMESTAMP_SIZE + KEY_SIZE + msg(0).len() + TAG_SIZE
+        );
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+        for i in 0..100 {
+            assert_eq!(rx.pop_keyed().unwrap(), (i, msg(i).as_bytes()));
+            assert_eq!(anon.try_pop().unwrap(), Some(msg(i).as_bytes()));
+        }
+        assert_eq!(rx.pop().unwrap(), b"");
+        assert!(matches!(rx.pop_framed(), Err(Error::MetadataEnabled)));
+        assert!(matches!(manager.compact(), Err(Error::InvalidConfig(_))));
+
+        drop((tx, rx, anon, manager));
+
+        // the wrong key is caught by the tag
+        let wrong = build([8; 32]).unwrap();
+        let mut rx = Receiver::new(1, wrong.clone()).unwrap();
+        assert!(matches!(rx.pop(), Err(Error::Decrypt)));
+        drop((rx, wrong));
+
+        // plaintext and ciphertext never mix
+        assert!(matches!(
+            DataPagesManager::builder(&path)
+                .timestamps(true)
+                .keys(true)
+                .build(),
+            Err(Error::FormatMismatch { field: "flags", .. })
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[cfg(all(feature = "encryption", feature = "lz4"))]
+    #[test]
+    fn encrypted_compressed_messages() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .codec(Codec::Lz4)
+            .encryption_key([7; 32])
+            .build()
+            .unwrap();
+
+        let msg = |i: u32| format!("{i}{}", "abcd".repeat(64));
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        for i in 0..100 {
+            tx.push(msg(i)).unwrap();
+            assert_eq!(rx.pop().unwrap(), msg(i).as_bytes());
+        }
+
+        // compressed before it's encrypted, or it wouldn't shrink
+        let bytes_used = manager.stats().bytes_used as usize;
+        assert!(bytes_used < msg(0).len() * 100 / 4);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn async_and_sync_consumers() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+
+        let mut sync_rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut async_rx = Receiver::new(1, manager.clone()).unwrap();
+        let mut async_anon = Receiver::new_anon(manager.clone()).unwrap();
+
+        let sync_consumer = thread::spawn(move || sync_rx.pop_owned().unwrap());
+        let async_consumer = thread::spawn(move || {
+            block_on(async {
+                let grouped = async_rx.pop_async().await.unwrap().to_vec();
+                let anon = async_anon.pop_async().await.unwrap().to_vec();
+
+                // across a page boundary
+                for i in 0..MAX_MESSAGES_PER_PAGE {
+                    assert_eq!(async_rx.pop_async().await.unwrap(), i.to_le_bytes());
+                }
+
+                (grouped, anon)
+            })
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        tx.push("wake up").unwrap();
+
+        assert_eq!(sync_consumer.join().unwrap(), b"wake up");
+
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let (grouped, anon) = async_consumer.join().unwrap();
+        assert_eq!(grouped, b"wake up");
+        assert_eq!(anon, b"wake up");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn pop_async_cancellation() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        {
+            let fut = std::pin::pin!(rx.pop_async());
+            let waker = std::task::Waker::noop();
+            assert!(fut.poll(&mut Context::from_waker(waker)).is_pending());
+        }
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        tx.push("claimed").unwrap();
+        tx.push("next").unwrap();
+
+        // the dropped future's claim stays with rx, clones don't get it
+        assert_eq!(rx.clone().pop().unwrap(), b"next");
+        assert_eq!(rx.pop().unwrap(), b"claimed");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn backlog_spans_pages() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        assert_eq!(rx.backlog().unwrap(), 0);
+
+        // page 0 and 1 full, 10 messages on page 2
+        let total = 2 * MAX_MESSAGES_PER_PAGE as u64 + 10;
+        for i in 0..total {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(rx.backlog().unwrap(), total);
+
+        for _ in 0..5 {
+            rx.pop().unwrap();
+        }
+        assert_eq!(rx.backlog().unwrap(), total - 5);
+        assert_eq!(rx.available() as u64, MAX_MESSAGES_PER_PAGE as u64 - 5);
+
+        // a clone ahead on page 1 counts for the group too
+        let mut ahead = rx.clone();
+        for _ in 5..MAX_MESSAGES_PER_PAGE + 3 {
+            ahead.pop().unwrap();
+        }
+        assert_eq!(
+            rx.backlog().unwrap(),
+            total - MAX_MESSAGES_PER_PAGE as u64 - 3
+        );
+
+        for _ in 0..MAX_MESSAGES_PER_PAGE + 5 {
+            rx.pop().unwrap();
+        }
+        assert_eq!(ahead.backlog().unwrap(), 2);
+        for _ in 0..2 {
+            rx.pop().unwrap();
+        }
+        assert_eq!(rx.backlog().unwrap(), 0);
+        assert_eq!(ahead.backlog().unwrap(), 0);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn available_and_backlog_skip_failed_pushes() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        // only one of these fits on a page, so the second one's push onto
+        // page 0 fails and leaves a slot behind before it rolls
+        let big = vec![0; MAX_MESSAGE_SIZE / 2 + 1];
+        tx.push(&big).unwrap();
+        tx.push(&big).unwrap();
+        let (_, page) = manager.get_datapage(0).unwrap().unwrap();
+        assert_eq!(page.get().message_count(), 2);
+
+        assert_eq!(rx.available(), 1);
+        assert_eq!(rx.backlog().unwrap(), 2);
+
+        rx.pop().unwrap();
+        assert_eq!(rx.available(), 0);
+        assert_eq!(rx.backlog().unwrap(), 1);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn pop_filtered() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        // a type tag in the first byte, every third message is wanted
+        let total = MAX_MESSAGES_PER_PAGE + 10;
+        for i in 0..total {
+            let mut msg = vec![(i % 3 == 0) as u8];
+            msg.extend(i.to_le_bytes());
+            tx.push(msg).unwrap();
+        }
+        tx.push([2]).unwrap();
+
+        let wanted = |msg: &[u8]| msg[0] == 1;
+        for i in (0..total).step_by(3) {
+            let msg = rx.pop_filtered(wanted).unwrap();
+            assert_eq!(msg[1..], i.to_le_bytes());
+        }
+
+        // the rejected messages after the last match went with it
+        assert_eq!(rx.pop_filtered(|msg| msg[0] == 2).unwrap(), [2]);
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        // a predicate with state of its own, dropping repeats
+        let mut seen = std::collections::HashSet::new();
+        for msg in ["a", "b", "a", "c", "b"] {
+            tx.push(msg).unwrap();
+        }
+        let mut unseen = |msg: &[u8]| seen.insert(msg.to_vec());
+        for msg in ["a", "b", "c"] {
+            assert_eq!(rx.pop_filtered(&mut unseen).unwrap(), msg.as_bytes());
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn pop_filtered_compressed() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .codec(Codec::Lz4)
+            .build()
+            .unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager).unwrap();
+
+        let msg = |i: u32| format!("{i}:{}", "abcd".repeat(64));
+        for i in 0..10 {
+            tx.push(msg(i)).unwrap();
+        }
+
+        for i in (0..10).step_by(2) {
+            let popped = rx.pop_filtered(|m| m[0] % 2 == 0).unwrap();
+            assert_eq!(popped, msg(i).as_bytes());
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn pop_indexed_matches_push_at() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut rx2 = rx.clone();
+
+        let total = MAX_MESSAGES_PER_PAGE as u64 + 10;
+        let indices: Vec<u64> = (0..total)
+            .map(|i| tx.push_at(i.to_le_bytes()).unwrap())
+            .collect();
+        // a full page count rolls onto the next one without a gap
+        assert_eq!(indices, (0..total).collect::<Vec<_>>());
+
+        for (n, expected) in indices.iter().enumerate() {
+            let rx = if n % 2 == 0 { &mut rx } else { &mut rx2 };
+            let (index, data) = rx.pop_indexed().unwrap();
+
+            assert_eq!(index, *expected);
+            assert_eq!(data, (n as u64).to_le_bytes());
+            assert_eq!(rx.offset(), index + 1);
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn pop_located() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        for i in 0..MAX_MESSAGES_PER_PAGE + 2 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            let (loc, data) = rx.pop_located().unwrap();
+            assert_eq!(loc, PageLoc { page: 0, count: i });
+            assert_eq!(data, i.to_le_bytes());
+        }
+        for i in 0..2 {
+            let (loc, data) = rx.pop_located().unwrap();
+            assert_eq!(loc, PageLoc { page: 1, count: i });
+            assert_eq!(data, (MAX_MESSAGES_PER_PAGE + i).to_le_bytes());
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn with_next() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        let total = MAX_MESSAGES_PER_PAGE as usize + 10;
+        for i in 0..total {
+            tx.push(vec![0; i % 7]).unwrap();
+        }
+
+        let mut sum = 0;
+        for _ in 0..total {
+            sum += rx.with_next(<[u8]>::len).unwrap();
+        }
+        assert_eq!(sum, (0..total).map(|i| i % 7).sum());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    /// `cargo test --features no-futex futex_fallback`
+    #[test]
+    #[cfg(feature = "no-futex")]
+    fn futex_fallback() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert!(datapage::futex_fallback());
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+        let consumer = thread::spawn(move || {
+            (0..MAX_MESSAGES_PER_PAGE + 10)
+                .map(|_| {
+                    let msg = rx.pop().unwrap().to_vec();
+                    assert_eq!(anon.pop().unwrap(), msg);
+                    u32::from_le_bytes(msg.try_into().unwrap())
+                })
+                .collect::<Vec<_>>()
+        });
+
+        // the consumer parks on empty slots and at the end of the page
+        thread::sleep(Duration::from_millis(50));
+        let mut tx = Sender::new(manager).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE + 10 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let popped = consumer.join().unwrap();
+        assert_eq!(popped, (0..MAX_MESSAGES_PER_PAGE + 10).collect::<Vec<_>>());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn pop_spin() {
+        const TOTAL_MESSAGES: u32 = 2 * MAX_MESSAGES_PER_PAGE + 100;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+
+        let producer = thread::spawn(move || {
+            let mut tx = Sender::new(manager).unwrap();
+
+            for i in 0..TOTAL_MESSAGES {
+                tx.push(i.to_le_bytes()).unwrap();
+            }
+        });
+
+        let anon = thread::spawn(move || {
+            for i in 0..TOTAL_MESSAGES {
+                assert_eq!(anon.pop_spin().unwrap(), i.to_le_bytes());
+            }
+        });
+
+        for i in 0..TOTAL_MESSAGES {
+            assert_eq!(rx.pop_spin().unwrap(), i.to_le_bytes());
+        }
+
+        producer.join().unwrap();
+        anon.join().unwrap();
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn pop_bounded() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+
+        for i in 0..=MAX_MESSAGES_PER_PAGE {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let last_on_page = (MAX_MESSAGES_PER_PAGE - 1).to_le_bytes();
+        let next_page = MAX_MESSAGES_PER_PAGE.to_le_bytes();
+
+        rx.skip(MAX_MESSAGES_PER_PAGE as usize - 1).unwrap();
+        assert_eq!(rx.pop_bounded(0).unwrap(), Some(&last_on_page[..]));
+        assert_eq!(rx.pop_bounded(0).unwrap(), None);
+        assert_eq!(rx.pop_bounded(0).unwrap(), None);
+        assert_eq!(rx.pop_bounded(1).unwrap(), Some(&next_page[..]));
+
+        for _ in 0..MAX_MESSAGES_PER_PAGE - 1 {
+            anon.pop().unwrap();
+        }
+        assert_eq!(anon.pop_bounded(0).unwrap(), Some(&last_on_page[..]));
+        assert_eq!(anon.pop_bounded(0).unwrap(), None);
+        assert_eq!(anon.pop_bounded(1).unwrap(), Some(&next_page[..]));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn group_counts_past_page_end() {
+        const PAGES: usize = 4;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        for i in 0..PAGES as u32 * MAX_MESSAGES_PER_PAGE + 1 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        for page in 0..PAGES {
+            let (_, datapage) = manager.get_or_create_datapage(page).unwrap();
+            // each page counts the group's messages from 0
+            assert_eq!(datapage.get().get_group_count(0), 0);
+
+            assert_eq!(
+                rx.skip(MAX_MESSAGES_PER_PAGE as usize).unwrap(),
+                MAX_MESSAGES_PER_PAGE as usize
+            );
+
+            // waiting for the next page claims a single slot past the end,
+            // however often it's retried
+            for _ in 0..100 {
+                assert_eq!(rx.pop_bounded(0).unwrap(), None);
+            }
+            assert_eq!(datapage.get().get_group_count(0), MAX_MESSAGES_PER_PAGE + 1);
+            assert_eq!(rx.cursor().datapage_count, page);
+            assert_eq!(rx.cursor().count, MAX_MESSAGES_PER_PAGE);
+        }
+
+        let last = PAGES as u32 * MAX_MESSAGES_PER_PAGE;
+        assert_eq!(rx.pop().unwrap(), last.to_le_bytes());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn skip_across_pages() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        assert_eq!(rx.skip(10).unwrap(), 0);
+
+        let total = MAX_MESSAGES_PER_PAGE + 100;
+        for i in 0..total {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        assert_eq!(rx.skip(3).unwrap(), 3);
+        assert_eq!(rx.pop().unwrap(), 3_u32.to_le_bytes());
+
+        let past_boundary = MAX_MESSAGES_PER_PAGE as usize + 10 - 4;
+        assert_eq!(rx.skip(past_boundary).unwrap(), past_boundary);
+        assert_eq!(rx.datapage_count, 1);
+        assert_eq!(
+            rx.pop().unwrap(),
+            (MAX_MESSAGES_PER_PAGE + 10).to_le_bytes()
+        );
+
+        // stops at the live tail
+        assert_eq!(rx.skip(1000).unwrap(), 89);
+        tx.push("next").unwrap();
+        assert_eq!(rx.pop().unwrap(), b"next");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn grouped_try_pop_and_drain() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        assert_eq!(rx.try_pop().unwrap(), None);
+        assert!(rx.drain(10).unwrap().is_empty());
+
+        let total = MAX_MESSAGES_PER_PAGE + 10;
+        for i in 0..total {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        assert_eq!(rx.try_pop().unwrap(), Some(&0_u32.to_le_bytes()[..]));
+
+        let first = rx.drain(4).unwrap();
+        assert_eq!(first.len(), 4);
+        assert_eq!(first[3], 4_u32.to_le_bytes());
+
+        // giving up at the tail didn't cost the group anything
+        let rest = rx.drain(usize::MAX).unwrap();
+        assert_eq!(rest.len(), total as usize - 5);
+        assert_eq!(rest.last().unwrap(), &(total - 1).to_le_bytes());
+
+        tx.push("later").unwrap();
+        assert_eq!(rx.pop().unwrap(), b"later");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn available() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+
+        assert_eq!(rx.available(), 0);
+
+        for i in 0..3_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(rx.available(), 3);
+
+        // a claim held by try_claim is still there for this receiver
+        assert!(rx.try_claim().unwrap());
+        assert_eq!(rx.available(), 3);
+        assert_eq!(rx.pop().unwrap(), 0_u32.to_le_bytes());
+        assert_eq!(rx.available(), 2);
+
+        // process what's here, then yield
+        while rx.available() > 0 {
+            rx.pop().unwrap();
+        }
+        assert_eq!(rx.try_pop().unwrap(), None);
+        assert_eq!(rx.available(), 0);
+
+        // other groups are unaffected
+        assert_eq!(Receiver::new(1, manager.clone()).unwrap().available(), 3);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn fork_independent() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut worker = rx.clone();
+
+        for i in 0..MAX_MESSAGES_PER_PAGE + 10 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        assert_eq!(rx.pop().unwrap(), 0_u32.to_le_bytes());
+        assert_eq!(worker.pop().unwrap(), 1_u32.to_le_bytes());
+
+        // a claimed message is still ahead of the source, so the fork sees it,
+        // but not the one the other receiver of the group claimed after it
+        assert!(rx.try_claim().unwrap());
+        assert!(worker.try_claim().unwrap());
+        let mut fork = rx.fork_independent();
+        assert_eq!(rx.pop().unwrap(), 2_u32.to_le_bytes());
+        assert_eq!(fork.pop().unwrap(), 2_u32.to_le_bytes());
+
+        // the clone takes half the remaining messages, the fork sees all of them
+        for i in 4..MAX_MESSAGES_PER_PAGE + 10 {
+            assert_eq!(fork.pop().unwrap(), i.to_le_bytes());
+        }
+        assert_eq!(fork.try_pop().unwrap(), None);
+
+        let mut rest = Vec::new();
+        while let Some(msg) = rx.try_pop().unwrap() {
+            rest.push(u32::from_le_bytes(msg.try_into().unwrap()));
+            if let Some(msg) = worker.try_pop().unwrap() {
+                rest.push(u32::from_le_bytes(msg.try_into().unwrap()));
+            }
+        }
+        rest.sort_unstable();
+        assert_eq!(rest, (3..MAX_MESSAGES_PER_PAGE + 10).collect::<Vec<_>>());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn timestamped_messages() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .timestamps(true)
+            .build()
+            .unwrap();
+
+        let before = SystemTime::now();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..1000_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+        let after = SystemTime::now();
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut last = before;
+        for i in 0..1000_u32 {
+            let (ts, msg) = rx.pop_with_meta().unwrap();
+            let ts = ts.unwrap();
+
+            assert_eq!(msg, i.to_le_bytes());
+            assert!(ts >= last && ts <= after);
+            last = ts;
+        }
+
+        let mut rx = Receiver::new(1, manager).unwrap();
+        assert_eq!(rx.pop().unwrap(), 0_u32.to_le_bytes());
+
+        assert!(matches!(
+            DataPagesManager::new(&path),
+            Err(Error::FormatMismatch { field: "flags", .. })
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+
+        // no timestamp is told apart from one at the epoch
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        Sender::new(manager.clone())
+            .unwrap()
+            .push("untimed")
+            .unwrap();
+        let mut rx = Receiver::new(0, manager).unwrap();
+        assert_eq!(rx.pop_with_meta().unwrap(), (None, &b"untimed"[..]));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
 
-#[derive(Clone)]
-pub struct Receiver<T> {
-    group: usize,
-    anon_count: u32,
-    manager: DataPagesManager,
-    datapage_count: usize,
-    datapage: Arc<MmapCell<DataPage>>,
-    _type: std::marker::PhantomData<T>,
-}
+    #[test]
+    fn commit_and_resume() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
 
-pub trait GenReceiver {
-    fn pop(&mut self) -> Result<&[u8], std::io::Error>;
-}
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..10_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
 
-impl Receiver<Grouped> {
-    pub fn new(group: usize, manager: DataPagesManager) -> Result<Self, std::io::Error> {
-        let (datapage_count, datapage) = manager.get_or_create_datapage(0)?;
+        assert_eq!(manager.committed(0).unwrap(), None);
 
-        Ok(Receiver {
-            group,
-            anon_count: 0,
-            manager,
-            datapage_count,
-            datapage,
-            _type: PhantomData,
-        })
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        for i in 0..4_u32 {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+        manager.commit(0, rx.offset()).unwrap();
+
+        // consumed but never committed
+        for _ in 0..3 {
+            rx.pop().unwrap();
+        }
+
+        drop(rx);
+        drop(tx);
+        drop(manager);
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.committed(0).unwrap(), Some(4));
+
+        let mut rx = Receiver::resume_committed(0, manager).unwrap();
+        for i in 4..10_u32 {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
     }
-}
 
-impl GenReceiver for Receiver<Grouped> {
-    fn pop(&mut self) -> Result<&[u8], std::io::Error> {
-        loop {
-            let count = self.datapage.get().increment_group_count(self.group, 1);
+    #[test]
+    fn anon_commit_and_resume() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
 
-            match self.datapage.get().get(count) {
-                Ok(data) => return Ok(data),
-                // WARN: if you add more errors in the future make sure to match on them!!!
-                Err(_e) => {}
-            };
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE + 10 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
 
-            let (dp_count, datapage) = self
-                .manager
-                .get_or_create_datapage(self.datapage_count.wrapping_add(1))?;
+        assert_eq!(manager.committed_named("tailer").unwrap(), None);
+        assert!(matches!(
+            manager.commit_named("../tailer", 0),
+            Err(Error::InvalidConfig(_))
+        ));
 
-            self.datapage_count = dp_count;
-            self.datapage = datapage;
+        let mut rx = Receiver::new_anon(manager.clone()).unwrap();
+        rx.seek(1, 2).unwrap();
+        for i in 2..5 {
+            assert_eq!(rx.pop().unwrap(), (MAX_MESSAGES_PER_PAGE + i).to_le_bytes());
+        }
+        rx.commit("tailer").unwrap();
+        rx.pop().unwrap();
+
+        // named offsets don't clash with group offsets
+        assert_eq!(manager.committed(0).unwrap(), None);
+
+        drop(rx);
+        drop(tx);
+        drop(manager);
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut rx = Receiver::resume("tailer", manager.clone()).unwrap();
+        assert_eq!(rx.cursor().datapage_count, 1);
+        for i in 5..10 {
+            assert_eq!(rx.pop().unwrap(), (MAX_MESSAGES_PER_PAGE + i).to_le_bytes());
+        }
+
+        let fresh = Receiver::resume("other", manager.clone()).unwrap();
+        assert_eq!(
+            (fresh.cursor().datapage_count, fresh.cursor().count),
+            (0, 0)
+        );
+
+        // pages 0 and 1 are reclaimed, so resuming clamps forward to page 2
+        manager.set_max_datapages(2);
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..2 * MAX_MESSAGES_PER_PAGE {
+            tx.push(i.to_le_bytes()).unwrap();
         }
+        let cursor = Receiver::resume("tailer", manager).unwrap().cursor();
+        assert_eq!((cursor.datapage_count, cursor.count), (2, 0));
+
+        std::fs::remove_dir_all(path).unwrap();
     }
-}
 
-impl Receiver<Anonymous> {
-    pub fn new_anon(manager: DataPagesManager) -> Result<Self, std::io::Error> {
-        Ok(Receiver::new(0, manager)?.into())
+    #[test]
+    fn commit_before_loses_on_crash() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..5_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        assert_eq!(rx.pop_commit_before().unwrap(), 0_u32.to_le_bytes());
+        assert_eq!(manager.committed(0).unwrap(), Some(1));
+        // crashes while handling 1, after it was committed
+        assert_eq!(rx.pop_commit_before().unwrap(), 1_u32.to_le_bytes());
+
+        drop(rx);
+        drop(tx);
+        drop(manager);
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut rx = Receiver::resume_committed(0, manager).unwrap();
+        for i in 2..5_u32 {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
     }
-}
 
-impl GenReceiver for Receiver<Anonymous> {
-    fn pop(&mut self) -> Result<&[u8], std::io::Error> {
-        loop {
-            let count = self.anon_count;
-            self.anon_count += 1;
+    #[test]
+    fn commit_after_redelivers_on_crash() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
 
-            match self.datapage.get().get(count) {
-                Ok(data) => return Ok(data),
-                // WARN: if you add more errors in the future make sure to match on them!!!
-                Err(_e) => {}
-            };
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE + 5 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
 
-            self.anon_count = 0;
+        // across the end of a page, where the offset moves on to the next one
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE + 1 {
+            assert_eq!(rx.pop_commit_after().unwrap(), i.to_le_bytes());
+            rx.ack().unwrap();
+        }
+        assert_eq!(
+            manager.committed(0).unwrap(),
+            Some(MAX_MESSAGES_PER_PAGE as u64 + 1)
+        );
 
-            let (dp_count, datapage) = self
-                .manager
-                .get_or_create_datapage(self.datapage_count.wrapping_add(1))?;
+        // crashes while handling the next one, before acking it
+        let unacked = MAX_MESSAGES_PER_PAGE + 1;
+        assert_eq!(rx.pop_commit_after().unwrap(), unacked.to_le_bytes());
 
-            self.datapage_count = dp_count;
-            self.datapage = datapage;
+        drop(rx);
+        drop(tx);
+        drop(manager);
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut rx = Receiver::resume_committed(0, manager.clone()).unwrap();
+        for i in unacked..MAX_MESSAGES_PER_PAGE + 5 {
+            assert_eq!(rx.pop_commit_after().unwrap(), i.to_le_bytes());
         }
+        rx.ack().unwrap();
+        rx.ack().unwrap();
+        assert_eq!(
+            manager.committed(0).unwrap(),
+            Some(MAX_MESSAGES_PER_PAGE as u64 + 5)
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
     }
-}
 
-impl From<Receiver<Grouped>> for Receiver<Anonymous> {
-    fn from(value: Receiver<Grouped>) -> Self {
-        Receiver {
-            group: 0,
-            anon_count: 0,
-            manager: value.manager,
-            datapage_count: value.datapage_count,
-            datapage: value.datapage,
-            _type: PhantomData,
+    #[test]
+    fn commit_after_waits_for_other_receivers() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..5_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let mut slow = Receiver::new(0, manager.clone()).unwrap();
+        let mut fast = slow.clone();
+        assert_eq!(slow.pop_commit_after().unwrap(), 0_u32.to_le_bytes());
+        assert_eq!(fast.pop_commit_after().unwrap(), 1_u32.to_le_bytes());
+        assert_eq!(fast.pop_commit_after().unwrap(), 2_u32.to_le_bytes());
+
+        // 0 isn't handled yet, so nothing past it is committed
+        fast.ack().unwrap();
+        assert_eq!(manager.committed(0).unwrap(), Some(0));
+
+        slow.ack().unwrap();
+        assert_eq!(manager.committed(0).unwrap(), Some(3));
+
+        // crashes while handling 3, after 4 was acked
+        assert_eq!(slow.pop_commit_after().unwrap(), 3_u32.to_le_bytes());
+        assert_eq!(fast.pop_commit_after().unwrap(), 4_u32.to_le_bytes());
+        fast.ack().unwrap();
+        assert_eq!(manager.committed(0).unwrap(), Some(3));
+
+        drop((slow, fast, tx, manager));
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut rx = Receiver::resume_committed(0, manager).unwrap();
+        assert_eq!(rx.pop().unwrap(), 3_u32.to_le_bytes());
+        assert_eq!(rx.pop().unwrap(), 4_u32.to_le_bytes());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn disk_full_errors() {
+        let enospc = std::io::Error::from_raw_os_error(28);
+        assert!(matches!(Error::from(enospc), Error::DiskFull(_)));
+        assert!(matches!(
+            Error::from(std::io::Error::from(std::io::ErrorKind::StorageFull)),
+            Error::DiskFull(_)
+        ));
+        assert!(matches!(
+            Error::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied)),
+            Error::Io(_)
+        ));
+    }
+
+    #[test]
+    fn sender_check() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let tx = Sender::new(manager.clone()).unwrap();
+
+        tx.check().unwrap();
+        // page 0 and the control file, the probe is gone again
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(&path).unwrap();
+        assert!(matches!(tx.check(), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn try_push_and_ensure_capacity() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+
+        tx.ensure_capacity().unwrap();
+        assert_eq!(tx.datapage_count, 0);
+        assert_eq!(manager.stats().mapped_pages, 2);
+
+        let mut pushed = 0_u32;
+        while tx.try_push(pushed.to_le_bytes()).is_ok() {
+            pushed += 1;
+        }
+
+        assert_eq!(pushed, MAX_MESSAGES_PER_PAGE);
+        assert!(matches!(
+            tx.try_push(pushed.to_le_bytes()),
+            Err(TryPushError::PageFull)
+        ));
+
+        tx.ensure_capacity().unwrap();
+        assert_eq!(tx.datapage_count, 1);
+
+        let too_large = vec![0; MAX_MESSAGE_SIZE + 1];
+        assert!(matches!(
+            tx.try_push(&too_large),
+            Err(TryPushError::TooLarge(len)) if len == too_large.len()
+        ));
+        assert!(matches!(
+            tx.push(&too_large),
+            Err(Error::MessageTooLarge(_))
+        ));
+        assert_eq!(tx.datapage_count, 1);
+        tx.try_push(pushed.to_le_bytes()).unwrap();
+
+        let mut rx = Receiver::new(0, manager).unwrap();
+        for i in 0..=pushed {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
         }
+
+        std::fs::remove_dir_all(path).unwrap();
     }
-}
 
-#[derive(Clone)]
-pub struct Sender {
-    manager: DataPagesManager,
-    datapage_count: usize,
-    datapage: Arc<MmapCell<DataPage>>,
-}
+    #[test]
+    fn try_push_batch() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
 
-impl Sender {
-    pub fn new(manager: DataPagesManager) -> Result<Self, std::io::Error> {
-        let (datapage_count, datapage) = manager.get_or_create_datapage(0)?;
-        //let datapage_count = RefCell::new(datapage_count);
-        //let datapage = RefCell::new(datapage);
+        for i in 0..MAX_MESSAGES_PER_PAGE - 3 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
 
-        Ok(Sender {
-            manager,
-            datapage_count,
-            datapage,
-        })
+        let batch: Vec<_> = (MAX_MESSAGES_PER_PAGE - 3..MAX_MESSAGES_PER_PAGE + 7)
+            .map(u32::to_le_bytes)
+            .collect();
+        assert_eq!(tx.try_push_batch(&batch).unwrap(), 3);
+        assert_eq!(tx.datapage_count, 0);
+        assert_eq!(tx.try_push_batch(&batch[3..]).unwrap(), 0);
+
+        tx.ensure_capacity().unwrap();
+        assert_eq!(tx.try_push_batch(&batch[3..]).unwrap(), 7);
+        assert_eq!(tx.datapage_count, 1);
+        assert_eq!(tx.try_push_batch::<&[u8]>(&[]).unwrap(), 0);
+
+        let mut rx = Receiver::new(0, manager).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE + 7 {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        std::fs::remove_dir_all(path).unwrap();
     }
 
-    pub fn push<T: AsRef<[u8]>>(&mut self, data: T) -> Result<(), std::io::Error> {
-        loop {
-            match self.datapage.get_mut().push(&data) {
-                Ok(()) => return Ok(()),
-                Err(_e) => {}
-            }
+    #[test]
+    fn flush_every() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap().flush_every(3);
 
-            let (dp_count, datapage) = self
-                .manager
-                .get_or_create_datapage(self.datapage_count.wrapping_add(1))?;
+        for i in 0..7_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+            assert_eq!(tx.unflushed, (i + 1) % 3);
+        }
 
-            self.datapage_count = dp_count;
-            self.datapage = datapage;
+        // moving on to a new page flushes the old one first
+        tx.flush_every = 1_000;
+        for i in 7..MAX_MESSAGES_PER_PAGE + 1 {
+            tx.push(i.to_le_bytes()).unwrap();
         }
+        assert_eq!(tx.datapage_count, 1);
+        assert_eq!(tx.unflushed, 1);
+
+        tx.flush().unwrap();
+        assert_eq!(tx.unflushed, 0);
+
+        // the sender dies without running anything, everything flushed is
+        // there for the next one to open
+        std::mem::forget(tx);
+        drop(manager);
+        let mut rx = Receiver::new(0, DataPagesManager::new(&path).unwrap()).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE + 1 {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        std::fs::remove_dir_all(path).unwrap();
     }
-}
 
-// TODO: Move these out to a test dir
-// they take up wayyyy too much space
-#[cfg(test)]
-mod test {
-    use std::{
-        path::{Path, PathBuf},
-        sync::{
-            atomic::{AtomicUsize, Ordering},
-            mpsc, Barrier,
-        },
-        thread,
-        time::Instant,
-    };
+    #[test]
+    fn push_all() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
 
-    use rand::random;
-    use tracing::info;
+        let items: Vec<_> = (0..MAX_MESSAGES_PER_PAGE + 100)
+            .map(u32::to_le_bytes)
+            .collect();
+        tx.push_all(&items).unwrap();
+        assert_eq!(tx.datapage_count, 1);
+        assert_eq!((tx.flush_every, tx.unflushed), (0, 0));
 
-    use super::*;
+        // stops at the item that can't go in, for the caller to carry on
+        // after. Leaving page 1 flushed it, so only the push onto page 2 is
+        // left unflushed
+        let too_large = vec![0; MAX_MESSAGE_SIZE + 1];
+        let mut rest = vec![&b"a"[..]; (MAX_MESSAGES_PER_PAGE - 100) as usize];
+        rest.extend([&b"b"[..], &too_large, b"c"]);
+        let err = tx.push_all(&rest).unwrap_err();
+        assert_eq!(err.pushed, rest.len() - 2);
+        assert!(matches!(err.error, Error::MessageTooLarge(_)));
+        assert_eq!(tx.datapage_count, 2);
+        assert_eq!((tx.flush_every, tx.unflushed), (0, 1));
+
+        tx.push_all(&rest[err.pushed + 1..]).unwrap();
+        assert_eq!(tx.unflushed, 0);
+
+        // a manager reopened on the directory reads it all back
+        drop((tx, manager));
+        let mut rx = Receiver::new(0, DataPagesManager::new(&path).unwrap()).unwrap();
+        for item in &items {
+            assert_eq!(rx.pop().unwrap(), item);
+        }
+        for item in rest.iter().filter(|item| item.len() == 1) {
+            assert_eq!(rx.pop().unwrap(), *item);
+        }
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn remaining_on_page() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        assert_eq!(
+            tx.remaining_on_page(),
+            (MAX_MESSAGES_PER_PAGE, MAX_BYTES_PER_PAGE)
+        );
+
+        tx.push(0_u32.to_le_bytes()).unwrap();
+        let framed_len = (4 + DataPage::SIZE_OF_LEN) as u32;
+        assert_eq!(
+            tx.remaining_on_page(),
+            (MAX_MESSAGES_PER_PAGE - 1, MAX_BYTES_PER_PAGE - framed_len)
+        );
+
+        let mut i = 1_u32;
+        while tx.remaining_on_page().0 > 0 {
+            tx.push(i.to_le_bytes()).unwrap();
+            i += 1;
+        }
+        assert_eq!(i, MAX_MESSAGES_PER_PAGE);
+        assert_eq!(tx.datapage_count, 0);
+        assert_eq!(tx.remaining_on_page(), (0, 0));
+
+        tx.push(i.to_le_bytes()).unwrap();
+        assert_eq!(tx.datapage_count, 1);
+        assert_eq!(
+            tx.remaining_on_page(),
+            (MAX_MESSAGES_PER_PAGE - 1, MAX_BYTES_PER_PAGE - framed_len)
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn pop_until() {
+        const WAIT: Duration = Duration::from_millis(50);
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
 
-    fn mkdir_random() -> PathBuf {
-        const TEST_DIR: &str = "/tmp/";
-        let num: u64 = random();
-        let rand_file_name = format!("disk-mpmc-test-{:X}", num);
+        let start = Instant::now();
+        assert_eq!(rx.pop_until(start + WAIT).unwrap(), None);
+        assert_eq!(anon.pop_until(start + WAIT).unwrap(), None);
+        assert!(start.elapsed() >= WAIT);
+
+        // both caught up at the end of a full page, the next one doesn't exist
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(
+            rx.skip(MAX_MESSAGES_PER_PAGE as usize).unwrap(),
+            MAX_MESSAGES_PER_PAGE as usize
+        );
+        anon.seek(0, MAX_MESSAGES_PER_PAGE).unwrap();
+        assert!(!manager.page_exists(1));
 
-        let dir = Path::new(TEST_DIR).join(rand_file_name);
+        // the producer shows up well after the first wait would have given up
+        let start = Instant::now();
+        let deadline = start + 10 * WAIT;
+        let producer = thread::spawn(move || {
+            thread::sleep(5 * WAIT);
+            tx.push("late").unwrap();
+            tx
+        });
+        assert_eq!(rx.pop_until(deadline).unwrap(), Some(&b"late"[..]));
+        assert_eq!(anon.pop_until(deadline).unwrap(), Some(&b"late"[..]));
+        assert!(start.elapsed() >= 5 * WAIT);
+        let mut tx = producer.join().unwrap();
 
-        std::fs::create_dir_all(&dir).unwrap();
+        // giving up doesn't cost the group a message
+        assert_eq!(rx.pop_until(Instant::now() + WAIT).unwrap(), None);
+        assert_eq!(anon.pop_until(Instant::now() + WAIT).unwrap(), None);
+        tx.push("next").unwrap();
+        assert_eq!(rx.try_pop().unwrap(), Some(&b"next"[..]));
+        assert_eq!(anon.try_pop().unwrap(), Some(&b"next"[..]));
 
-        dir
+        std::fs::remove_dir_all(path).unwrap();
     }
 
     #[test]
     fn sequential_test() {
+        const TOTAL_MESSAGES: usize = 200_000;
         const TEST_MESSAGE: &str = const_str::repeat!("a", 100);
 
-        tracing_subscriber::fmt::init();
+        let _ = tracing_subscriber::fmt::try_init();
 
         let path = mkdir_random();
         let manager = DataPagesManager::new(&path).unwrap();
@@ -187,15 +3906,15 @@ mod test {
         let mut tx = Sender::new(manager.clone()).unwrap();
         let now = Instant::now();
 
-        for _ in 0..50_000_000 {
+        for _ in 0..TOTAL_MESSAGES {
             tx.push(TEST_MESSAGE).unwrap();
         }
         let elapsed = now.elapsed();
 
-        let test_msg_bytes = TEST_MESSAGE.as_bytes().len() * 50_000_000;
+        let test_msg_bytes = TEST_MESSAGE.len() * TOTAL_MESSAGES;
         let test_msg_mb = test_msg_bytes as f64 * 0.000001;
         info!(
-            "pushed 50,000,000 messages ({:.2} MB) in {} ms [{:.2}MB/s]",
+            "pushed {TOTAL_MESSAGES} messages ({:.2} MB) in {} ms [{:.2}MB/s]",
             test_msg_mb,
             elapsed.as_millis(),
             test_msg_bytes as f64 / elapsed.as_micros() as f64
@@ -203,30 +3922,30 @@ mod test {
 
         let mut rx = Receiver::new(0, manager).unwrap();
         let now = Instant::now();
-        for _ in 0..50_000_000 {
-            rx.pop().unwrap();
+        for _ in 0..TOTAL_MESSAGES {
+            assert_eq!(rx.pop().unwrap(), TEST_MESSAGE.as_bytes());
         }
         let elapsed = now.elapsed();
 
-        let test_msg_bytes = TEST_MESSAGE.as_bytes().len() * 50_000_000;
+        let test_msg_bytes = TEST_MESSAGE.len() * TOTAL_MESSAGES;
         let test_msg_mb = test_msg_bytes as f64 * 0.000001;
         info!(
-            "popped 50,000,000 messages ({:.2} MB) in {} ms [{:.2}MB/s]",
+            "popped {TOTAL_MESSAGES} messages ({:.2} MB) in {} ms [{:.2}MB/s]",
             test_msg_mb,
             elapsed.as_millis(),
             test_msg_bytes as f64 / elapsed.as_micros() as f64
         );
 
-        // std::fs::remove_dir_all(path).unwrap();
+        std::fs::remove_dir_all(path).unwrap();
     }
 
     #[test]
     fn spsc_test() {
-        const TOTAL_MESSAGES: usize = 50_000_000;
+        const TOTAL_MESSAGES: usize = 200_000;
         const NUM_THREADS: usize = 1;
         const TEST_MESSAGE: &str = const_str::repeat!("a", 100);
 
-        tracing_subscriber::fmt::init();
+        let _ = tracing_subscriber::fmt::try_init();
 
         let path = mkdir_random();
         let manager = DataPagesManager::new(&path).unwrap();
@@ -282,10 +4001,10 @@ mod test {
         let _ = rx_end.recv();
 
         let elapsed = now.elapsed();
-        let test_msg_bytes = TEST_MESSAGE.as_bytes().len() * 50_000_000;
+        let test_msg_bytes = TEST_MESSAGE.len() * TOTAL_MESSAGES;
         let test_msg_mb = test_msg_bytes as f64 * 0.000001;
         info!(
-            "pushed & popped 50,000,000 messages ({:.2} MB) in {} ms [{:.2}MB/s]",
+            "pushed & popped {TOTAL_MESSAGES} messages ({:.2} MB) in {} ms [{:.2}MB/s]",
             test_msg_mb,
             elapsed.as_millis(),
             test_msg_bytes as f64 / elapsed.as_micros() as f64
@@ -296,11 +4015,11 @@ mod test {
 
     #[test]
     fn mpmc_test() {
-        const TOTAL_MESSAGES: usize = 50_000_000;
+        const TOTAL_MESSAGES: usize = 200_000;
         const NUM_THREADS: usize = 8;
         const TEST_MESSAGE: &str = const_str::repeat!("a", 100);
 
-        tracing_subscriber::fmt::init();
+        let _ = tracing_subscriber::fmt::try_init();
 
         let path = mkdir_random();
         let manager = DataPagesManager::new(&path).unwrap();
@@ -356,10 +4075,10 @@ mod test {
         let _ = rx_end.recv();
 
         let elapsed = now.elapsed();
-        let test_msg_bytes = TEST_MESSAGE.as_bytes().len() * 50_000_000;
+        let test_msg_bytes = TEST_MESSAGE.len() * TOTAL_MESSAGES;
         let test_msg_mb = test_msg_bytes as f64 * 0.000001;
         info!(
-            "pushed & popped 50,000,000 messages ({:.2} MB) in {} ms [{:.2}MB/s]",
+            "pushed & popped {TOTAL_MESSAGES} messages ({:.2} MB) in {} ms [{:.2}MB/s]",
             test_msg_mb,
             elapsed.as_millis(),
             test_msg_bytes as f64 / elapsed.as_micros() as f64
@@ -370,11 +4089,11 @@ mod test {
 
     #[test]
     fn two_topics() {
-        const TOTAL_MESSAGES: usize = 50_000_000;
+        const TOTAL_MESSAGES: usize = 200_000;
         const NUM_THREADS: usize = 1;
         const TEST_MESSAGE: &str = const_str::repeat!("a", 100);
 
-        tracing_subscriber::fmt::init();
+        let _ = tracing_subscriber::fmt::try_init();
 
         let path = mkdir_random();
         let path2 = mkdir_random();
@@ -421,15 +4140,17 @@ mod test {
         }
 
         let elapsed = now.elapsed();
-        let test_msg_bytes = TEST_MESSAGE.as_bytes().len() * 100_000_000;
+        let test_msg_bytes = TEST_MESSAGE.len() * TOTAL_MESSAGES * 2;
         let test_msg_mb = test_msg_bytes as f64 * 0.000001;
         info!(
-            "pushed 100,000,000 messages ({:.2} MB) in {} ms [{:.2}MB/s]",
+            "pushed {} messages ({:.2} MB) in {} ms [{:.2}MB/s]",
+            TOTAL_MESSAGES * 2,
             test_msg_mb,
             elapsed.as_millis(),
             test_msg_bytes as f64 / elapsed.as_micros() as f64
         );
 
         std::fs::remove_dir_all(path).unwrap();
+        std::fs::remove_dir_all(path2).unwrap();
     }
 }
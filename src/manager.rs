@@ -1,130 +1,4822 @@
 use std::{
-    collections::VecDeque,
-    ffi::OsStr,
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::{Read, Write},
     path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{fence, AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
+    task::Waker,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use mmapcell::MmapCell;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock, RwLockUpgradableReadGuard};
 
-use crate::datapage::DataPage;
+use crate::{
+    datapage::{
+        futex_fallback, probe_futex, wait_timeout, DataPage, PageFormat, SlotProblem, KEY_SIZE,
+        LEGACY_PAGE_SIZE, MAX_BYTES_PER_PAGE, MAX_MESSAGES_PER_PAGE, MAX_RECEIVER_GROUPS,
+        TIMESTAMP_SIZE,
+    },
+    encryption::Cipher,
+    strip_frame, Codec, Error, Grouped, Receiver, Record, Sender, Stored,
+};
+
+type ReclaimHook = Box<dyn Fn(usize, &Path) + Send + Sync>;
+type PageMapper = dyn Fn(&Path) -> std::io::Result<MmapCell<DataPage>> + Send + Sync;
+/// A page number along with its mapped page.
+type NumberedPage = (usize, Arc<MmapCell<DataPage>>);
 
 #[derive(Clone)]
 pub struct DataPagesManager {
     path: PathBuf,
+    topic: Arc<str>,
+    /// `topic` followed by the page file stem, page files are `<page_stem>.<n>`.
+    page_stem: Arc<str>,
     max_datapages: Arc<AtomicUsize>,
+    min_datapages: Arc<AtomicUsize>,
     datapage_count: Arc<AtomicUsize>,
     datapage_ring: Arc<RwLock<VecDeque<Arc<MmapCell<DataPage>>>>>,
+    /// Shared with managers in other processes, see `shared_newest_page`.
+    newest_page: Arc<MmapCell<AtomicU64>>,
+    closed: Arc<AtomicBool>,
+    on_reclaim: Arc<RwLock<Option<ReclaimHook>>>,
+    created_pages: Arc<AtomicU64>,
+    reclaimed_pages: Arc<AtomicU64>,
+    size_histogram: Option<Arc<[AtomicU64; SIZE_BUCKETS]>>,
+    /// Bumped and woken whenever this process adds a page, see `wait_datapage`.
+    page_added: Arc<AtomicU32>,
+    group_consumers: Arc<[AtomicUsize; MAX_RECEIVER_GROUPS]>,
+    /// Shared with managers in other processes, see `heartbeat`.
+    group_heartbeats: Arc<MmapCell<[AtomicU64; MAX_RECEIVER_GROUPS]>>,
+    heartbeat_ttl: Option<Duration>,
+    file_mode: Option<u32>,
+    preallocate: bool,
+    prefault: bool,
+    reuse_pages: bool,
+    release_reclaimed: bool,
+    consumer_creates_pages: bool,
+    sync_directory_on_create: bool,
+    map_page: Option<Arc<PageMapper>>,
+    format: PageFormat,
+    cipher: Cipher,
+    overflow_policy: OverflowPolicy,
+    page_creation_limit: Option<Arc<PageRateLimit>>,
+    async_waiters: Arc<AsyncWaiters>,
+    /// Held while writing the checkpoint, see `checkpoint`.
+    checkpoint_lock: Arc<Mutex<()>>,
+    ack_watermarks: AckWatermarks,
+    _cleanup: Option<Arc<DirCleanup>>,
 }
 
-const DATAPAGE_FILE_STEM: &str = ".dp.data.maxi";
+/// What to do when creating a page would reclaim one past `max_datapages`
+/// that attached consumers haven't finished reading.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Reclaim the page anyway; slow consumers lose its messages.
+    #[default]
+    DropOldest,
+    /// Refuse to create the page, `Sender::push` returns `Error::Full`.
+    DropNewest,
+    /// Wait until consumers have moved past the page.
+    Block,
+}
 
-impl DataPagesManager {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
-        // TODO: actually get the page count
-        let total_page_count = Self::load_total_page_count(path.as_ref())?;
-        let max_page_count = Self::load_max_page(path.as_ref())?;
+/// What to do when adding a page would go over
+/// `DataPagesManagerBuilder::page_creation_limit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThrottlePolicy {
+    /// Refuse to add the page, `Sender::push` returns `Error::RolloverThrottled`.
+    #[default]
+    Fail,
+    /// Wait until the page can be added.
+    Block,
+}
 
-        let mut init_pages = VecDeque::new();
-        for i in max_page_count.saturating_sub(total_page_count)..max_page_count + 1 {
-            init_pages.push_back(unsafe {
-                Arc::new(MmapCell::new_named(
-                    path.as_ref()
-                        .join(DATAPAGE_FILE_STEM)
-                        .with_extension(i.to_string()),
-                )?)
-            });
+/// Token bucket behind `DataPagesManagerBuilder::page_creation_limit`,
+/// shared by every clone of the manager.
+struct PageRateLimit {
+    per_second: u32,
+    policy: ThrottlePolicy,
+    /// Tokens left and when they were last topped up.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl PageRateLimit {
+    fn new(per_second: u32, policy: ThrottlePolicy) -> Self {
+        PageRateLimit {
+            per_second,
+            policy,
+            bucket: Mutex::new((per_second as f64, Instant::now())),
         }
+    }
 
-        Ok(DataPagesManager {
-            path: path.as_ref().into(),
-            max_datapages: Arc::new(AtomicUsize::new(usize::MAX)),
-            datapage_count: Arc::new(AtomicUsize::new(total_page_count)),
-            datapage_ring: Arc::new(RwLock::new(init_pages)),
-        })
+    /// Takes a token for a new page, or says how long until there is one.
+    fn try_take(&self) -> Result<(), Duration> {
+        let rate = self.per_second as f64;
+        let now = Instant::now();
+
+        let mut bucket = self.bucket.lock();
+        let (tokens, refilled) = &mut *bucket;
+        *tokens = (*tokens + now.duration_since(*refilled).as_secs_f64() * rate).min(rate);
+        *refilled = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - *tokens) / rate))
+        }
     }
+}
 
-    fn load_total_page_count<P: AsRef<Path>>(path: P) -> Result<usize, std::io::Error> {
-        Ok(std::fs::read_dir(path)?
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().file_stem() == Some(OsStr::new(DATAPAGE_FILE_STEM)))
-            .count())
+/// Removes the data directory once the last manager clone (and with it every
+/// sender and receiver) has been dropped.
+struct DirCleanup(PathBuf);
+
+impl Drop for DirCleanup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Tasks parked in `Receiver::pop_async`, each woken by the next push from
+/// this process onto the page it waits on.
+///
+/// Wakers are process local so unlike the futexes they can't live in the
+/// pages themselves.
+#[derive(Default)]
+struct AsyncWaiters {
+    waiting: AtomicUsize,
+    /// Page each waker waits on, and the waker.
+    wakers: Mutex<Vec<(usize, Waker)>>,
+}
+
+impl AsyncWaiters {
+    fn register(&self, page: usize, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+
+        if !wakers.iter().any(|(p, w)| *p == page && w.will_wake(waker)) {
+            wakers.push((page, waker.clone()));
+            self.waiting.store(wakers.len(), Ordering::SeqCst);
+        }
     }
 
-    fn load_max_page<P: AsRef<Path>>(path: P) -> Result<usize, std::io::Error> {
-        Ok(std::fs::read_dir(path)?
+    fn notify(&self, page: usize) {
+        // pairs with the waiter re-checking its slot after registering, so
+        // either it sees the push or we see it waiting. The futex wake that
+        // published the slot is a full barrier already, only without one
+        // the fence is needed here
+        if !cfg!(target_os = "linux") || futex_fallback() {
+            fence(Ordering::SeqCst);
+        }
+
+        if self.waiting.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let woken: Vec<_> = {
+            let mut wakers = self.wakers.lock();
+            let (woken, rest) = std::mem::take(&mut *wakers)
+                .into_iter()
+                .partition(|(p, _)| *p == page);
+            *wakers = rest;
+            self.waiting.store(wakers.len(), Ordering::SeqCst);
+
+            woken
+        };
+
+        for (_, waker) in woken {
+            waker.wake();
+        }
+    }
+}
+
+/// A snapshot of the manager's counters, see `DataPagesManager::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ManagerStats {
+    /// Pages currently held in the ring.
+    pub mapped_pages: usize,
+    pub datapage_count: usize,
+    pub max_datapages: usize,
+    /// Messages reserved on the tail page, see `DataPage::message_count`.
+    pub published_count: u32,
+    /// Bytes of `buf` reserved on the tail page, see `DataPage::bytes_used`.
+    pub bytes_used: u32,
+}
+
+/// What `DataPagesManager::verify` found wrong with a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageProblem {
+    /// The file isn't the size of a page. Its contents aren't checked.
+    Size {
+        len: u64,
+        expected: u64,
+    },
+    /// The header doesn't match the queue's format or the page's number,
+    /// see `Error::FormatMismatch`.
+    Format {
+        field: &'static str,
+        expected: u32,
+        found: u32,
+    },
+    Slot(SlotProblem),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of page files checked.
+    pub pages_checked: usize,
+    /// Every problem found, with the number of the page it's on, in page
+    /// order.
+    pub problems: Vec<(usize, PageProblem)>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+pub struct DataPagesManagerBuilder {
+    path: PathBuf,
+    topic: String,
+    file_stem: String,
+    cleanup_on_drop: bool,
+    format: PageFormat,
+    overflow_policy: OverflowPolicy,
+    page_creation_limit: Option<(u32, ThrottlePolicy)>,
+    heartbeat_ttl: Option<Duration>,
+    file_mode: Option<u32>,
+    preallocate: bool,
+    prefault: bool,
+    reuse_pages: bool,
+    release_reclaimed: bool,
+    consumer_creates_pages: bool,
+    sync_directory_on_create: bool,
+    map_page: Option<Arc<PageMapper>>,
+    size_histogram: bool,
+    cipher: Cipher,
+}
+
+impl DataPagesManagerBuilder {
+    /// Remove the whole data directory when the last clone of the manager drops.
+    ///
+    /// Meant for ephemeral queues (tests, scratch pipelines). Senders and
+    /// receivers hold a manager clone, so cleanup waits for them as well.
+    pub fn cleanup_on_drop(mut self, val: bool) -> Self {
+        self.cleanup_on_drop = val;
+        self
+    }
+
+    /// Number of receiver groups usable on this queue, at most `MAX_RECEIVER_GROUPS`
+    /// (set at build time through `DP_BUILD_MAX_RECEIVER_GROUPS`).
+    ///
+    /// The value is recorded in every page, so reopening a directory with a
+    /// different count fails with `Error::FormatMismatch`. So does opening
+    /// pages written by a build with another `MAX_RECEIVER_GROUPS`, since it
+    /// sizes the group counts every page has room for, or pages that hold
+    /// messages but have no header at all.
+    pub fn receiver_groups(mut self, val: usize) -> Self {
+        self.format.receiver_groups = val as u32;
+        self
+    }
+
+    /// Prefix every message with the wall-clock time it was pushed, readable
+    /// through `GenReceiver::pop_with_meta`. Costs 8 bytes per message.
+    ///
+    /// Recorded in every page, like `receiver_groups`.
+    pub fn timestamps(mut self, val: bool) -> Self {
+        self.format.timestamps = val;
+        self
+    }
+
+    /// Attach a `u64` key to every message, see `Sender::push_keyed` and
+    /// `Receiver::with_partitions`. Costs 8 bytes per message.
+    ///
+    /// Recorded in every page, like `receiver_groups`.
+    pub fn keys(mut self, val: bool) -> Self {
+        self.format.keys = val;
+        self
+    }
+
+    /// Compress message payloads with `codec`. Receivers then hand out
+    /// messages from a buffer of their own instead of straight from the page.
+    ///
+    /// Recorded in every page, like `receiver_groups`.
+    pub fn codec(mut self, val: Codec) -> Self {
+        self.format.codec = val;
+        self
+    }
+
+    /// Frame messages with a `u16` length instead of a `u32`, saving 2 bytes
+    /// per message for queues of small messages. Messages (timestamp and key
+    /// included) over `MAX_SHORT_MESSAGE_SIZE` bytes are then rejected with
+    /// `Error::MessageTooLarge`.
+    ///
+    /// Recorded in every page, like `receiver_groups`.
+    pub fn short_lengths(mut self, val: bool) -> Self {
+        self.format.short_lengths = val;
+        self
+    }
+
+    /// Encrypt message payloads at rest with AES-256-GCM under `key`. The
+    /// timestamp and key of each message stay readable but are authenticated
+    /// along with the payload, which costs 16 bytes per message. Receivers
+    /// then hand out messages from a buffer of their own, like with a codec.
+    ///
+    /// Nonces come from each message's page and count, so a key must only
+    /// ever be used for a single queue (topic and directory) and must be
+    /// replaced if the directory is emptied and the queue starts over.
+    /// `DataPagesManager::compact` is refused for the same reason. Reading
+    /// with the wrong key fails with `Error::Decrypt`.
+    ///
+    /// Whether pages are encrypted is recorded in every page, like
+    /// `receiver_groups`, so plaintext and encrypted pages never mix; the key
+    /// itself is never written anywhere. Needs the `encryption` feature.
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.format.encrypted = true;
+        self.cipher = Cipher::new(&key);
+        self
+    }
+
+    /// What to do when creating a page would reclaim one past
+    /// `max_datapages` that a group hasn't finished reading: drop it anyway
+    /// (`OverflowPolicy::DropOldest`, the default), refuse the new page
+    /// (`DropNewest`) or wait for the group to move on (`Block`).
+    ///
+    /// Only groups `DataPagesManager::is_group_alive` considers alive hold
+    /// pages back: those with consumers attached in this process or, with a
+    /// `heartbeat_ttl`, those heartbeating from any process.
+    pub fn overflow_policy(mut self, val: OverflowPolicy) -> Self {
+        self.overflow_policy = val;
+        self
+    }
+
+    /// Add at most `per_second` pages a second, on average, as a safety valve
+    /// against a bug or a flood of tiny messages creating page files as fast
+    /// as the filesystem allows. Past the limit `policy` decides whether
+    /// adding a page waits or fails with `Error::RolloverThrottled`.
+    ///
+    /// Bursts of up to `per_second` pages go through at once. Only worth
+    /// setting where messages are big enough that a sane workload never
+    /// comes close to the limit: it slows down or fails pushes, not just the
+    /// runaway ones. Counts the pages this manager and its clones add, not
+    /// those added by other processes.
+    pub fn page_creation_limit(mut self, per_second: u32, policy: ThrottlePolicy) -> Self {
+        self.page_creation_limit = Some((per_second, policy));
+        self
+    }
+
+    /// Treat a group as abandoned once none of its receivers has called
+    /// `Receiver::heartbeat` for `ttl`, so it no longer holds pages back
+    /// under `OverflowPolicy::DropNewest`/`Block`.
+    ///
+    /// Heartbeats are kept in a file next to the pages, so a group whose
+    /// receivers live in another process counts as alive here too. With a
+    /// TTL only heartbeats decide: a group stays alive for `ttl` after its
+    /// last one, even once its receivers are gone. Every process on the
+    /// directory should use the same `ttl`, and their clocks have to agree.
+    ///
+    /// A group that comes back after being declared dead keeps reading the
+    /// page it holds (its mapping stays valid even if the file was deleted)
+    /// and then continues from the oldest retained page: whatever was
+    /// reclaimed in between is lost to it, but nothing is reclaimed twice
+    /// since reclamation only ever pops the front of the ring.
+    pub fn heartbeat_ttl(mut self, ttl: Duration) -> Self {
+        self.heartbeat_ttl = Some(ttl);
+        self
+    }
+
+    /// Create page files with permission bits `mode` (e.g. `0o660`) instead
+    /// of leaving them to the process umask, and apply the same mode to the
+    /// data directory, with execute added wherever read is set.
+    ///
+    /// Only affects files this manager creates; existing pages keep their mode.
+    #[cfg(unix)]
+    pub fn file_mode(mut self, mode: u32) -> Self {
+        self.file_mode = Some(mode);
+        self
+    }
+
+    /// Reserve every new page's blocks on disk up front instead of leaving
+    /// the page file sparse.
+    ///
+    /// Sparse pages (the default) only take up disk space as messages are
+    /// written, but the first write to each block pays for allocating it
+    /// inside `push`. Preallocated pages cost the full page size on disk as
+    /// soon as they're created, even if they end up mostly empty, and creating
+    /// one takes longer. Only has an effect on Linux.
+    ///
+    /// Needed to survive a full disk: a push to a sparse page writes to
+    /// mapped memory, so a block the filesystem can't allocate raises
+    /// `SIGBUS` and kills the process. With preallocation it's page creation
+    /// that fails instead, with `Error::DiskFull`.
+    pub fn preallocate(mut self, val: bool) -> Self {
+        self.preallocate = val;
+        self
+    }
+
+    /// Touch every page of memory of a new page's message buffer right after
+    /// creating it, so the pushes filling it don't take a page fault each
+    /// time they reach untouched memory.
+    ///
+    /// Moves the faults of a whole page to the push that rolls onto it
+    /// (see `DataPage::prefault`), which then takes longer, in exchange for
+    /// even latency on the pushes after it. `reserve` can take them off the
+    /// hot path altogether. Pages that already exist when mapped aren't
+    /// touched.
+    ///
+    /// Touching means writing, so it costs what writing the whole page would:
+    /// all of the page's memory is resident and dirty from the start, and
+    /// writeback allocates and writes out every block of the file, leaving it
+    /// as large on disk as a preallocated page.
+    pub fn prefault(mut self, val: bool) -> Self {
+        self.prefault = val;
+        self
+    }
+
+    /// Turn the file of a page reclaimed to make room for a new one into the
+    /// new page, renaming it and clearing it in place, instead of deleting it
+    /// and creating a new file. Saves the file creation and, with
+    /// `preallocate`, allocating the blocks again.
+    ///
+    /// Only for directories a single process uses: another process's mapping
+    /// of the old page would be cleared under it. Within the process a page
+    /// some sender or receiver still holds is deleted as usual, as are pages
+    /// while an `on_reclaim` hook is set, since it may have linked the file.
+    pub fn reuse_pages(mut self, val: bool) -> Self {
+        self.reuse_pages = val;
+        self
+    }
+
+    /// Map page files with `f` instead of `DataPage::open`, e.g. to pick the
+    /// mmap flags, advise the kernel or place the memory yourself.
+    ///
+    /// `f` gets the path of an existing page file that's already the size of
+    /// a `DataPage` and has to map all of it, writable and shared with other
+    /// mappings of the file, or fail with `NotFound` if the file is gone. It
+    /// must not create files. New pages are still created, sized and set up
+    /// by the manager, `f` maps them under their temporary name before they
+    /// take their place in the directory.
+    pub fn map_page(
+        mut self,
+        f: impl Fn(&Path) -> std::io::Result<MmapCell<DataPage>> + Send + Sync + 'static,
+    ) -> Self {
+        self.map_page = Some(Arc::new(f));
+        self
+    }
+
+    /// Drop this process's resident memory for a reclaimed page that some
+    /// sender or receiver still holds, with `madvise(MADV_DONTNEED)`, instead
+    /// of keeping it until the last of them moves on. Only has an effect on
+    /// Linux.
+    ///
+    /// Safe with readers still on the page: pages are shared file mappings,
+    /// so a reader faults the same contents back in from the deleted file on
+    /// its next access, at the cost of that fault. A page nothing else holds
+    /// is unmapped on reclamation anyway.
+    ///
+    /// Skipped while a `map_page` mapper is set: its memory needn't be a
+    /// shared file mapping, and dropping private or anonymous memory zeroes it
+    /// under the readers.
+    pub fn release_reclaimed(mut self, val: bool) -> Self {
+        self.release_reclaimed = val;
+        self
+    }
+
+    /// Whether a receiver moved past the newest page, with
+    /// `Receiver::<Anonymous>::seek`, `Receiver::<Grouped>::resume_committed`
+    /// or `Receiver::<Grouped>::from_cursor`, creates the next page itself
+    /// (the default) or waits for a producer to create it.
+    ///
+    /// Turn it off where only producers should ever add pages, e.g. when
+    /// they run elsewhere with their own manager, so readers never leave
+    /// empty pages ahead of the writers. Receivers reaching the end of a page
+    /// while popping always wait for the next one either way.
+    pub fn consumer_creates_pages(mut self, val: bool) -> Self {
+        self.consumer_creates_pages = val;
+        self
+    }
+
+    /// Fsync the data directory whenever a page file is created (or a
+    /// reclaimed one renamed into place with `reuse_pages`), before the page
+    /// is used, so its directory entry survives a crash of the machine along
+    /// with the messages later flushed to it.
+    ///
+    /// The checkpoint written after each new page already syncs the
+    /// directory, but only logs a warning if that fails and doesn't cover
+    /// the first page created when opening an empty directory. With this on
+    /// a failed sync fails the push that created the page instead. Costs one
+    /// more directory fsync per new page, which is cheap next to a page's
+    /// worth of pushes but can take milliseconds on spinning disks.
+    pub fn sync_directory_on_create(mut self, val: bool) -> Self {
+        self.sync_directory_on_create = val;
+        self
+    }
+
+    /// Count the sizes of pushed messages, see `DataPagesManager::size_histogram`.
+    /// Costs each push an atomic increment.
+    pub fn size_histogram(mut self, val: bool) -> Self {
+        self.size_histogram = val;
+        self
+    }
+
+    /// Keep this queue's files apart from other topics in the same directory
+    /// by prefixing them with `topic` (e.g. `<topic>.dp.data.maxi.<n>`).
+    ///
+    /// Each topic has its own pages, group counts and committed offsets; see
+    /// `DataPagesManager::topics` to list them. `cleanup_on_drop` still removes
+    /// the whole directory, other topics included.
+    pub fn topic(mut self, topic: &str) -> Self {
+        self.topic = topic.into();
+        self
+    }
+
+    /// Name page files `<stem>.<n>` (e.g. `mystream.page` for
+    /// `mystream.page.<n>`) instead of `.dp.data.maxi.<n>`, after the topic
+    /// if there is one.
+    ///
+    /// Every open of the queue has to use the same stem, pages under another
+    /// stem aren't seen at all. The same goes for `repair` and `topics`.
+    pub fn file_stem(mut self, stem: &str) -> Self {
+        self.file_stem = stem.into();
+        self
+    }
+
+    pub fn build(self) -> Result<DataPagesManager, Error> {
+        self.check_names()?;
+
+        if self
+            .page_creation_limit
+            .is_some_and(|(per_second, _)| per_second == 0)
+        {
+            return Err(Error::InvalidConfig(
+                "page creation limit must allow at least one page a second",
+            ));
+        }
+
+        let groups = self.format.receiver_groups as usize;
+        if groups == 0 || groups > MAX_RECEIVER_GROUPS {
+            return Err(Error::InvalidConfig(
+                "receiver_groups must be between 1 and MAX_RECEIVER_GROUPS",
+            ));
+        }
+
+        let mut manager = DataPagesManager::open(&self)?;
+
+        if self.cleanup_on_drop {
+            manager._cleanup = Some(Arc::new(DirCleanup(self.path)));
+        }
+
+        Ok(manager)
+    }
+
+    /// Checks every page file of this queue and recreates a truncated newest
+    /// page, which is what a crash while creating it leaves behind. Returns
+    /// whether anything had to be repaired. Only the topic and file stem are
+    /// used from the builder.
+    ///
+    /// A truncated page anywhere else can't be fixed without losing messages
+    /// and fails with `Error::TruncatedPage`. No manager may have the queue
+    /// open while this runs.
+    pub fn repair(self) -> Result<bool, Error> {
+        self.check_names()?;
+
+        let path = &self.path;
+        let page_stem = format!("{}{}", self.topic, self.file_stem);
+
+        let min_page = DataPagesManager::load_min_page(path, &page_stem)?;
+        let max_page = DataPagesManager::load_max_page(path, &page_stem)?;
+
+        for i in min_page..=max_page {
+            match check_page_len(&datapage_path(path, &page_stem, i)) {
+                Err(Error::TruncatedPage { path, .. }) if i == max_page => {
+                    std::fs::remove_file(path)?;
+                    return Ok(true);
+                }
+                Err(e) => return Err(e),
+                Ok(()) => {}
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Topics with at least one page under this builder's file stem in its
+    /// directory, sorted. The default topic (no `topic`) isn't listed, nor
+    /// is the builder's own topic used.
+    pub fn topics(self) -> Result<Vec<String>, Error> {
+        self.check_names()?;
+
+        let mut topics: Vec<String> = std::fs::read_dir(&self.path)?
             .filter_map(|e| e.ok())
-            .filter(|e| e.path().file_stem() == Some(OsStr::new(DATAPAGE_FILE_STEM)))
             .filter_map(|e| {
-                e.path()
-                    .extension()
-                    .and_then(|s| s.to_str().and_then(|s| s.parse().ok()))
+                let name = e.file_name();
+                let (stem, _) = parse_datapage_name(name.to_str()?)?;
+                let topic = stem.strip_suffix(&*self.file_stem)?;
+
+                (!topic.is_empty() && !topic.contains('.')).then(|| topic.to_owned())
             })
-            .max()
-            .unwrap_or(0))
+            .collect();
+
+        topics.sort_unstable();
+        topics.dedup();
+
+        Ok(topics)
     }
 
-    pub fn set_max_datapages(&mut self, val: usize) {
-        let _dp = self.datapage_ring.write();
-        self.max_datapages.store(val, Ordering::Relaxed);
+    /// Rejects a topic or file stem that would put files outside the
+    /// directory or clash with the queue's other files.
+    fn check_names(&self) -> Result<(), Error> {
+        if self.topic.contains(['/', '\\', '.']) {
+            return Err(Error::InvalidConfig(
+                "topic can't contain path separators or dots",
+            ));
+        }
+
+        if self.file_stem.is_empty() || self.file_stem.contains(['/', '\\']) {
+            return Err(Error::InvalidConfig(
+                "file stem must be non-empty without path separators",
+            ));
+        }
+
+        if [OFFSET_FILE_STEM, PROBE_FILE_STEM].contains(&&*self.file_stem) {
+            return Err(Error::InvalidConfig(
+                "file stem is already used for other files",
+            ));
+        }
+
+        Ok(())
     }
+}
 
-    pub fn get_max_datapages(&self) -> usize {
-        self.max_datapages.load(Ordering::Relaxed)
+/// Counts a grouped receiver as attached to its group for as long as it lives.
+///
+/// Cloning the guard attaches another consumer and dropping it detaches one,
+/// so the count follows `Receiver` clones without a manual `Drop` on the receiver.
+pub(crate) struct ConsumerGuard {
+    group: usize,
+    group_consumers: Arc<[AtomicUsize; MAX_RECEIVER_GROUPS]>,
+}
+
+impl ConsumerGuard {
+    pub(crate) fn group(&self) -> usize {
+        self.group
     }
+}
 
-    pub fn get_last_datapage(&self) -> Result<(usize, Arc<MmapCell<DataPage>>), std::io::Error> {
-        let datapages = self.datapage_ring.read();
-        let last_datapage = datapages
-            .back()
-            .ok_or(std::io::Error::other("DataPage not found"))?;
+impl Clone for ConsumerGuard {
+    fn clone(&self) -> Self {
+        self.group_consumers[self.group].fetch_add(1, Ordering::Relaxed);
 
-        let dp_count = self.datapage_count.load(Ordering::Relaxed);
-        Ok((dp_count, last_datapage.clone()))
+        ConsumerGuard {
+            group: self.group,
+            group_consumers: self.group_consumers.clone(),
+        }
+    }
+}
+
+impl Drop for ConsumerGuard {
+    fn drop(&mut self) {
+        self.group_consumers[self.group].fetch_sub(1, Ordering::Relaxed);
     }
+}
 
-    pub fn get_or_create_datapage(
-        &self,
-        num: usize,
-    ) -> Result<(usize, Arc<MmapCell<DataPage>>), std::io::Error> {
-        let mut datapages = self.datapage_ring.upgradable_read();
-        let dp_count = self.datapage_count.load(Ordering::Relaxed);
-        let max_dps = self.max_datapages.load(Ordering::Relaxed);
+/// Messages of one group that receivers in this process popped with
+/// `Receiver::pop_commit_after` and haven't acked, so one receiver's `ack`
+/// never commits past a message another is still handling.
+#[derive(Default)]
+struct AckWatermark {
+    /// Global index of the oldest unacked message of each receiver that has
+    /// any, with how many receivers hold it.
+    outstanding: BTreeMap<u64, usize>,
+    /// Offset past the newest message acked so far.
+    acked: u64,
+    /// Offset last committed by an `ack`.
+    committed: u64,
+}
 
-        if num > dp_count {
-            return datapages.with_upgraded(|datapages| {
-                let dp_count = self.datapage_count.fetch_add(1, Ordering::Relaxed) + 1;
-                let max_dps = self.max_datapages.load(Ordering::Relaxed);
+impl AckWatermark {
+    fn release(&mut self, index: u64) {
+        if let Some(holders) = self.outstanding.get_mut(&index) {
+            *holders -= 1;
+            if *holders == 0 {
+                self.outstanding.remove(&index);
+            }
+        }
+    }
+}
 
-                if dp_count >= max_dps {
-                    std::fs::remove_file(
-                        self.path
-                            .join(DATAPAGE_FILE_STEM)
-                            .with_extension((dp_count - max_dps).to_string()),
-                    )?;
+type AckWatermarks = Arc<Mutex<HashMap<usize, AckWatermark>>>;
 
-                    let _ = datapages.pop_front();
-                }
+/// Keeps `DataPagesManager::ack` from committing its group's offset past the
+/// message at `index`, until it's passed back to `ack` or dropped.
+pub(crate) struct AckHold {
+    group: usize,
+    index: Option<u64>,
+    watermarks: AckWatermarks,
+}
 
-                datapages.push_back(Arc::new(DataPage::new(
-                    self.path
-                        .join(DATAPAGE_FILE_STEM)
-                        .with_extension(dp_count.to_string()),
-                )?));
+impl AckHold {
+    /// Moves the hold to `index`, e.g. from a lower bound taken before the
+    /// message was claimed to the message itself.
+    pub(crate) fn advance(&mut self, index: u64) {
+        let mut watermarks = self.watermarks.lock();
+        let watermark = watermarks.entry(self.group).or_default();
 
-                Ok::<(usize, Arc<MmapCell<DataPage>>), std::io::Error>((
-                    dp_count,
-                    datapages[dp_count % max_dps].clone(),
-                ))
-            });
+        if let Some(old) = self.index.replace(index) {
+            watermark.release(old);
+        }
+        *watermark.outstanding.entry(index).or_default() += 1;
+    }
+}
+
+impl Drop for AckHold {
+    fn drop(&mut self) {
+        if let Some(index) = self.index.take() {
+            self.watermarks
+                .lock()
+                .entry(self.group)
+                .or_default()
+                .release(index);
+        }
+    }
+}
+
+const DATAPAGE_FILE_STEM: &str = ".dp.data.maxi";
+/// What pages were named before they had a header, see `migrate_legacy_pages`.
+const LEGACY_DATAPAGE_FILE_STEM: &str = ".dp.data";
+const OFFSET_FILE_STEM: &str = ".dp.offset.maxi";
+/// Goes in front of the names of `commit_named` offsets so they can't be
+/// taken for a group's.
+const NAMED_OFFSET_PREFIX: &str = "name.";
+const PROBE_FILE_STEM: &str = ".dp.probe.maxi";
+const CHECKPOINT_FILE_SUFFIX: &str = ".checkpoint";
+/// Compacted pages are written as `<page_stem>.compact.<n>` before taking
+/// the place of the pages they replace.
+const COMPACT_FILE_SUFFIX: &str = ".compact";
+/// Marks a compaction whose pages are all written, see `finish_compaction`.
+const COMPACTION_FILE_SUFFIX: &str = ".compacting";
+const CONTROL_FILE_SUFFIX: &str = ".control";
+const HEARTBEAT_FILE_SUFFIX: &str = ".heartbeats";
+
+/// Buckets of `DataPagesManager::size_histogram`, enough for any `u32` length.
+pub const SIZE_BUCKETS: usize = 33;
+
+const BLOCK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+fn datapage_path(dir: &Path, page_stem: &str, num: usize) -> PathBuf {
+    dir.join(format!("{page_stem}.{num}"))
+}
+
+/// `<page_stem>.checkpoint`, which isn't a page name since it doesn't end in
+/// a number.
+fn checkpoint_path(dir: &Path, page_stem: &str) -> PathBuf {
+    dir.join(format!("{page_stem}{CHECKPOINT_FILE_SUFFIX}"))
+}
+
+/// Records the retained pages, `first..=last`, so opening the queue doesn't
+/// have to scan the directory for them. Written to a temporary file that is
+/// synced and renamed over the checkpoint, so a crash leaves either the old
+/// or the new range.
+fn write_checkpoint(dir: &Path, page_stem: &str, first: usize, last: usize) -> std::io::Result<()> {
+    write_fields(
+        dir,
+        &checkpoint_path(dir, page_stem),
+        &[first, last, last - first + 1],
+    )
+}
+
+/// Writes `fields` to `path`, in `dir`, as little endian `u64`s through a
+/// temporary file that is synced and renamed over it, so a crash leaves
+/// either the old or the new contents.
+fn write_fields(dir: &Path, path: &Path, fields: &[usize]) -> std::io::Result<()> {
+    let mut tmp_name = path
+        .file_name()
+        .expect("paths written end in a file name")
+        .to_owned();
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = std::fs::File::create(&tmp_path)?;
+    for &field in fields {
+        file.write_all(&(field as u64).to_le_bytes())?;
+    }
+    file.sync_all()?;
+
+    std::fs::rename(&tmp_path, path)?;
+    sync_dir(dir)
+}
+
+/// Moves the pages left in `dir` by versions from before pages had a header,
+/// named `.dp.data.<n>`, to `<page_stem>.<n>` in the current layout.
+///
+/// Each page is rewritten into a temporary file that is synced and linked in
+/// under its new name before the old file is removed, so after a crash the
+/// old page is simply migrated again. An old page whose new name is taken
+/// already is left alone.
+fn migrate_legacy_pages(
+    dir: &Path,
+    page_stem: &str,
+    format: &PageFormat,
+    file_mode: Option<u32>,
+) -> Result<(), Error> {
+    let legacy_pages = DataPagesManager::load_page_numbers(dir, LEGACY_DATAPAGE_FILE_STEM)?;
+
+    for &num in &legacy_pages {
+        let legacy_path = datapage_path(dir, LEGACY_DATAPAGE_FILE_STEM, num);
+        let path = datapage_path(dir, page_stem, num);
+        if path.exists() {
+            continue;
+        }
+
+        let legacy = std::fs::read(&legacy_path)?;
+        if legacy.len() != LEGACY_PAGE_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{legacy_path:?} is {} bytes, not a page of this build",
+                    legacy.len()
+                ),
+            )
+            .into());
+        }
+
+        let mut tmp_name = path
+            .file_name()
+            .expect("page paths end in a file name")
+            .to_owned();
+        tmp_name.push(format!(".{}.tmp", std::process::id()));
+        let tmp_path = path.with_file_name(tmp_name);
+
+        let migrated = (|| {
+            if let Some(mode) = file_mode {
+                create_with_mode(&tmp_path, mode)?;
+            }
+
+            let datapage = DataPage::new(&tmp_path)?;
+            datapage
+                .get_mut()
+                .migrate_legacy(&legacy, num, format)
+                .map_err(|e| Error::FormatMismatch {
+                    path: legacy_path.clone(),
+                    field: e.field,
+                    expected: e.expected,
+                    found: e.found,
+                })?;
+            sync_page(&tmp_path, &datapage)?;
+
+            std::fs::hard_link(&tmp_path, &path).map_err(Error::from)
+        })();
+        let _ = std::fs::remove_file(&tmp_path);
+
+        match migrated {
+            // another process migrated it first
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            migrated => migrated?,
+        }
+        std::fs::remove_file(&legacy_path)?;
+    }
+
+    if !legacy_pages.is_empty() {
+        sync_dir(dir)?;
+    }
+
+    Ok(())
+}
+
+/// Stem of the pages `DataPagesManager::compact` writes for `page_stem`,
+/// which never parses as one of its pages.
+fn compact_stem(page_stem: &str) -> String {
+    format!("{page_stem}{COMPACT_FILE_SUFFIX}")
+}
+
+fn compaction_marker_path(dir: &Path, page_stem: &str) -> PathBuf {
+    dir.join(format!("{page_stem}{COMPACTION_FILE_SUFFIX}"))
+}
+
+/// Finishes a compaction of the `page_stem` pages in `dir` that got as far
+/// as writing its marker, returning whether there was one.
+///
+/// The marker records the old pages, `first..=last`, and how many compacted
+/// pages replace them, and is only written once every compacted page is on
+/// disk. From there each step can be repeated after a crash: compacted pages
+/// still under their temporary name are renamed over the old page of the
+/// same number, the old pages past the compacted ones are deleted and the
+/// marker goes last.
+fn finish_compaction(dir: &Path, page_stem: &str) -> Result<bool, Error> {
+    let marker = compaction_marker_path(dir, page_stem);
+    let bytes = match std::fs::read(&marker) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    let fields: [u8; 24] = bytes.try_into().map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt compaction marker")
+    })?;
+    let field = |i: usize| u64::from_le_bytes(fields[i * 8..][..8].try_into().unwrap()) as usize;
+    let (first, last, compacted) = (field(0), field(1), field(2));
+
+    let compact_stem = compact_stem(page_stem);
+    for i in 0..compacted {
+        match std::fs::rename(
+            datapage_path(dir, &compact_stem, i),
+            datapage_path(dir, page_stem, i),
+        ) {
+            // renamed before the crash
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+            Ok(()) => {}
+        }
+    }
+
+    for i in first.max(compacted)..=last {
+        match std::fs::remove_file(datapage_path(dir, page_stem, i)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    // still describes the old pages
+    match std::fs::remove_file(checkpoint_path(dir, page_stem)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    sync_dir(dir)?;
+    std::fs::remove_file(&marker)?;
+    sync_dir(dir)?;
+
+    Ok(true)
+}
+
+/// Makes the directory's entries, e.g. a page just linked into place, durable.
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::File::open(dir)?.sync_all()
+}
+
+/// The range in the checkpoint, if there is one that still matches the
+/// pages on disk: both ends exist and the pages right outside them don't.
+/// Anything else, e.g. a checkpoint another process's pages outran, means
+/// the directory has to be scanned.
+fn read_checkpoint(dir: &Path, page_stem: &str) -> Option<(usize, usize)> {
+    let bytes = std::fs::read(checkpoint_path(dir, page_stem)).ok()?;
+    let fields: [u8; 24] = bytes.try_into().ok()?;
+    let field = |i: usize| {
+        let field = u64::from_le_bytes(fields[i * 8..][..8].try_into().unwrap());
+        usize::try_from(field).ok()
+    };
+    let (first, last, count) = (field(0)?, field(1)?, field(2)?);
+
+    if first > last || count != last - first + 1 {
+        return None;
+    }
+
+    let exists = |num: usize| datapage_path(dir, page_stem, num).exists();
+    let before_first = first.checked_sub(1).is_some_and(exists);
+    (exists(first) && exists(last) && !exists(last + 1) && !before_first).then_some((first, last))
+}
+
+/// Splits a file name of the form `<page_stem>.<n>` into its stem and page
+/// number. Anything else, temp files included, is not a page.
+fn parse_datapage_name(name: &str) -> Option<(&str, usize)> {
+    let (stem, num) = name.rsplit_once('.')?;
+
+    if num.is_empty() || !num.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((stem, num.parse().ok()?))
+}
+
+/// Fails with `TruncatedPage` if the page file at `path` exists but is too short
+/// to hold a whole `DataPage`. Mapping it anyway would silently grow it back
+/// with zeros where messages used to be.
+fn check_page_len(path: &Path) -> Result<(), Error> {
+    let expected = size_of::<DataPage>() as u64;
+
+    match std::fs::metadata(path) {
+        Ok(m) if m.len() < expected => Err(Error::TruncatedPage {
+            path: path.into(),
+            len: m.len(),
+            expected,
+        }),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Maps page `num` at `path`, creating it if there's no such file.
+fn map_datapage(
+    path: PathBuf,
+    num: usize,
+    format: &PageFormat,
+    file_mode: Option<u32>,
+    preallocate: bool,
+    prefault: bool,
+    map_page: Option<&PageMapper>,
+) -> Result<MmapCell<DataPage>, Error> {
+    check_page_len(&path)?;
+
+    let opened = match map_page {
+        Some(f) => f(&path),
+        None => DataPage::open(&path),
+    };
+
+    match opened {
+        Ok(datapage) => {
+            check_format(path, datapage.get(), num, format)?;
+            Ok(datapage)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => create_datapage(
+            path,
+            num,
+            format,
+            file_mode,
+            preallocate,
+            prefault,
+            map_page,
+        ),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Creates page `num` in a temporary file and only links it in at `path`
+/// once it's sized and initialized, so managers in other processes never
+/// map a partly created page. If one of them created the page first, its
+/// page is mapped instead.
+fn create_datapage(
+    path: PathBuf,
+    num: usize,
+    format: &PageFormat,
+    file_mode: Option<u32>,
+    preallocate: bool,
+    prefault: bool,
+    map_page: Option<&PageMapper>,
+) -> Result<MmapCell<DataPage>, Error> {
+    static TMP_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+    // ends in `.tmp` rather than a number, so it's never taken for a page
+    let mut tmp_name = path
+        .file_name()
+        .expect("page paths end in a file name")
+        .to_owned();
+    tmp_name.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        TMP_SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let created = (|| {
+        if let Some(mode) = file_mode {
+            create_with_mode(&tmp_path, mode)?;
         }
 
-        let dp_count = num.max(dp_count.saturating_sub(max_dps));
+        let datapage = match map_page {
+            Some(f) => {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&tmp_path)?
+                    .set_len(size_of::<DataPage>() as u64)?;
+                f(&tmp_path)?
+            }
+            None => DataPage::new(&tmp_path)?,
+        };
+
+        if preallocate {
+            preallocate_file(&tmp_path)?;
+        }
+        if prefault {
+            datapage.get_mut().prefault();
+        }
+
+        check_format(tmp_path.clone(), datapage.get(), num, format)?;
+        std::fs::hard_link(&tmp_path, &path)?;
+
+        Ok(datapage)
+    })();
+    let _ = std::fs::remove_file(&tmp_path);
+
+    match created {
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists => map_datapage(
+            path,
+            num,
+            format,
+            file_mode,
+            preallocate,
+            prefault,
+            map_page,
+        ),
+        created => created,
+    }
+}
+
+/// Initializes a fresh page or checks an existing one, see `DataPage::init_format`.
+fn check_format(
+    path: PathBuf,
+    datapage: &DataPage,
+    num: usize,
+    format: &PageFormat,
+) -> Result<(), Error> {
+    datapage
+        .init_format(num, format)
+        .map_err(|e| Error::FormatMismatch {
+            path,
+            field: e.field,
+            expected: e.expected,
+            found: e.found,
+        })
+}
+
+/// Maps `<page_stem><suffix>`, a file every manager on the directory shares,
+/// creating it zero filled if it isn't there yet. That has to be a valid `T`.
+fn map_shared<T>(
+    dir: &Path,
+    page_stem: &str,
+    suffix: &str,
+    file_mode: Option<u32>,
+) -> Result<MmapCell<T>, Error> {
+    let path = dir.join(format!("{page_stem}{suffix}"));
+
+    if let Some(mode) = file_mode {
+        create_with_mode(&path, mode)?;
+    }
+
+    Ok(unsafe { MmapCell::new_named(path)? })
+}
+
+/// Milliseconds since the unix epoch, what heartbeats are stamped with.
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Creates an empty file at `path` with exactly `mode`, unless it already exists.
+#[cfg(unix)]
+fn create_with_mode(path: &Path, mode: u32) -> Result<(), std::io::Error> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path);
+
+    match file {
+        // the mode passed to open is still masked by the umask
+        Ok(file) => file.set_permissions(std::fs::Permissions::from_mode(mode)),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+fn create_with_mode(_path: &Path, _mode: u32) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+/// Allocates the blocks backing the whole page file at `path`.
+#[cfg(target_os = "linux")]
+fn preallocate_file(path: &Path) -> Result<(), std::io::Error> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::OpenOptions::new().write(true).open(path)?;
+    let len = size_of::<DataPage>() as libc::off_t;
+
+    // returns the error instead of setting errno
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len) } {
+        0 => Ok(()),
+        errno => Err(std::io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn preallocate_file(_path: &Path) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+/// Reads the length in front of the next message of an `export`, or `None`
+/// if `input` ended right before it.
+fn read_len<R: Read>(input: &mut R) -> Result<Option<u32>, std::io::Error> {
+    let mut len = [0; 4];
+    let mut read = 0;
+
+    while read < len.len() {
+        match input.read(&mut len[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Some(u32::from_le_bytes(len)))
+}
+
+/// Drops this process's resident pages of `datapage`'s mapping, see
+/// `DataPagesManagerBuilder::release_reclaimed`.
+#[cfg(target_os = "linux")]
+fn release_memory(datapage: &MmapCell<DataPage>) {
+    // mappings start page aligned
+    let addr = datapage.get() as *const DataPage as *mut libc::c_void;
+
+    if unsafe { libc::madvise(addr, size_of::<DataPage>(), libc::MADV_DONTNEED) } != 0 {
+        tracing::warn!(
+            error = %std::io::Error::last_os_error(),
+            "failed to release a reclaimed page's memory",
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn release_memory(_datapage: &MmapCell<DataPage>) {}
+
+/// Bytes an unprivileged process can still write to the filesystem holding `path`.
+#[cfg(target_os = "linux")]
+fn available_space(path: &Path) -> Result<u64, std::io::Error> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let stat = unsafe { stat.assume_init() };
+
+    // both are narrower than u64 on some targets
+    #[allow(clippy::unnecessary_cast)]
+    Ok((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+// no portable free space query, the probe file has to do
+#[cfg(not(target_os = "linux"))]
+fn available_space(_path: &Path) -> Result<u64, std::io::Error> {
+    Ok(u64::MAX)
+}
+
+/// Writes the dirty parts of `datapage`, the mapping of `path`, back to disk.
+#[cfg(target_os = "linux")]
+fn sync_page(_path: &Path, datapage: &MmapCell<DataPage>) -> Result<(), std::io::Error> {
+    let addr = datapage.get() as *const DataPage as *mut libc::c_void;
+
+    // SAFETY: the page is mapped as a whole for as long as `datapage` lives
+    match unsafe { libc::msync(addr, size_of::<DataPage>(), libc::MS_SYNC) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sync_page(path: &Path, _datapage: &MmapCell<DataPage>) -> Result<(), std::io::Error> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .sync_all()
+}
+
+impl std::fmt::Debug for DataPagesManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // may be printed from an `on_reclaim` hook, which runs with the ring
+        // locked for writing
+        let ring_len = self.datapage_ring.try_read().map(|ring| ring.len());
+
+        let mut s = f.debug_struct("DataPagesManager");
+        s.field("path", &self.path)
+            .field(
+                "datapage_count",
+                &self.datapage_count.load(Ordering::Relaxed),
+            )
+            .field("max_datapages", &self.max_datapages.load(Ordering::Relaxed));
+        match ring_len {
+            Some(len) => s.field("ring_len", &len),
+            None => s.field("ring_len", &format_args!("<locked>")),
+        };
+        s.finish_non_exhaustive()
+    }
+}
+
+impl DataPagesManager {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::builder(path).build()
+    }
+
+    /// Opens `topic` in `path`, see `DataPagesManagerBuilder::topic`.
+    pub fn new_topic<P: AsRef<Path>>(path: P, topic: &str) -> Result<Self, Error> {
+        Self::builder(path).topic(topic).build()
+    }
+
+    pub fn builder<P: AsRef<Path>>(path: P) -> DataPagesManagerBuilder {
+        DataPagesManagerBuilder {
+            path: path.as_ref().into(),
+            topic: String::new(),
+            file_stem: DATAPAGE_FILE_STEM.into(),
+            cleanup_on_drop: false,
+            format: PageFormat::default(),
+            overflow_policy: OverflowPolicy::default(),
+            page_creation_limit: None,
+            heartbeat_ttl: None,
+            file_mode: None,
+            preallocate: false,
+            prefault: false,
+            reuse_pages: false,
+            release_reclaimed: false,
+            consumer_creates_pages: true,
+            sync_directory_on_create: false,
+            map_page: None,
+            size_histogram: false,
+            cipher: Cipher::default(),
+        }
+    }
+
+    fn open(options: &DataPagesManagerBuilder) -> Result<Self, Error> {
+        let path = &options.path;
+        probe_futex();
+
+        #[cfg(unix)]
+        if let Some(mode) = options.file_mode {
+            use std::os::unix::fs::PermissionsExt;
+
+            let dir_mode = mode | (mode & 0o444) >> 2;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(dir_mode))?;
+        }
+
+        let page_stem = format!("{}{}", options.topic, options.file_stem);
+        // only the default topic and stem existed back then
+        if page_stem == DATAPAGE_FILE_STEM {
+            migrate_legacy_pages(path, &page_stem, &options.format, options.file_mode)?;
+        }
+        let compacted = finish_compaction(path, &page_stem)?;
+
+        // the ring runs from the oldest page on disk to the newest, with
+        // pages deleted in between (see `delete_page`) left as gaps. An empty
+        // directory still starts out with page 0
+        let (first_page, max_page) = match read_checkpoint(path, &page_stem) {
+            Some(range) => range,
+            None => (
+                Self::load_min_page(path, &page_stem)?,
+                Self::load_max_page(path, &page_stem)?,
+            ),
+        };
+
+        // the newest page any manager added plus one (0 before the first)
+        let newest_page: MmapCell<AtomicU64> =
+            map_shared(path, &page_stem, CONTROL_FILE_SUFFIX, options.file_mode)?;
+        // the last heartbeat of each group, 0 for none
+        let group_heartbeats =
+            map_shared(path, &page_stem, HEARTBEAT_FILE_SUFFIX, options.file_mode)?;
+        if compacted {
+            // the compaction renumbered the pages from 0
+            newest_page
+                .get()
+                .store(max_page as u64 + 1, Ordering::Release);
+        } else {
+            newest_page
+                .get()
+                .fetch_max(max_page as u64 + 1, Ordering::AcqRel);
+        }
+
+        let mut init_pages = VecDeque::new();
+        let mut created = false;
+        for i in first_page..=max_page {
+            let page_path = datapage_path(path, &page_stem, i);
+
+            // a page missing between retained ones stays a gap, mapped as a
+            // closed page so receivers skip past it instead of waiting on it
+            // forever
+            if i < max_page && !page_path.exists() {
+                tracing::warn!(
+                    page_index = i,
+                    datapage_count = max_page,
+                    "page missing between retained pages, its messages are skipped",
+                );
+                init_pages.push_back(Arc::new(DataPage::closed_anon(i, &options.format)?));
+                continue;
+            }
+            created |= !page_path.exists();
+
+            let datapage = map_datapage(
+                page_path,
+                i,
+                &options.format,
+                options.file_mode,
+                options.preallocate,
+                options.prefault,
+                options.map_page.as_deref(),
+            )?;
+            init_pages.push_back(Arc::new(datapage));
+        }
+        if created && options.sync_directory_on_create {
+            sync_dir(path)?;
+        }
+
+        Ok(DataPagesManager {
+            path: path.clone(),
+            topic: options.topic.as_str().into(),
+            page_stem: page_stem.into(),
+            max_datapages: Arc::new(AtomicUsize::new(usize::MAX)),
+            min_datapages: Arc::default(),
+            datapage_count: Arc::new(AtomicUsize::new(max_page)),
+            datapage_ring: Arc::new(RwLock::new(init_pages)),
+            newest_page: Arc::new(newest_page),
+            closed: Arc::new(AtomicBool::new(false)),
+            on_reclaim: Arc::default(),
+            created_pages: Arc::default(),
+            reclaimed_pages: Arc::default(),
+            size_histogram: options
+                .size_histogram
+                .then(|| Arc::new(std::array::from_fn(|_| AtomicU64::new(0)))),
+            page_added: Arc::default(),
+            group_consumers: Arc::new(std::array::from_fn(|_| AtomicUsize::new(0))),
+            group_heartbeats: Arc::new(group_heartbeats),
+            async_waiters: Arc::default(),
+            checkpoint_lock: Arc::default(),
+            ack_watermarks: Arc::default(),
+            heartbeat_ttl: options.heartbeat_ttl,
+            file_mode: options.file_mode,
+            preallocate: options.preallocate,
+            prefault: options.prefault,
+            reuse_pages: options.reuse_pages,
+            release_reclaimed: options.release_reclaimed,
+            consumer_creates_pages: options.consumer_creates_pages,
+            sync_directory_on_create: options.sync_directory_on_create,
+            map_page: options.map_page.clone(),
+            format: options.format,
+            cipher: options.cipher.clone(),
+            overflow_policy: options.overflow_policy,
+            page_creation_limit: options
+                .page_creation_limit
+                .map(|(per_second, policy)| Arc::new(PageRateLimit::new(per_second, policy))),
+            _cleanup: None,
+        })
+    }
+
+    /// Checks every page file in `path` and recreates a truncated newest page,
+    /// see `DataPagesManagerBuilder::repair` for queues with a topic or
+    /// another file stem.
+    pub fn repair<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
+        Self::builder(path).repair()
+    }
+
+    /// Topics with at least one page in `path`, sorted, see
+    /// `DataPagesManagerBuilder::topics` for another file stem. The default
+    /// topic (no `DataPagesManagerBuilder::topic`) isn't listed.
+    pub fn topics<P: AsRef<Path>>(path: P) -> Result<Vec<String>, Error> {
+        Self::builder(path).topics()
+    }
+
+    /// Numbers of the `page_stem` pages in `path`, in no particular order.
+    fn load_page_numbers<P: AsRef<Path>>(
+        path: P,
+        page_stem: &str,
+    ) -> Result<Vec<usize>, std::io::Error> {
+        Ok(std::fs::read_dir(path)?
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name();
+                let (stem, num) = parse_datapage_name(name.to_str()?)?;
+
+                (stem == page_stem).then_some(num)
+            })
+            .collect())
+    }
+
+    fn load_max_page<P: AsRef<Path>>(path: P, page_stem: &str) -> Result<usize, std::io::Error> {
+        Ok(Self::load_page_numbers(path, page_stem)?
+            .into_iter()
+            .max()
+            .unwrap_or(0))
+    }
+
+    fn load_min_page<P: AsRef<Path>>(path: P, page_stem: &str) -> Result<usize, std::io::Error> {
+        Ok(Self::load_page_numbers(path, page_stem)?
+            .into_iter()
+            .min()
+            .unwrap_or(0))
+    }
+
+    /// Whether the file for page `num` is on disk, without mapping it.
+    pub fn page_exists(&self, num: usize) -> bool {
+        datapage_path(&self.path, &self.page_stem, num).exists()
+    }
+
+    /// Lowest and highest page numbers on disk.
+    ///
+    /// Scans the directory on every call rather than reading the ring, so it
+    /// also reports pages another process created or a failed reclamation
+    /// left behind: fewer than `high - low + 1` pages on disk (see
+    /// `list_pages`) means there's a gap. A page being
+    /// created or reclaimed concurrently may or may not be counted.
+    pub fn page_range(&self) -> Result<(usize, usize), Error> {
+        Ok((
+            Self::load_min_page(&self.path, &self.page_stem)?,
+            Self::load_max_page(&self.path, &self.page_stem)?,
+        ))
+    }
+
+    /// Page numbers of every page file in the directory, sorted. Scans the
+    /// directory like `page_range`.
+    pub fn list_pages(&self) -> Result<Vec<usize>, Error> {
+        let mut pages = Self::load_page_numbers(&self.path, &self.page_stem)?;
+        pages.sort_unstable();
+
+        Ok(pages)
+    }
+
+    /// Checks every page file of the queue on disk, reporting all problems
+    /// found rather than stopping at the first: files that aren't the size of
+    /// a page, headers that don't match the queue's format, and slots that
+    /// `DataPage::check_slots` finds wrong.
+    ///
+    /// Pages are read through their own private mappings, so this doesn't
+    /// touch the ring or anything senders and receivers are doing, but it
+    /// does read every page. Gaps on pages producers may still be writing,
+    /// the newest one or beyond, are left out since a push in progress looks
+    /// the same.
+    pub fn verify(&self) -> Result<VerifyReport, Error> {
+        let expected = size_of::<DataPage>() as u64;
+        let dp_count = self.datapage_count.load(Ordering::Relaxed);
+        let mut report = VerifyReport::default();
+
+        for num in self.list_pages()? {
+            let path = datapage_path(&self.path, &self.page_stem, num);
+            let len = match std::fs::metadata(&path) {
+                Ok(m) => m.len(),
+                // reclaimed since it was listed
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            report.pages_checked += 1;
+
+            if len != expected {
+                report
+                    .problems
+                    .push((num, PageProblem::Size { len, expected }));
+                continue;
+            }
+
+            let datapage = match DataPage::open_read_only(&path) {
+                Ok(datapage) => datapage,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let datapage = datapage.get();
+
+            if let Err(e) = datapage.check_format(num, &self.format) {
+                report.problems.push((
+                    num,
+                    PageProblem::Format {
+                        field: e.field,
+                        expected: e.expected,
+                        found: e.found,
+                    },
+                ));
+            }
+
+            report.problems.extend(
+                datapage
+                    .check_slots()
+                    .into_iter()
+                    .filter(|p| num < dp_count || !matches!(p, SlotProblem::Gap { .. }))
+                    .map(|p| (num, PageProblem::Slot(p))),
+            );
+        }
+
+        Ok(report)
+    }
+
+    /// Deletes retained page `num` ahead of reclamation, e.g. once it's been
+    /// archived.
+    ///
+    /// The oldest page is dropped from the ring. A page further in leaves a
+    /// gap: `list_pages` stops listing it while `page_range` still spans it,
+    /// and receivers reaching it move on to the next page as if it had been
+    /// closed empty, also after the queue is reopened. Deleting the oldest
+    /// page also drops the gaps right behind it.
+    ///
+    /// The newest `min_datapages` pages (at least the newest page, which
+    /// producers write to) can't be deleted, nor can pages outside the ring;
+    /// both fail with `Error::PageNotDeletable`. Senders and receivers still
+    /// on the page keep reading it until they move on, like with reclamation.
+    /// `on_reclaim` isn't called and `reclaimed_pages` isn't counted.
+    pub fn delete_page(&self, num: usize) -> Result<(), Error> {
+        let mut datapages = self.datapage_ring.write();
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+
+        let dp_count = self.datapage_count.load(Ordering::Relaxed);
+        let first_page = (dp_count + 1).saturating_sub(datapages.len());
+        let min_datapages = self.min_datapages.load(Ordering::Relaxed).max(1);
+        if num < first_page || num + min_datapages > dp_count {
+            return Err(Error::PageNotDeletable(num));
+        }
+
+        let path = datapage_path(&self.path, &self.page_stem, num);
+        if self.has_pending_consumers(datapages[num - first_page].get()) {
+            tracing::warn!(
+                page_index = num,
+                path = %path.display(),
+                datapage_count = dp_count,
+                "deleting a page attached consumers haven't finished",
+            );
+        }
+
+        // mapped before the file goes, so a failure leaves the page in place
+        let gap = if num > first_page {
+            Some(DataPage::closed_anon(num, &self.format)?)
+        } else {
+            None
+        };
+
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        match gap {
+            Some(gap) => datapages[num - first_page] = Arc::new(gap),
+            None => {
+                datapages.pop_front();
+
+                let mut next = num + 1;
+                while next < dp_count && !self.page_exists(next) {
+                    datapages.pop_front();
+                    next += 1;
+                }
+            }
+        }
+        drop(datapages);
+        self.checkpoint();
+
+        Ok(())
+    }
+
+    pub fn set_max_datapages(&mut self, val: usize) {
+        let _dp = self.datapage_ring.write();
+        self.max_datapages.store(val, Ordering::Relaxed);
+    }
+
+    /// Always retain at least the newest `val` pages, so consumers attaching
+    /// late still have that much to replay. Fails with `InvalidConfig` if
+    /// `val` is over the current `max_datapages`.
+    ///
+    /// The floor wins over a later, lower `set_max_datapages`: reclamation
+    /// then stops at `val` pages. (Page count is the only retention this
+    /// manager has, so that's the only reclamation the floor applies to.)
+    pub fn set_min_datapages(&mut self, val: usize) -> Result<(), Error> {
+        let _dp = self.datapage_ring.write();
+
+        if val > self.max_datapages.load(Ordering::Relaxed) {
+            return Err(Error::InvalidConfig(
+                "min_datapages can't be over max_datapages",
+            ));
+        }
+
+        self.min_datapages.store(val, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Calls `f` with the number and file of every page about to be
+    /// reclaimed, while the file is still intact on disk, e.g. to archive it.
+    ///
+    /// Shared by every clone of the manager and replaces any earlier hook.
+    /// `f` runs with the page ring locked, stalling every sender and receiver
+    /// that needs a page until it returns, so keep it quick: copy or hard link
+    /// the file, or hand the path to another thread, rather than uploading it
+    /// in place.
+    ///
+    /// Pages without a file aren't passed to `f`: those deleted ahead of
+    /// reclamation with `delete_page`, or already missing when the queue was
+    /// opened.
+    pub fn on_reclaim(&mut self, f: impl Fn(usize, &Path) + Send + Sync + 'static) {
+        *self.on_reclaim.write() = Some(Box::new(f));
+    }
+
+    /// Pages added to the end of the queue since the manager was built,
+    /// across all its clones. Pages mapped when opening, or that another
+    /// manager had already created, don't count.
+    pub fn created_pages(&self) -> u64 {
+        self.created_pages.load(Ordering::Relaxed)
+    }
+
+    /// Pages reclaimed to stay within `max_datapages` since the manager was
+    /// built, across all its clones.
+    pub fn reclaimed_pages(&self) -> u64 {
+        self.reclaimed_pages.load(Ordering::Relaxed)
+    }
+
+    /// Rough count of the messages ever pushed onto the queue: every page
+    /// before the newest one with messages taken as full, plus the slots
+    /// reserved on that one. Cheap enough for a monitoring gauge, it only
+    /// reads the newest pages.
+    ///
+    /// Empty pages at the tail, created ahead of the producers by `reserve`
+    /// or by a receiver seeking past the newest page, don't count. Pages that
+    /// rolled over early, because the next message didn't fit in the bytes
+    /// left, hold fewer than `page_capacity_messages`, so this overcounts by
+    /// the slots each of them left unused. A push in progress, or one that
+    /// failed and rolled the page, counts too. See
+    /// `total_messages_written_exact` for the real count.
+    pub fn total_messages_written(&self) -> u64 {
+        let datapages = self.datapage_ring.read();
+        let dp_count = self.datapage_count.load(Ordering::Relaxed);
+        let first_page = (dp_count + 1).saturating_sub(datapages.len());
+
+        let (page, tail_count) = datapages
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, dp)| {
+                let count = dp.get().message_count();
+                (count > 0).then_some((first_page + i, count))
+            })
+            .unwrap_or((first_page, 0));
+
+        page as u64 * MAX_MESSAGES_PER_PAGE as u64 + tail_count as u64
+    }
+
+    /// Like `total_messages_written` but counts the published messages of
+    /// every retained page, so pages that rolled over early are counted
+    /// exactly. Reads the slots of every retained page.
+    ///
+    /// Pages already reclaimed or deleted can't be read anymore and are still
+    /// taken as full, so the count is only exact while the queue's first page
+    /// is retained.
+    pub fn total_messages_written_exact(&self) -> u64 {
+        let datapages = self.datapage_ring.read();
+        let dp_count = self.datapage_count.load(Ordering::Relaxed);
+        let first_page = (dp_count + 1).saturating_sub(datapages.len());
+
+        let retained: u64 = datapages
+            .iter()
+            .map(|dp| dp.get().iter_framed().count() as u64)
+            .sum();
+
+        first_page as u64 * MAX_MESSAGES_PER_PAGE as u64 + retained
+    }
+
+    /// How many messages of each size this process's senders on the queue
+    /// have pushed since the manager was built, with
+    /// `DataPagesManagerBuilder::size_histogram` on (all zeros otherwise).
+    ///
+    /// Bucket 0 counts empty messages and bucket `i` those of `2^(i-1)` up to
+    /// `2^i - 1` bytes, sized as passed to `push`, before any compression or
+    /// metadata. Compare with `page_capacity_bytes` to see whether pages fill
+    /// up on bytes before they run out of message slots. Pushes by other
+    /// processes aren't seen.
+    pub fn size_histogram(&self) -> [u64; SIZE_BUCKETS] {
+        match &self.size_histogram {
+            Some(buckets) => std::array::from_fn(|i| buckets[i].load(Ordering::Relaxed)),
+            None => [0; SIZE_BUCKETS],
+        }
+    }
+
+    /// Counts a pushed message of `len` bytes into `size_histogram`.
+    pub(crate) fn record_size(&self, len: usize) {
+        if let Some(buckets) = &self.size_histogram {
+            let bucket = (u32::BITS - (len as u32).leading_zeros()) as usize;
+            buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Bytes of messages (length prefixes included) a page holds, set at
+    /// build time through `DP_BUILD_EMSG_SIZE`.
+    ///
+    /// Each page file is a little bigger than this for its header and slot
+    /// index, see `size_of::<DataPage>()`. To keep roughly 1 GiB of messages:
+    ///
+    /// ```no_run
+    /// # use disk_mpmc::manager::DataPagesManager;
+    /// let mut manager = DataPagesManager::new("/tmp/queue")?;
+    /// let pages = (1 << 30) / manager.page_capacity_bytes();
+    /// manager.set_max_datapages(pages.max(1) as usize);
+    /// # Ok::<(), disk_mpmc::Error>(())
+    /// ```
+    pub fn page_capacity_bytes(&self) -> u64 {
+        MAX_BYTES_PER_PAGE as u64
+    }
+
+    /// Messages a page holds at most, whatever their size.
+    pub fn page_capacity_messages(&self) -> u32 {
+        MAX_MESSAGES_PER_PAGE
+    }
+
+    pub fn get_max_datapages(&self) -> usize {
+        self.max_datapages.load(Ordering::Relaxed)
+    }
+
+    /// Number of grouped receivers currently attached to `group` in this process.
+    ///
+    /// Receivers in the same group compete for messages, so a nonzero count
+    /// on a group that isn't making progress points at a stuck consumer.
+    pub fn group_consumer_count(&self, group: usize) -> usize {
+        self.group_consumers
+            .get(group)
+            .map(|c| c.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn format(&self) -> &PageFormat {
+        &self.format
+    }
+
+    pub(crate) fn cipher(&self) -> &Cipher {
+        &self.cipher
+    }
+
+    pub fn receiver_groups(&self) -> usize {
+        self.format.receiver_groups as usize
+    }
+
+    /// Returns a receiver on the first group that has no consumers attached.
+    ///
+    /// Each subscriber gets its own group and therefore sees every message
+    /// (broadcast). The group is released for reuse once the returned receiver
+    /// and all of its clones are dropped; a reused group resumes from wherever
+    /// its previous subscriber stopped.
+    pub fn subscribe(&self) -> Result<Receiver<Grouped>, Error> {
+        for group in 0..self.receiver_groups() {
+            if self.group_consumers[group]
+                .compare_exchange(0, 1, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.heartbeat(group);
+
+                let consumer = ConsumerGuard {
+                    group,
+                    group_consumers: self.group_consumers.clone(),
+                };
+
+                return Receiver::with_consumer(consumer, self.clone());
+            }
+        }
+
+        Err(Error::NoGroupsAvailable)
+    }
+
+    pub(crate) fn attach_consumer(&self, group: usize) -> ConsumerGuard {
+        self.heartbeat(group);
+        self.group_consumers[group].fetch_add(1, Ordering::Relaxed);
+
+        ConsumerGuard {
+            group,
+            group_consumers: self.group_consumers.clone(),
+        }
+    }
+
+    /// The newest `n` retained messages, oldest first, as stored: on queues
+    /// with timestamps, keys, a codec or encryption they're still framed,
+    /// compressed and encrypted.
+    ///
+    /// Walks back from the tail page, purely reading the pages like
+    /// `DataPage::iter`: no group's position moves and nothing is written to
+    /// them. Messages still being written, and any published after them, are
+    /// left out until the write finishes, the same as for receivers.
+    pub fn tail(&self, n: usize) -> Result<Vec<Vec<u8>>, Error> {
+        let datapages = self.datapage_ring.read();
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+
+        let mut newest_first = Vec::new();
+
+        'pages: for datapage in datapages.iter().rev() {
+            let datapage = datapage.get();
+
+            // read first, a push landing meanwhile only adds to `published`
+            let stored = datapage.stored_count() as usize;
+            let published: Vec<&[u8]> = datapage.iter().collect();
+            if published.len() < stored {
+                newest_first.clear();
+            }
+
+            for msg in published.into_iter().rev() {
+                if newest_first.len() == n {
+                    break 'pages;
+                }
+
+                newest_first.push(msg.to_vec());
+            }
+        }
+
+        newest_first.reverse();
+        Ok(newest_first)
+    }
+
+    /// Writes every retained message to `out`, oldest first, and returns how
+    /// many it wrote. `import` reads the same format back.
+    ///
+    /// Each message is written as its payload's length, a little-endian
+    /// `u32`, then its timestamp in nanoseconds since the Unix epoch and its
+    /// key, little-endian `u64`s that are 0 on queues that don't store them,
+    /// then the payload, decoded.
+    ///
+    /// Purely reads the pages, like `DataPage::iter`: no group counts are
+    /// claimed, so receivers are unaffected. Messages pushed while exporting
+    /// may or may not be included. The export always is a prefix of the
+    /// stream: it ends at the first message whose push is still in progress,
+    /// leaving out the pages after it too.
+    pub fn export<W: Write>(&self, out: &mut W) -> Result<u64, Error> {
+        // not held while writing, so a slow `out` doesn't stall producers
+        let (first_page, datapages) = {
+            let datapages = self.datapage_ring.read();
+            if self.is_closed() {
+                return Err(Error::Closed);
+            }
+
+            let dp_count = self.datapage_count.load(Ordering::Relaxed);
+            let first_page = (dp_count + 1).saturating_sub(datapages.len());
+            (first_page, datapages.iter().cloned().collect::<Vec<_>>())
+        };
+
+        let (mut scratch, mut opened) = (Vec::new(), Vec::new());
+        let mut exported = 0;
+
+        for (i, datapage) in datapages.iter().enumerate() {
+            let mut page_exported = 0;
+
+            for (count, framed) in datapage.get().iter_framed().enumerate() {
+                let record = Record::parse(&self.format, strip_frame(&self.format, framed));
+                let timestamp = record.timestamp.map_or(0, |timestamp| {
+                    timestamp
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_nanos() as u64
+                });
+                let key = record.key.unwrap_or(0);
+
+                let data = if self.format.codec == Codec::None && !self.format.encrypted {
+                    record.data
+                } else {
+                    let stored = Stored {
+                        page: first_page + i,
+                        count: count as u32,
+                        framed,
+                    };
+                    stored.decode_payload(&self.format, &self.cipher, &mut scratch, &mut opened)?;
+                    &scratch
+                };
+
+                out.write_all(&(data.len() as u32).to_le_bytes())?;
+                out.write_all(&timestamp.to_le_bytes())?;
+                out.write_all(&key.to_le_bytes())?;
+                out.write_all(data)?;
+                exported += 1;
+                page_exported += 1;
+            }
+
+            // a push still in progress, the messages after it would leave a gap
+            if page_exported < datapage.get().stored_count() {
+                break;
+            }
+        }
+
+        out.flush()?;
+        Ok(exported)
+    }
+
+    /// Pushes every message `input` holds in the format `export` writes, in
+    /// order, and returns how many it pushed.
+    ///
+    /// Messages keep their timestamps and keys where this queue stores them,
+    /// so partitioned receivers see the same keys as on the exported queue.
+    /// A timestamp of 0, from a queue without them, is replaced by the time
+    /// of the import.
+    ///
+    /// Fails with an `UnexpectedEof` `Error::Io` if `input` ends partway
+    /// through a message, after pushing the ones before it.
+    pub fn import<R: Read>(&self, input: &mut R) -> Result<u64, Error> {
+        let mut tx = Sender::new(self.clone())?;
+        let mut data = Vec::new();
+        let mut imported = 0;
+
+        while let Some(len) = read_len(input)? {
+            let mut timestamp = [0; TIMESTAMP_SIZE];
+            let mut key = [0; KEY_SIZE];
+            input.read_exact(&mut timestamp)?;
+            input.read_exact(&mut key)?;
+            data.resize(len as usize, 0);
+            input.read_exact(&mut data)?;
+
+            let timestamp = Some(u64::from_le_bytes(timestamp)).filter(|&t| t != 0);
+            tx.push_record(u64::from_le_bytes(key), timestamp, &data)?;
+            imported += 1;
+        }
+
+        Ok(imported)
+    }
+
+    /// Whether no retained page holds a message, e.g. on a directory nothing
+    /// was ever pushed to. A push still being written counts as a message.
+    pub fn is_empty(&self) -> bool {
+        let datapages = self.datapage_ring.read();
+
+        // only the tail page is ever empty, so this usually stops at it
+        datapages
+            .iter()
+            .rev()
+            .all(|dp| dp.get().message_count() == 0)
+    }
+
+    /// Reads the ring and the manager's counters, cheap enough for a
+    /// monitoring gauge: nothing touches the filesystem. `list_pages` and
+    /// `page_range` scan the directory for what's on disk.
+    pub fn stats(&self) -> ManagerStats {
+        let datapages = self.datapage_ring.read();
+        let (published_count, bytes_used) = datapages
+            .back()
+            .map(|dp| dp.get().usage())
+            .unwrap_or((0, 0));
+
+        ManagerStats {
+            mapped_pages: datapages.len(),
+            datapage_count: self.datapage_count.load(Ordering::Relaxed),
+            max_datapages: self.max_datapages.load(Ordering::Relaxed),
+            published_count,
+            bytes_used,
+        }
+    }
+
+    /// Stops the queue: flushes every mapped page to disk, unmaps them and
+    /// makes every clone of the manager refuse further work with `Error::Closed`.
+    ///
+    /// Pushes and receivers that need a new page fail from here on, but
+    /// senders and receivers keep their current page mapped until they drop,
+    /// so the files are only fully unmapped once `shutdown` holds the last
+    /// references to them. Stop pushing before calling this, a push that races
+    /// it may land after the flush.
+    pub fn shutdown(self) -> Result<(), Error> {
+        let mut datapages = self.datapage_ring.write();
+        self.closed.store(true, Ordering::Release);
+
+        let dp_count = self.datapage_count.load(Ordering::Relaxed);
+        let first_page = (dp_count + 1).saturating_sub(datapages.len());
+
+        for (i, datapage) in datapages.iter().enumerate() {
+            sync_page(
+                &datapage_path(&self.path, &self.page_stem, first_page + i),
+                datapage,
+            )?;
+        }
+
+        datapages.clear();
+        drop(datapages);
+
+        // waiters for the next page have to see the manager closed
+        self.page_added.fetch_add(1, Ordering::Release);
+        atomic_wait::wake_all(&*self.page_added);
+
+        Ok(())
+    }
+
+    /// Writes page `num`, mapped as `datapage`, back to disk, see `Sender::flush`.
+    pub(crate) fn sync(&self, num: usize, datapage: &MmapCell<DataPage>) -> Result<(), Error> {
+        Ok(sync_page(
+            &datapage_path(&self.path, &self.page_stem, num),
+            datapage,
+        )?)
+    }
+
+    /// Checks that a new page could be created right now, see `Sender::check`.
+    pub(crate) fn check_next_page(&self) -> Result<(), Error> {
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+
+        let probe = self.path.join(format!("{}{PROBE_FILE_STEM}", self.topic));
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&probe)?;
+
+        match std::fs::remove_file(&probe) {
+            // another sender's check got to it first
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        let needed = size_of::<DataPage>() as u64;
+        let available = available_space(&self.path)?;
+        if available < needed {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::StorageFull,
+                format!("{available} bytes left on disk, a page takes up to {needed}"),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    pub fn get_last_datapage(&self) -> Result<(usize, Arc<MmapCell<DataPage>>), Error> {
+        let datapages = self.datapage_ring.read();
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+
+        let last_datapage = datapages
+            .back()
+            .ok_or(std::io::Error::other("DataPage not found"))?;
+
+        let dp_count = self.datapage_count.load(Ordering::Relaxed);
+        Ok((dp_count, last_datapage.clone()))
+    }
+
+    /// Returns page `num`, or the oldest retained page if `num` was already
+    /// reclaimed, creating the next page (and reclaiming old ones) if `num`
+    /// is past the end of the ring.
+    ///
+    /// A next page that already exists on disk, e.g. because another manager
+    /// on the same directory created it, is mapped as is rather than
+    /// recreated, and old pages another manager already removed are skipped.
+    /// If another manager got so far ahead that it already reclaimed the next
+    /// page, this one moves on to the oldest page that manager retains
+    /// instead, so messages never go to a page nobody reads anymore.
+    pub fn get_or_create_datapage(
+        &self,
+        num: usize,
+    ) -> Result<(usize, Arc<MmapCell<DataPage>>), Error> {
+        loop {
+            let datapages = self.datapage_ring.upgradable_read();
+            if self.is_closed() {
+                return Err(Error::Closed);
+            }
+
+            let dp_count = self.datapage_count.load(Ordering::Relaxed);
+            let first_page = (dp_count + 1).saturating_sub(datapages.len());
+
+            if num <= dp_count {
+                let page = num.max(first_page);
+                return Ok((page, datapages[page - first_page].clone()));
+            }
+
+            let mut next = dp_count + 1;
+            let overtaken =
+                self.shared_newest_page().is_some_and(|n| n > next) && !self.page_exists(next);
+            let skip_to = if overtaken {
+                Self::load_page_numbers(&self.path, &self.page_stem)?
+                    .into_iter()
+                    .filter(|&n| n > next)
+                    .min()
+            } else {
+                None
+            };
+
+            let max_dps = self
+                .max_datapages
+                .load(Ordering::Relaxed)
+                .max(self.min_datapages.load(Ordering::Relaxed));
+            // pages we skip past are older still and went with the next one
+            let reclaim = match skip_to {
+                Some(_) => 0,
+                None => (datapages.len() + 1).saturating_sub(max_dps),
+            };
+
+            let blocked = datapages
+                .iter()
+                .take(reclaim)
+                .any(|dp| self.has_pending_consumers(dp.get()));
+
+            if blocked {
+                match self.overflow_policy {
+                    OverflowPolicy::DropOldest => {}
+                    OverflowPolicy::DropNewest => return Err(Error::Full),
+                    OverflowPolicy::Block => {
+                        drop(datapages);
+                        std::thread::sleep(BLOCK_POLL_INTERVAL);
+                        continue;
+                    }
+                }
+            }
+
+            // taken with the upgradable lock held, so senders racing to add
+            // the same page don't both spend a token on it
+            if let Some(limit) = &self.page_creation_limit {
+                if let Err(wait) = limit.try_take() {
+                    match limit.policy {
+                        ThrottlePolicy::Fail => return Err(Error::RolloverThrottled),
+                        ThrottlePolicy::Block => {
+                            drop(datapages);
+                            std::thread::sleep(wait);
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            let mut datapages = RwLockUpgradableReadGuard::upgrade(datapages);
+
+            if let Some(skip_to) = skip_to {
+                tracing::warn!(
+                    page_index = skip_to,
+                    datapage_count = dp_count,
+                    "another manager already reclaimed the next page, skipping ahead",
+                );
+
+                datapages.clear();
+                next = skip_to;
+            }
+
+            let page_path = datapage_path(&self.path, &self.page_stem, next);
+            let existed = page_path.exists();
+
+            let on_reclaim = self.on_reclaim.read();
+            let reuse = self.reuse_pages && on_reclaim.is_none() && !existed;
+            let mut recycled = None;
+
+            for page in first_page..first_page + reclaim {
+                let reclaimed_path = datapage_path(&self.path, &self.page_stem, page);
+                // a gap left by `delete_page` or a page missing on open, with
+                // only an anonymous placeholder in the ring
+                let on_disk = reclaimed_path.exists();
+
+                if let Some(f) = on_reclaim.as_ref().filter(|_| on_disk) {
+                    f(page, &reclaimed_path);
+                }
+
+                let unread = datapages
+                    .front()
+                    .is_some_and(|dp| self.has_pending_consumers(dp.get()));
+                if unread {
+                    tracing::warn!(
+                        page_index = page,
+                        path = %reclaimed_path.display(),
+                        datapage_count = dp_count,
+                        "reclaiming a page attached consumers haven't finished",
+                    );
+                } else {
+                    tracing::info!(
+                        page_index = page,
+                        path = %reclaimed_path.display(),
+                        datapage_count = dp_count,
+                        "reclaiming page",
+                    );
+                }
+
+                // the ring's is the only reference, nobody reads it anymore
+                let mut recycle = reuse
+                    && on_disk
+                    && recycled.is_none()
+                    && datapages
+                        .front()
+                        .is_some_and(|dp| Arc::strong_count(dp) == 1);
+
+                let removed = if recycle {
+                    // cleared and synced first, a crash before the rename
+                    // leaves an empty old page rather than stale messages
+                    // under the new number, and one after it a blank header
+                    // rather than the old page's number, which open rejects
+                    let datapage = datapages.front().expect("checked above");
+                    datapage.get().clear();
+                    sync_page(&reclaimed_path, datapage)
+                        .and_then(|()| std::fs::rename(&reclaimed_path, &page_path))
+                } else {
+                    std::fs::remove_file(&reclaimed_path)
+                };
+
+                match removed {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => recycle = false,
+                    Err(e) => return Err(e.into()),
+                }
+
+                let reclaimed = datapages.pop_front();
+                if recycle {
+                    recycled = reclaimed;
+                } else if let Some(datapage) = reclaimed.filter(|dp| {
+                    // dropping a placeholder's memory would blank it rather
+                    // than leave it closed for the readers still on it
+                    self.release_reclaimed
+                        && self.map_page.is_none()
+                        && on_disk
+                        && Arc::strong_count(dp) > 1
+                }) {
+                    release_memory(&datapage);
+                }
+                self.reclaimed_pages.fetch_add(1, Ordering::Relaxed);
+            }
+
+            let dp_count = next;
+            let datapage = match recycled {
+                Some(datapage) => {
+                    check_format(page_path.clone(), datapage.get(), dp_count, &self.format)?;
+                    datapage
+                }
+                None => Arc::new(map_datapage(
+                    page_path.clone(),
+                    dp_count,
+                    &self.format,
+                    self.file_mode,
+                    self.preallocate,
+                    self.prefault,
+                    self.map_page.as_deref(),
+                )?),
+            };
+            if !existed && self.sync_directory_on_create {
+                sync_dir(&self.path)?;
+            }
+
+            datapages.push_back(datapage.clone());
+            self.datapage_count.store(dp_count, Ordering::Relaxed);
+            if !existed {
+                self.created_pages.fetch_add(1, Ordering::Relaxed);
+            }
+            self.newest_page
+                .get()
+                .fetch_max(dp_count as u64 + 1, Ordering::AcqRel);
+            drop(datapages);
+            self.checkpoint();
+
+            tracing::debug!(
+                page_index = dp_count,
+                path = %page_path.display(),
+                datapage_count = dp_count,
+                created = !existed,
+                "added page",
+            );
+
+            self.page_added.fetch_add(1, Ordering::Release);
+            atomic_wait::wake_all(&*self.page_added);
+
+            return Ok((dp_count, datapage));
+        }
+    }
+
+    /// Newest page any manager on the directory, in this process or another,
+    /// has added, if the control file recorded one yet.
+    fn shared_newest_page(&self) -> Option<usize> {
+        let newest = self.newest_page.get().load(Ordering::Acquire);
+        newest.checked_sub(1).map(|n| n as usize)
+    }
+
+    /// Updates the checkpoint after the ring changed. The pages are already in
+    /// place by now and opening falls back to scanning the directory without
+    /// one, so failing to write it isn't an error.
+    ///
+    /// Called with the ring unlocked, so senders and receivers don't wait on
+    /// its fsyncs. The range is read under `checkpoint_lock`, so when several
+    /// changes race the last one written is the newest.
+    fn checkpoint(&self) {
+        let _writing = self.checkpoint_lock.lock();
+        let (first, last) = {
+            let datapages = self.datapage_ring.read();
+            let last = self.datapage_count.load(Ordering::Relaxed);
+            ((last + 1).saturating_sub(datapages.len()), last)
+        };
+
+        if let Err(e) = write_checkpoint(&self.path, &self.page_stem, first, last) {
+            tracing::warn!(error = %e, first, last, "failed to write page checkpoint");
+        }
+    }
+
+    /// Like `get_or_create_datapage` but for readers: never creates a page
+    /// that isn't on disk yet, returning `None` instead.
+    ///
+    /// A page past the ring that some other manager (e.g. in another process)
+    /// already created is mapped, as is, and added to the ring.
+    pub fn get_datapage(&self, num: usize) -> Result<Option<NumberedPage>, Error> {
+        let dp_count = {
+            let datapages = self.datapage_ring.read();
+            if self.is_closed() {
+                return Err(Error::Closed);
+            }
+
+            let dp_count = self.datapage_count.load(Ordering::Relaxed);
+            let first_page = (dp_count + 1).saturating_sub(datapages.len());
+
+            if num <= dp_count {
+                let page = num.max(first_page);
+                return Ok(Some((page, datapages[page - first_page].clone())));
+            }
+
+            dp_count
+        };
+
+        // a producer creates the file before sizing it, so only a full length
+        // one is there yet
+        let next_path = datapage_path(&self.path, &self.page_stem, dp_count + 1);
+        match std::fs::metadata(next_path) {
+            Ok(m) if m.len() >= size_of::<DataPage>() as u64 => {}
+            Ok(_) => return Ok(None),
+            // unless it's missing because it was already reclaimed, which
+            // `get_or_create_datapage` skips
+            Err(e)
+                if e.kind() == std::io::ErrorKind::NotFound
+                    && self.shared_newest_page().is_none_or(|n| n <= dp_count + 1) =>
+            {
+                return Ok(None)
+            }
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            Err(_) => {}
+        }
+
+        self.get_or_create_datapage(num).map(Some)
+    }
+
+    /// Creates `additional` pages after the newest one ahead of time, so
+    /// producers rolling onto them during a burst don't stop to create files.
+    ///
+    /// The pages are added as if producers had filled the ones before them,
+    /// so older pages are reclaimed past `max_datapages` and the
+    /// `OverflowPolicy` applies. At most `max_datapages - 1` are added, which
+    /// keeps the newest page producers are writing to. That's counted from the
+    /// newest page, so reserving again before the producers get to the
+    /// reserved pages can still reclaim it. Receivers reaching the end of a
+    /// page move onto the reserved pages and wait there as usual.
+    pub fn reserve(&self, additional: usize) -> Result<(), Error> {
+        let max_dps = self
+            .max_datapages
+            .load(Ordering::Relaxed)
+            .max(self.min_datapages.load(Ordering::Relaxed));
+
+        let mut num = self.datapage_count.load(Ordering::Relaxed);
+        for _ in 0..additional.min(max_dps.saturating_sub(1)) {
+            num = self.get_or_create_datapage(num + 1)?.0;
+        }
+
+        Ok(())
+    }
+
+    /// The page a receiver moving to page `num` ends up on, creating it or
+    /// waiting for it as `DataPagesManagerBuilder::consumer_creates_pages` says.
+    pub(crate) fn consumer_datapage(&self, num: usize) -> Result<NumberedPage, Error> {
+        if self.consumer_creates_pages {
+            self.get_or_create_datapage(num)
+        } else {
+            self.wait_datapage(num)
+        }
+    }
+
+    /// Waits until `get_datapage` finds page `num` (or the oldest retained
+    /// page after it) and returns it.
+    ///
+    /// Pages added through this manager or its clones wake the wait right
+    /// away; pages created by other processes are polled for.
+    pub fn wait_datapage(&self, num: usize) -> Result<NumberedPage, Error> {
+        loop {
+            let page_added = self.page_added.load(Ordering::Acquire);
+
+            if let Some(page) = self.get_datapage(num)? {
+                return Ok(page);
+            }
+
+            wait_timeout(&self.page_added, page_added, BLOCK_POLL_INTERVAL);
+        }
+    }
+
+    /// Like `wait_datapage` but gives up with `None` once `deadline` has
+    /// passed.
+    pub fn wait_datapage_until(
+        &self,
+        num: usize,
+        deadline: Instant,
+    ) -> Result<Option<NumberedPage>, Error> {
+        loop {
+            let page_added = self.page_added.load(Ordering::Acquire);
+
+            if let Some(page) = self.get_datapage(num)? {
+                return Ok(Some(page));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            wait_timeout(
+                &self.page_added,
+                page_added,
+                (deadline - now).min(BLOCK_POLL_INTERVAL),
+            );
+        }
+    }
+
+    /// Waits until producers have written everything before the global
+    /// `offset` (`page * MAX_MESSAGES_PER_PAGE + count`, as from
+    /// `Receiver::offset`), returning `false` if that takes longer than
+    /// `timeout`.
+    ///
+    /// Only watches the queue, no group count moves. A page that filled up
+    /// before reaching `count` counts as written past it, as does a page
+    /// that's already been reclaimed.
+    pub fn wait_for_offset(&self, offset: u64, timeout: Duration) -> Result<bool, Error> {
+        let deadline = Instant::now() + timeout;
+        let page = (offset / MAX_MESSAGES_PER_PAGE as u64) as usize;
+        let count = (offset % MAX_MESSAGES_PER_PAGE as u64) as u32;
+
+        loop {
+            let page_added = self.page_added.load(Ordering::Acquire);
+
+            match self.get_datapage(page)? {
+                Some((num, _)) if num > page => return Ok(true),
+                Some((num, datapage)) if num == page => {
+                    let Some(last) = count.checked_sub(1) else {
+                        return Ok(true);
+                    };
+
+                    return Ok(Self::wait_for_slot(datapage.get(), last, deadline));
+                }
+                // mapped a page on the way there, keep going
+                Some(_) => continue,
+                None => {}
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+
+            wait_timeout(
+                &self.page_added,
+                page_added,
+                (deadline - now).min(BLOCK_POLL_INTERVAL),
+            );
+        }
+    }
+
+    /// Waits until slot `count` is published, or known never to be because
+    /// the page filled up before reaching it.
+    fn wait_for_slot(datapage: &DataPage, count: u32, deadline: Instant) -> bool {
+        loop {
+            // a push that reserved the slot publishes it or marks the page full
+            // there, slots after that stay empty for good
+            if datapage.is_full() && count >= datapage.message_count() {
+                return true;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            let wait = (deadline - now).min(BLOCK_POLL_INTERVAL);
+            if !matches!(datapage.get_with_timeout(count, wait), Ok(None)) {
+                return true;
+            }
+        }
+    }
+
+    /// Whether a live group still has unclaimed slots on `datapage`.
+    fn has_pending_consumers(&self, datapage: &DataPage) -> bool {
+        let (published, _) = datapage.usage();
+
+        (0..self.receiver_groups())
+            .any(|group| self.is_group_alive(group) && datapage.get_group_count(group) < published)
+    }
+
+    /// Messages stored on the retained pages after page `page` that `group`
+    /// hasn't claimed, see `Receiver::<Grouped>::backlog`.
+    pub(crate) fn backlog_after(&self, group: usize, page: usize) -> Result<u64, Error> {
+        let datapages = self.datapage_ring.read();
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+
+        let dp_count = self.datapage_count.load(Ordering::Relaxed);
+        let first_page = (dp_count + 1).saturating_sub(datapages.len());
+
+        Ok(datapages
+            .iter()
+            .skip((page + 1).saturating_sub(first_page))
+            .map(|dp| {
+                let dp = dp.get();
+                dp.stored_count().saturating_sub(dp.get_group_count(group)) as u64
+            })
+            .sum())
+    }
+
+    /// Has `waker` woken by the next push from this process onto `page`.
+    /// Callers have to check for their message again after registering.
+    pub(crate) fn register_waker(&self, page: usize, waker: &Waker) {
+        self.async_waiters.register(page, waker);
+        fence(Ordering::SeqCst);
+    }
+
+    /// Wakes the tasks waiting on `page`, after a push onto it.
+    pub(crate) fn notify_async(&self, page: usize) {
+        self.async_waiters.notify(page);
+    }
+
+    /// Stamps `group`'s heartbeat with the current time in the heartbeat
+    /// file, where managers in every process see it.
+    pub(crate) fn heartbeat(&self, group: usize) {
+        self.group_heartbeats.get()[group].store(unix_millis(), Ordering::Relaxed);
+    }
+
+    /// Without a heartbeat TTL, a group is alive while it has consumers
+    /// attached in this process. With one, while a receiver in any process
+    /// has sent a heartbeat for it within the TTL.
+    pub fn is_group_alive(&self, group: usize) -> bool {
+        let Some(ttl) = self.heartbeat_ttl else {
+            return self.group_consumer_count(group) > 0;
+        };
+
+        let Some(last) = self.group_heartbeats.get().get(group) else {
+            return false;
+        };
+        let last = last.load(Ordering::Relaxed);
+
+        last != 0 && unix_millis().saturating_sub(last) <= ttl.as_millis() as u64
+    }
+
+    /// Durably records `offset` as the next message `group` should consume.
+    ///
+    /// Offsets are global, `page * MAX_MESSAGES_PER_PAGE + count`, as returned
+    /// by `Receiver::offset`. The value is written to a temporary file which is
+    /// synced and then renamed over the group's offset file, so a crash leaves
+    /// either the old or the new offset, never a torn one.
+    pub fn commit(&self, group: usize, offset: u64) -> Result<(), Error> {
+        if group >= self.receiver_groups() {
+            return Err(Error::GroupOutOfRange(group));
+        }
+
+        self.write_offset(&group.to_string(), offset)
+    }
+
+    /// The offset last committed for `group`, if any.
+    pub fn committed(&self, group: usize) -> Result<Option<u64>, Error> {
+        if group >= self.receiver_groups() {
+            return Err(Error::GroupOutOfRange(group));
+        }
+
+        self.read_offset(&group.to_string())
+    }
+
+    /// Holds `group`'s commits through `ack` back at `index`, the global index
+    /// of the oldest message a receiver popped with
+    /// `Receiver::pop_commit_after` and hasn't handled yet.
+    pub(crate) fn hold_ack(&self, group: usize, index: u64) -> AckHold {
+        let mut hold = AckHold {
+            group,
+            index: None,
+            watermarks: self.ack_watermarks.clone(),
+        };
+        hold.advance(index);
+
+        hold
+    }
+
+    /// Releases `hold` once its receiver has handled every message before
+    /// `offset`, and commits its group's offset up to the oldest message
+    /// another receiver of the group in this process still holds.
+    ///
+    /// Commits never move back, and on an error the messages still count as
+    /// acked, so the group's next `ack` commits them.
+    pub(crate) fn ack(&self, mut hold: AckHold, offset: u64) -> Result<(), Error> {
+        let mut watermarks = self.ack_watermarks.lock();
+        let watermark = watermarks.entry(hold.group).or_default();
+
+        if let Some(index) = hold.index.take() {
+            watermark.release(index);
+        }
+        watermark.acked = watermark.acked.max(offset);
+
+        let oldest_held = watermark.outstanding.keys().next().copied();
+        let offset = oldest_held
+            .map_or(watermark.acked, |held| held.min(watermark.acked))
+            .max(watermark.committed);
+
+        // committed with the lock held, so concurrent acks land in order
+        self.commit(hold.group, offset)?;
+        watermark.committed = offset;
+
+        Ok(())
+    }
+
+    /// Like `commit` but for a position that isn't a group's, kept under a
+    /// name of the caller's choosing, e.g. by `Receiver::<Anonymous>::commit`.
+    ///
+    /// Names are kept apart from group numbers, so `"0"` doesn't touch group
+    /// 0's offset. Fails with `InvalidConfig` on an empty name or one with
+    /// path separators.
+    pub fn commit_named(&self, name: &str, offset: u64) -> Result<(), Error> {
+        self.write_offset(&Self::named_offset_suffix(name)?, offset)
+    }
+
+    /// The offset last committed under `name` with `commit_named`, if any.
+    pub fn committed_named(&self, name: &str) -> Result<Option<u64>, Error> {
+        self.read_offset(&Self::named_offset_suffix(name)?)
+    }
+
+    fn named_offset_suffix(name: &str) -> Result<String, Error> {
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(Error::InvalidConfig(
+                "offset name must be non-empty without path separators",
+            ));
+        }
+
+        Ok(format!("{NAMED_OFFSET_PREFIX}{name}"))
+    }
+
+    fn write_offset(&self, suffix: &str, offset: u64) -> Result<(), Error> {
+        let topic = &self.topic;
+        let path = self
+            .path
+            .join(format!("{topic}{OFFSET_FILE_STEM}.{suffix}"));
+        let tmp_path = self
+            .path
+            .join(format!("{topic}{OFFSET_FILE_STEM}.{suffix}.tmp"));
+
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&offset.to_le_bytes())?;
+        file.sync_all()?;
+
+        std::fs::rename(&tmp_path, &path)?;
+        sync_dir(&self.path)?;
+
+        Ok(())
+    }
+
+    fn read_offset(&self, suffix: &str) -> Result<Option<u64>, Error> {
+        let topic = &self.topic;
+        let path = self
+            .path
+            .join(format!("{topic}{OFFSET_FILE_STEM}.{suffix}"));
+
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let offset = bytes.try_into().map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt offset file")
+        })?;
+
+        Ok(Some(u64::from_le_bytes(offset)))
+    }
+
+    /// Moves `group` back (or forward) to `count` on page `num` and clears its
+    /// progress on every later retained page.
+    pub(crate) fn reset_group(&self, group: usize, num: usize, count: u32) {
+        let datapages = self.datapage_ring.read();
+        let dp_count = self.datapage_count.load(Ordering::Relaxed);
+        let first_page = (dp_count + 1).saturating_sub(datapages.len());
+
+        for (i, datapage) in datapages.iter().enumerate() {
+            let page = first_page + i;
+
+            if page == num {
+                datapage.get().set_group_count(group, count);
+            } else if page > num {
+                datapage.get().set_group_count(group, 0);
+            }
+        }
+    }
+
+    /// Rewrites every retained message, in order, into densely packed pages
+    /// numbered from 0 and deletes the old page files.
+    ///
+    /// The queue must be quiescent: no sender or receiver may be in use while
+    /// this runs, and all of them have to be recreated afterwards since the
+    /// pages they hold no longer exist. Group progress lives in the pages, so
+    /// every group starts over from the first compacted message.
+    ///
+    /// Crash safe: the compacted pages are written and synced under temporary
+    /// names before any old page is touched. If the process dies after that,
+    /// the next open finishes putting them in place; before that, the old
+    /// pages are kept and the next `compact` starts over.
+    ///
+    /// Not available on encrypted queues: renumbering messages would reuse
+    /// the nonces of the messages that were first stored at those positions.
+    pub fn compact(&self) -> Result<(), Error> {
+        if self.format.encrypted {
+            return Err(Error::InvalidConfig("encrypted queues can't be compacted"));
+        }
+
+        let mut datapages = self.datapage_ring.write();
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+
+        let compacted = self.stage_compaction(&datapages)?;
+
+        datapages.clear();
+        finish_compaction(&self.path, &self.page_stem)?;
+
+        self.datapage_count
+            .store(compacted.len() - 1, Ordering::Relaxed);
+        self.newest_page
+            .get()
+            .store(compacted.len() as u64, Ordering::Release);
+        datapages.extend(compacted);
+        drop(datapages);
+        self.checkpoint();
+
+        Ok(())
+    }
+
+    /// The first half of `compact`: writes the messages of `datapages`, the
+    /// locked ring, to compacted pages under temporary names, syncs them and
+    /// then writes the marker `finish_compaction` picks up. Returns the
+    /// compacted pages, still mapped under their temporary names.
+    fn stage_compaction(
+        &self,
+        datapages: &VecDeque<Arc<MmapCell<DataPage>>>,
+    ) -> Result<Vec<Arc<MmapCell<DataPage>>>, Error> {
+        let dp_count = self.datapage_count.load(Ordering::Relaxed);
+        let first_page = (dp_count + 1).saturating_sub(datapages.len());
+
+        // left over from a compaction that died before its marker
+        let compact_stem = compact_stem(&self.page_stem);
+        for i in Self::load_page_numbers(&self.path, &compact_stem)? {
+            std::fs::remove_file(datapage_path(&self.path, &compact_stem, i))?;
+        }
+
+        let compact_page = |i: usize| {
+            map_datapage(
+                datapage_path(&self.path, &compact_stem, i),
+                i,
+                &self.format,
+                self.file_mode,
+                self.preallocate,
+                self.prefault,
+                self.map_page.as_deref(),
+            )
+            .map(Arc::new)
+        };
+
+        let mut compacted = vec![compact_page(0)?];
+
+        for datapage in datapages.iter() {
+            let datapage = datapage.get();
+
+            for count in 0.. {
+                let msg = match datapage.try_get(count) {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) | Err(_) => break,
+                };
+
+                loop {
+                    let last = compacted.last().expect("at least one compacted page");
+
+                    if last.get_mut().push(msg).is_ok() {
+                        break;
+                    }
+
+                    compacted.push(compact_page(compacted.len())?);
+                }
+            }
+        }
+
+        for (i, datapage) in compacted.iter().enumerate() {
+            sync_page(&datapage_path(&self.path, &compact_stem, i), datapage)?;
+        }
+
+        write_fields(
+            &self.path,
+            &compaction_marker_path(&self.path, &self.page_stem),
+            &[first_page, dp_count, compacted.len()],
+        )?;
+
+        Ok(compacted)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::{path::Path, sync::atomic::AtomicBool, thread};
+
+    use super::*;
+    use crate::{
+        datapage::{MAX_MESSAGES_PER_PAGE, MAX_MESSAGE_SIZE},
+        test_util::mkdir_random,
+        GenReceiver, Receiver, Sender,
+    };
+
+    #[test]
+    fn compact() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+
+        for i in 0..5 {
+            let (_, datapage) = manager.get_or_create_datapage(i).unwrap();
+
+            for j in 0..3 {
+                datapage.get_mut().push(format!("{i}-{j}")).unwrap();
+            }
+        }
+
+        assert_eq!(manager.list_pages().unwrap().len(), 5);
+
+        manager.compact().unwrap();
+
+        assert_eq!(manager.list_pages().unwrap().len(), 1);
+        assert_eq!(read_checkpoint(&path, DATAPAGE_FILE_STEM), Some((0, 0)));
+
+        let mut rx = Receiver::new(0, manager).unwrap();
+        for i in 0..5 {
+            for j in 0..3 {
+                let msg = rx.pop().unwrap();
+                assert_eq!(msg, format!("{i}-{j}").as_bytes());
+            }
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn compaction_finishes_on_open() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::builder(&path)
+            .topic("orders")
+            .file_stem("pages")
+            .build()
+            .unwrap();
+        manager.set_max_datapages(2);
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 3).unwrap();
+        drop(tx);
+
+        // a queue under another stem in the same directory is left alone
+        let other = DataPagesManager::builder(&path)
+            .topic("orders")
+            .build()
+            .unwrap();
+        Sender::new(other.clone()).unwrap().push("other").unwrap();
+        other.compact().unwrap();
+
+        // dies after writing the compacted pages, before touching the old ones
+        let datapages = manager.datapage_ring.write();
+        manager.stage_compaction(&datapages).unwrap();
+        drop(datapages);
+        assert_eq!(manager.list_pages().unwrap(), [1, 2]);
+        drop(manager);
+
+        let manager = DataPagesManager::builder(&path)
+            .topic("orders")
+            .file_stem("pages")
+            .build()
+            .unwrap();
+        assert_eq!(manager.list_pages().unwrap(), [0, 1]);
+        assert!(!compaction_marker_path(&path, "orderspages").exists());
+
+        let mut rx = Receiver::new(0, manager).unwrap();
+        for i in MAX_MESSAGES_PER_PAGE..3 * MAX_MESSAGES_PER_PAGE {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        let mut rx = Receiver::new(0, other).unwrap();
+        assert_eq!(rx.pop().unwrap(), b"other");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn stats() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        tx.push("abc").unwrap();
+        tx.push("defgh").unwrap();
+
+        let stats = manager.stats();
+        assert_eq!(stats.mapped_pages, 1);
+        assert_eq!(stats.datapage_count, 0);
+        assert_eq!(stats.max_datapages, usize::MAX);
+        assert_eq!(stats.published_count, 2);
+        assert_eq!(stats.bytes_used, 3 + 5 + 2 * 4);
+
+        let mut rx = Receiver::new(0, manager).unwrap();
+        rx.pop().unwrap();
+
+        let rx_stats = rx.stats();
+        assert_eq!(rx_stats.datapage_count, 0);
+        assert_eq!(rx_stats.consumed_count, 1);
+        assert_eq!(rx_stats.lag, 1);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn total_messages_written() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.total_messages_written(), 0);
+        assert_eq!(manager.total_messages_written_exact(), 0);
+
+        // only one of these fits on a page, so each rolls the page early
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for _ in 0..3 {
+            tx.push(vec![0; MAX_MESSAGE_SIZE / 2 + 1]).unwrap();
+        }
+        tx.push("a").unwrap();
+        tx.push("b").unwrap();
+
+        assert_eq!(manager.stats().datapage_count, 2);
+        assert_eq!(
+            manager.total_messages_written(),
+            2 * MAX_MESSAGES_PER_PAGE as u64 + 3
+        );
+        assert_eq!(manager.total_messages_written_exact(), 5);
+
+        // pages created ahead of any push aren't counted as full
+        manager.reserve(2).unwrap();
+        assert_eq!(manager.stats().datapage_count, 4);
+        assert_eq!(
+            manager.total_messages_written(),
+            2 * MAX_MESSAGES_PER_PAGE as u64 + 3
+        );
+        assert_eq!(manager.total_messages_written_exact(), 5);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn subscribe() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let _manual = Receiver::new(0, manager.clone()).unwrap();
+
+        let mut subscribers: Vec<_> = (1..MAX_RECEIVER_GROUPS)
+            .map(|_| manager.subscribe().unwrap())
+            .collect();
+
+        let mut groups: Vec<_> = subscribers.iter().map(|rx| rx.group).collect();
+        groups.sort_unstable();
+        assert_eq!(groups, (1..MAX_RECEIVER_GROUPS).collect::<Vec<_>>());
+
+        assert!(matches!(manager.subscribe(), Err(Error::NoGroupsAvailable)));
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        tx.push("hello").unwrap();
+        for rx in subscribers.iter_mut() {
+            assert_eq!(rx.pop().unwrap(), b"hello");
+        }
+
+        let released = subscribers.swap_remove(5);
+        let released_group = released.group;
+        drop(released);
+
+        assert_eq!(manager.subscribe().unwrap().group, released_group);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn configured_receiver_groups() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .receiver_groups(8)
+            .build()
+            .unwrap();
+
+        assert!(matches!(
+            Receiver::new(8, manager.clone()),
+            Err(Error::GroupOutOfRange(8))
+        ));
+
+        let _subscribers: Vec<_> = (0..8).map(|_| manager.subscribe().unwrap()).collect();
+        assert!(matches!(manager.subscribe(), Err(Error::NoGroupsAvailable)));
+
+        assert!(matches!(
+            DataPagesManager::builder(&path).receiver_groups(16).build(),
+            Err(Error::FormatMismatch {
+                field: "receiver_groups",
+                expected: 16,
+                found: 8,
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            DataPagesManager::builder(&path)
+                .receiver_groups(MAX_RECEIVER_GROUPS + 1)
+                .build(),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        DataPagesManager::builder(&path)
+            .receiver_groups(8)
+            .build()
+            .unwrap();
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    fn fill_pages(tx: &mut Sender, pages: u32) -> Result<(), Error> {
+        for i in 0..pages * MAX_MESSAGES_PER_PAGE {
+            tx.push(i.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn skips_deleted_page_gap() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+        tx.push("after the gap").unwrap();
+        drop(tx);
+        drop(manager);
+
+        std::fs::remove_file(datapage_path(&path, DATAPAGE_FILE_STEM, 1)).unwrap();
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut grouped = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager.clone()).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            assert_eq!(grouped.pop().unwrap(), i.to_le_bytes());
+            assert_eq!(anon.pop().unwrap(), i.to_le_bytes());
+        }
+
+        assert_eq!(grouped.pop().unwrap(), b"after the gap");
+        assert_eq!(grouped.stats().datapage_count, 2);
+        assert_eq!(anon.pop().unwrap(), b"after the gap");
+
+        // the gap stays one, and won't take messages
+        assert!(!manager.page_exists(1));
+        assert_eq!(manager.list_pages().unwrap(), [0, 2]);
+        let (_, gap) = manager.get_or_create_datapage(1).unwrap();
+        assert!(gap.get().is_full());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn skips_gap_without_checkpoint() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 3).unwrap();
+        tx.push("after the gap").unwrap();
+        drop((tx, manager));
+
+        std::fs::remove_file(datapage_path(&path, DATAPAGE_FILE_STEM, 1)).unwrap();
+        let _ = std::fs::remove_file(checkpoint_path(&path, DATAPAGE_FILE_STEM));
+
+        // the oldest page is still found with one missing after it
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.page_range().unwrap(), (0, 3));
+        assert_eq!(manager.stats().mapped_pages, 4);
+        assert!(!manager.page_exists(1));
+
+        let mut rx = Receiver::new(0, manager).unwrap();
+        for i in
+            (0..MAX_MESSAGES_PER_PAGE).chain(2 * MAX_MESSAGES_PER_PAGE..3 * MAX_MESSAGES_PER_PAGE)
+        {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+        assert_eq!(rx.pop().unwrap(), b"after the gap");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn readers_dont_create_pages() {
+        let path = mkdir_random();
+        let writer = DataPagesManager::new(&path).unwrap();
+        let reader = DataPagesManager::new(&path).unwrap();
+        let next_page = datapage_path(&path, &writer.page_stem, 1);
+
+        let mut tx = Sender::new(writer.clone()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+
+        let mut peek = Receiver::new(1, reader.clone()).unwrap();
+        let mut rx = Receiver::new(0, reader.clone()).unwrap();
+        // pushes through another manager's mapping don't wake a parked pop,
+        // so spin on the slots, the wait for the page itself still polls
+        let consumer = thread::spawn(move || {
+            for i in 0..MAX_MESSAGES_PER_PAGE {
+                assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+            }
+            rx.pop_spin().unwrap().to_vec()
+        });
+
+        thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!consumer.is_finished());
+        assert!(!next_page.exists());
+
+        assert_eq!(
+            peek.skip(MAX_MESSAGES_PER_PAGE as usize).unwrap(),
+            MAX_MESSAGES_PER_PAGE as usize
+        );
+        assert_eq!(peek.try_pop().unwrap(), None);
+        assert!(reader.get_datapage(1).unwrap().is_none());
+        assert!(!next_page.exists());
+
+        // the writer's manager creates the page, the reader's maps it as is
+        tx.push("next").unwrap();
+        assert_eq!(consumer.join().unwrap(), b"next");
+        assert_eq!(peek.try_pop().unwrap(), Some(&b"next"[..]));
+        assert_eq!(writer.created_pages(), 1);
+        assert_eq!(reader.created_pages(), 0);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn consumer_waits_for_pages() {
+        let path = mkdir_random();
+        let writer = DataPagesManager::new(&path).unwrap();
+        let reader = DataPagesManager::builder(&path)
+            .consumer_creates_pages(false)
+            .build()
+            .unwrap();
+        let next_page = datapage_path(&path, &writer.page_stem, 1);
+
+        let mut tx = Sender::new(writer.clone()).unwrap();
+        let mut rx = Receiver::new_anon(reader.clone()).unwrap();
+        let seeker = thread::spawn(move || {
+            let at = rx.seek(1, 0).unwrap();
+            (at, rx.pop_spin().unwrap().to_vec())
+        });
+
+        thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!seeker.is_finished());
+        assert!(!next_page.exists());
+
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("next").unwrap();
+        assert_eq!(seeker.join().unwrap(), ((1, 0), b"next".to_vec()));
+        assert_eq!(reader.created_pages(), 0);
+
+        // with the default the receiver makes the page it asked for
+        let mut rx = Receiver::new_anon(writer.clone()).unwrap();
+        assert_eq!(rx.seek(5, 0).unwrap(), (2, 0));
+        assert!(writer.page_exists(2));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn sync_directory_on_create() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .sync_directory_on_create(true)
+            .build()
+            .unwrap();
+        assert!(manager.page_exists(0));
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+        tx.push("last").unwrap();
+        assert_eq!(manager.created_pages(), 2);
+        drop(tx);
+        drop(manager);
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.list_pages().unwrap(), vec![0, 1, 2]);
+        let mut rx = Receiver::new_anon(manager.clone()).unwrap();
+        assert_eq!(rx.seek(2, 0).unwrap(), (2, 0));
+        assert_eq!(rx.pop().unwrap(), b"last");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn reserve() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        tx.push("before").unwrap();
+
+        manager.reserve(5).unwrap();
+        assert_eq!(manager.page_range().unwrap(), (0, 5));
+        assert_eq!(manager.created_pages(), 5);
+        for num in 1..=5 {
+            assert!(datapage_path(&path, &manager.page_stem, num).exists());
+        }
+
+        // the producer finds every page already there
+        fill_pages(&mut tx, 5).unwrap();
+        tx.push("last").unwrap();
+        assert_eq!(tx.datapage_count, 5);
+        assert_eq!(manager.created_pages(), 5);
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        assert_eq!(rx.pop().unwrap(), b"before");
+        assert_eq!(
+            rx.skip(usize::MAX >> 1).unwrap(),
+            5 * MAX_MESSAGES_PER_PAGE as usize + 1
+        );
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        // never so many that the page being written is reclaimed
+        manager.set_max_datapages(3);
+        manager.reserve(5).unwrap();
+        assert_eq!(manager.page_range().unwrap(), (5, 7));
+        assert_eq!(manager.created_pages(), 7);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn delete_page() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_min_datapages(2).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 4).unwrap();
+        tx.push("newest").unwrap();
+        assert_eq!(manager.list_pages().unwrap(), [0, 1, 2, 3, 4]);
+
+        // the newest min_datapages pages, and pages outside the ring, stay
+        for num in [3, 4, 5] {
+            assert!(matches!(
+                manager.delete_page(num),
+                Err(Error::PageNotDeletable(n)) if n == num
+            ));
+        }
+        assert_eq!(manager.list_pages().unwrap(), [0, 1, 2, 3, 4]);
+
+        // a page in the middle leaves a gap that receivers skip
+        manager.delete_page(1).unwrap();
+        assert_eq!(manager.list_pages().unwrap(), [0, 2, 3, 4]);
+        assert_eq!(manager.page_range().unwrap(), (0, 4));
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+        assert_eq!(rx.pop().unwrap(), (2 * MAX_MESSAGES_PER_PAGE).to_le_bytes());
+        assert_eq!(rx.stats().datapage_count, 2);
+
+        // deleting the oldest page takes the gap right behind it along
+        manager.delete_page(0).unwrap();
+        assert_eq!(manager.list_pages().unwrap(), [2, 3, 4]);
+        assert_eq!(manager.page_range().unwrap(), (2, 4));
+        assert_eq!(manager.stats().mapped_pages, 3);
+        assert_eq!(manager.reclaimed_pages(), 0);
+
+        // receivers and reopened managers start from the oldest page left
+        let mut rx = Receiver::new(1, manager.clone()).unwrap();
+        assert_eq!(rx.pop().unwrap(), (2 * MAX_MESSAGES_PER_PAGE).to_le_bytes());
+
+        drop((tx, rx, manager));
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.stats().mapped_pages, 3);
+        assert!(matches!(
+            manager.delete_page(4),
+            Err(Error::PageNotDeletable(4))
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn verify() {
+        use std::os::unix::fs::FileExt;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+        for i in 0..10_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let report = manager.verify().unwrap();
+        assert!(report.is_ok(), "{report:?}");
+        assert_eq!(report.pages_checked, 3);
+
+        let corrupt = |page: usize, count: u32, idx_with_salt: u32| {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(datapage_path(&path, &manager.page_stem, page))
+                .unwrap()
+                .write_all_at(&idx_with_salt.to_le_bytes(), DataPage::slot_offset(count))
+                .unwrap();
+        };
+        corrupt(0, 5, MAX_BYTES_PER_PAGE - 2);
+        corrupt(1, 7, 0);
+        // producers might still publish this one
+        corrupt(2, 3, 0);
+        std::fs::write(datapage_path(&path, &manager.page_stem, 3), "short").unwrap();
+
+        let report = manager.verify().unwrap();
+        assert_eq!(report.pages_checked, 4);
+        assert_eq!(
+            report.problems,
+            vec![
+                (0, PageProblem::Slot(SlotProblem::OutOfBounds { count: 5 })),
+                (1, PageProblem::Slot(SlotProblem::Gap { count: 7 })),
+                (
+                    3,
+                    PageProblem::Size {
+                        len: 5,
+                        expected: size_of::<DataPage>() as u64
+                    }
+                ),
+            ]
+        );
+
+        // consumers carry on regardless
+        assert_eq!(rx.pop().unwrap(), 0_u32.to_le_bytes());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn overtaken_manager_skips_ahead() {
+        let path = mkdir_random();
+        let mut ahead = DataPagesManager::new(&path).unwrap();
+        ahead.set_max_datapages(2);
+        let behind = DataPagesManager::new(&path).unwrap();
+
+        let mut behind_tx = Sender::new(behind.clone()).unwrap();
+        let mut behind_rx = Receiver::new_anon(behind.clone()).unwrap();
+        let mut tx = Sender::new(ahead.clone()).unwrap();
+        fill_pages(&mut tx, 3).unwrap();
+        tx.push("page 3").unwrap();
+        assert_eq!(ahead.page_range().unwrap(), (2, 3));
+
+        // page 1 is gone, so both move on to page 2 rather than recreate it
+        behind_tx.push("behind").unwrap();
+        assert!(!behind.page_exists(1));
+        assert_eq!(behind.stats().datapage_count, 3);
+        assert_eq!(ahead.tail(2).unwrap(), [&b"page 3"[..], b"behind"]);
+
+        // a reader still on page 0 rolls to page 2 as well
+        for _ in 0..MAX_MESSAGES_PER_PAGE {
+            behind_rx.pop().unwrap();
+        }
+        assert_eq!(
+            behind_rx.pop().unwrap(),
+            (2 * MAX_MESSAGES_PER_PAGE).to_le_bytes()
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    /// Set for the child process `two_process_producers` spawns, to the
+    /// directory it should push to.
+    const CHILD_PRODUCER_DIR: &str = "DISK_MPMC_TEST_CHILD_PRODUCER_DIR";
+    const PRODUCER_MESSAGES: u32 = MAX_MESSAGES_PER_PAGE * 2;
+
+    /// Pushes `PRODUCER_MESSAGES` messages tagged with `producer`.
+    fn produce(path: &Path, producer: u8) {
+        let mut tx = Sender::new(DataPagesManager::new(path).unwrap()).unwrap();
+
+        for i in 0..PRODUCER_MESSAGES {
+            let mut msg = vec![producer];
+            msg.extend(i.to_le_bytes());
+            tx.push(msg).unwrap();
+        }
+    }
+
+    /// The child half of `two_process_producers`.
+    #[test]
+    #[ignore = "run by two_process_producers in a child process"]
+    fn child_producer() {
+        let path = std::env::var_os(CHILD_PRODUCER_DIR).expect("run by two_process_producers");
+        produce(Path::new(&path), 1);
+    }
+
+    #[test]
+    fn two_process_producers() {
+        let path = mkdir_random();
+        DataPagesManager::new(&path).unwrap();
+
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "manager::test::child_producer",
+                "--ignored",
+                "--quiet",
+            ])
+            .env(CHILD_PRODUCER_DIR, &path)
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        produce(&path, 0);
+        assert!(child.wait().unwrap().success());
+
+        // every message once, in each producer's order, and no gaps in the pages
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut rx = Receiver::new_anon(manager.clone()).unwrap();
+        let mut next = [0_u32; 2];
+        while let Some(msg) = rx.try_pop().unwrap() {
+            let producer = msg[0] as usize;
+            assert_eq!(msg[1..], next[producer].to_le_bytes());
+            next[producer] += 1;
+        }
+
+        assert_eq!(next, [PRODUCER_MESSAGES; 2]);
+        let (first, last) = manager.page_range().unwrap();
+        assert_eq!(first, 0);
+        assert_eq!(manager.list_pages().unwrap().len(), last + 1);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn woken_by_another_process() {
+        let path = mkdir_random();
+        let mut rx = Receiver::new_anon(DataPagesManager::new(&path).unwrap()).unwrap();
+
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "manager::test::child_producer",
+                "--ignored",
+                "--quiet",
+            ])
+            .env(CHILD_PRODUCER_DIR, &path)
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+
+        // parked well before the child pushes, only its wake gets us out in time
+        let msg = rx
+            .pop_until(Instant::now() + Duration::from_secs(10))
+            .unwrap()
+            .expect("woken by the child's push");
+        assert_eq!(msg[0], 1);
+        assert!(child.wait().unwrap().success());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    /// Set for the child process `failed_page_creation_leaves_nothing`
+    /// spawns, to the directory it should fail to create page 1 in.
+    const CHILD_CREATE_DIR: &str = "DISK_MPMC_TEST_CHILD_CREATE_DIR";
+
+    /// The child half of `failed_page_creation_leaves_nothing`, does nothing
+    /// on its own.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn child_create_page() {
+        let Some(path) = std::env::var_os(CHILD_CREATE_DIR) else {
+            return;
+        };
+
+        // files can't grow to a page, so the new page's file gets created
+        // but can't be sized, like on a full disk
+        unsafe {
+            libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+            let limit = libc::rlimit {
+                rlim_cur: 1 << 20,
+                rlim_max: libc::RLIM_INFINITY,
+            };
+            assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &limit), 0);
+        }
+
+        let manager = DataPagesManager::new(path).unwrap();
+        assert!(matches!(
+            manager.get_or_create_datapage(1),
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::FileTooLarge
+        ));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn failed_page_creation_leaves_nothing() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "manager::test::child_create_page", "--quiet"])
+            .env(CHILD_CREATE_DIR, &path)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let names: Vec<_> = std::fs::read_dir(&path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(names.iter().all(|name| !name.ends_with(".tmp")));
+        assert!(!manager.page_exists(1));
+
+        // so the queue opens and carries on as if nothing happened
+        drop((tx, manager));
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.page_range().unwrap(), (0, 0));
+        Sender::new(manager.clone())
+            .unwrap()
+            .push("page 1")
+            .unwrap();
+        assert_eq!(manager.page_range().unwrap(), (0, 1));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn renamed_page_is_rejected() {
+        let path = mkdir_random();
+        let mut tx = Sender::new(DataPagesManager::new(&path).unwrap()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("page 1").unwrap();
+        drop(tx);
+
+        std::fs::rename(
+            datapage_path(&path, DATAPAGE_FILE_STEM, 1),
+            datapage_path(&path, DATAPAGE_FILE_STEM, 5),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            DataPagesManager::new(&path),
+            Err(Error::FormatMismatch {
+                field: "page",
+                expected: 5,
+                found: 1,
+                ..
+            })
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn min_datapages() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(5);
+
+        assert!(matches!(
+            manager.set_min_datapages(6),
+            Err(Error::InvalidConfig(_))
+        ));
+        manager.set_min_datapages(3).unwrap();
+
+        // retention as aggressive as it gets, the floor still holds
+        manager.set_max_datapages(1);
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 6).unwrap();
+        tx.push("last").unwrap();
+
+        assert_eq!(manager.stats().mapped_pages, 3);
+        assert_eq!(manager.list_pages().unwrap().len(), 3);
+        assert_eq!(manager.page_range().unwrap(), (4, 6));
+
+        // a late consumer replays the whole floor
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        assert_eq!(
+            rx.drain(usize::MAX).unwrap().len(),
+            2 * MAX_MESSAGES_PER_PAGE as usize + 1
+        );
+
+        manager.set_min_datapages(0).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        assert_eq!(manager.stats().mapped_pages, 1);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn recycled_pages_with_parked_consumers() {
+        const PAGES: u32 = 6;
+
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(2);
+
+        let last = PAGES * MAX_MESSAGES_PER_PAGE - 1;
+        let consumers: Vec<_> = (0..3)
+            .map(|group| {
+                let mut rx = Receiver::new(group, manager.clone()).unwrap();
+
+                std::thread::spawn(move || {
+                    let mut prev = None;
+
+                    loop {
+                        let i = u32::from_le_bytes(rx.pop().unwrap().try_into().unwrap());
+                        // reclaimed pages may be skipped, but never replayed
+                        assert!(prev < Some(i), "{prev:?} then {i}");
+                        prev = Some(i);
+
+                        if i == last {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        // let the consumers park on the empty first page
+        std::thread::sleep(Duration::from_millis(50));
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, PAGES).unwrap();
+
+        for consumer in consumers {
+            consumer.join().unwrap();
+        }
+
+        assert_eq!(
+            manager.page_range().unwrap(),
+            (PAGES as usize - 2, PAGES as usize - 1)
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn on_reclaim() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(2);
+
+        let reclaimed = Arc::new(Mutex::new(Vec::new()));
+        manager.on_reclaim({
+            let reclaimed = reclaimed.clone();
+            move |page, path| {
+                let len = std::fs::metadata(path).unwrap().len();
+                reclaimed.lock().push((page, path.to_owned(), len));
+            }
+        });
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 4).unwrap();
+
+        assert_eq!(manager.created_pages(), 3);
+        assert_eq!(manager.reclaimed_pages(), 2);
+
+        let page_len = size_of::<DataPage>() as u64;
+        assert_eq!(
+            *reclaimed.lock(),
+            [
+                (0, datapage_path(&path, DATAPAGE_FILE_STEM, 0), page_len),
+                (1, datapage_path(&path, DATAPAGE_FILE_STEM, 1), page_len),
+            ]
+        );
+        assert!(!datapage_path(&path, DATAPAGE_FILE_STEM, 1).exists());
+
+        // a page deleted ahead of time has no file left to hand to the hook
+        manager.set_max_datapages(3);
+        for _ in 0..MAX_MESSAGES_PER_PAGE {
+            tx.push("page 4").unwrap();
+        }
+        manager.delete_page(3).unwrap();
+        manager.set_max_datapages(2);
+        tx.push("page 5").unwrap();
+
+        assert_eq!(manager.page_range().unwrap(), (4, 5));
+        let reclaimed = reclaimed.lock();
+        assert_eq!(reclaimed.len(), 3);
+        assert_eq!(
+            reclaimed[2],
+            (2, datapage_path(&path, DATAPAGE_FILE_STEM, 2), page_len)
+        );
+        drop(reclaimed);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn map_page() {
+        let path = mkdir_random();
+        let mapped = Arc::new(Mutex::new(Vec::new()));
+        let builder = || {
+            let mapped = mapped.clone();
+            DataPagesManager::builder(&path).map_page(move |page_path| {
+                let datapage = DataPage::open(page_path)?;
+                mapped
+                    .lock()
+                    .push(page_path.file_name().unwrap().to_str().unwrap().to_owned());
+                Ok(datapage)
+            })
+        };
+
+        let manager = builder().build().unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+        tx.push("last").unwrap();
+
+        // new pages are mapped before they're linked in under their name
+        let created = std::mem::take(&mut *mapped.lock());
+        assert_eq!(created.len(), 3);
+        assert!(created.iter().all(|name| name.ends_with(".tmp")));
+        drop(tx);
+        drop(manager);
+
+        let manager = builder().build().unwrap();
+        let expected: Vec<_> = (0..3)
+            .map(|i| format!("{}.{i}", manager.page_stem))
+            .collect();
+        assert_eq!(*mapped.lock(), expected);
+
+        let mut rx = Receiver::new_anon(manager.clone()).unwrap();
+        rx.seek(2, 0).unwrap();
+        assert_eq!(rx.pop().unwrap(), b"last");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn overflow_drop_oldest() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(3);
+
+        let _rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 4).unwrap();
+
+        assert!(!datapage_path(&path, DATAPAGE_FILE_STEM, 0).exists());
+        assert_eq!(manager.stats().mapped_pages, 3);
+
+        let mut rx = Receiver::new(1, manager).unwrap();
+        assert_eq!(rx.pop().unwrap(), MAX_MESSAGES_PER_PAGE.to_le_bytes());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn overflow_drop_newest() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::builder(&path)
+            .overflow_policy(OverflowPolicy::DropNewest)
+            .build()
+            .unwrap();
+        manager.set_max_datapages(2);
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+
+        assert!(matches!(tx.push("overflow"), Err(Error::Full)));
+
+        for _ in 0..MAX_MESSAGES_PER_PAGE {
+            rx.pop().unwrap();
+        }
+
+        tx.push("overflow").unwrap();
+        assert!(!datapage_path(&path, DATAPAGE_FILE_STEM, 0).exists());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn overflow_block() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::builder(&path)
+            .overflow_policy(OverflowPolicy::Block)
+            .build()
+            .unwrap();
+        manager.set_max_datapages(2);
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let done_clone = done.clone();
+
+        let producer = thread::spawn(move || {
+            fill_pages(&mut tx, 2).unwrap();
+            tx.push("overflow").unwrap();
+            done_clone.store(true, Ordering::Relaxed);
+        });
+
+        thread::sleep(Duration::from_millis(500));
+        assert!(!done.load(Ordering::Relaxed));
+
+        for _ in 0..MAX_MESSAGES_PER_PAGE {
+            rx.pop().unwrap();
+        }
+
+        producer.join().unwrap();
+        assert!(done.load(Ordering::Relaxed));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn page_creation_limit() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .page_creation_limit(1, ThrottlePolicy::Fail)
+            .build()
+            .unwrap();
+
+        // a page within the limit goes through
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+        assert_eq!(manager.created_pages(), 1);
+
+        // tiny messages rolling pages as fast as they can don't
+        assert!(matches!(tx.push("runaway"), Err(Error::RolloverThrottled)));
+        assert_eq!(manager.stats().datapage_count, 1);
+
+        thread::sleep(Duration::from_millis(1100));
+        tx.push("runaway").unwrap();
+        assert_eq!(manager.stats().datapage_count, 2);
+
+        std::fs::remove_dir_all(path).unwrap();
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .page_creation_limit(1, ThrottlePolicy::Block)
+            .build()
+            .unwrap();
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+
+        let start = Instant::now();
+        tx.push("runaway").unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(manager.created_pages(), 2);
+
+        assert!(matches!(
+            DataPagesManager::builder(&path)
+                .page_creation_limit(0, ThrottlePolicy::Fail)
+                .build(),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn heartbeat_ttl() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::builder(&path)
+            .overflow_policy(OverflowPolicy::DropNewest)
+            .heartbeat_ttl(Duration::from_millis(300))
+            .build()
+            .unwrap();
+        manager.set_max_datapages(2);
+
+        let rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+
+        rx.heartbeat();
+        assert!(matches!(tx.push("overflow"), Err(Error::Full)));
+
+        thread::sleep(Duration::from_millis(200));
+        rx.heartbeat();
+        thread::sleep(Duration::from_millis(200));
+        assert!(manager.is_group_alive(0));
+        assert!(matches!(tx.push("overflow"), Err(Error::Full)));
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(!manager.is_group_alive(0));
+        tx.push("overflow").unwrap();
+
+        // a manager with no receivers of its own sees another's heartbeats
+        let other = DataPagesManager::builder(&path)
+            .heartbeat_ttl(Duration::from_millis(300))
+            .build()
+            .unwrap();
+        assert_eq!(other.group_consumer_count(0), 0);
+        assert!(!other.is_group_alive(0));
+        rx.heartbeat();
+        assert!(other.is_group_alive(0));
+        assert!(!other.is_group_alive(1));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_tail_page() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("lost").unwrap();
+        drop((tx, manager));
+
+        // what a crash between creating the file and sizing it leaves behind
+        let truncate = |page: usize, len: u64| {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(datapage_path(&path, DATAPAGE_FILE_STEM, page))
+                .unwrap()
+                .set_len(len)
+                .unwrap();
+        };
+        truncate(1, 0);
+
+        assert!(matches!(
+            DataPagesManager::new(&path),
+            Err(Error::TruncatedPage { len: 0, .. })
+        ));
+        assert!(DataPagesManager::repair(&path).unwrap());
+        assert!(!DataPagesManager::repair(&path).unwrap());
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        tx.push("after repair").unwrap();
+        assert_eq!(rx.pop().unwrap(), b"after repair");
+        drop((tx, rx, manager));
+
+        // only the newest page can be recreated
+        truncate(0, 4096);
+        assert!(matches!(
+            DataPagesManager::repair(&path),
+            Err(Error::TruncatedPage { len: 4096, .. })
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode_of = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .file_mode(0o660)
+            .build()
+            .unwrap();
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("next page").unwrap();
+
+        assert_eq!(mode_of(&path), 0o770);
+        assert_eq!(mode_of(&datapage_path(&path, DATAPAGE_FILE_STEM, 0)), 0o660);
+        assert_eq!(mode_of(&datapage_path(&path, DATAPAGE_FILE_STEM, 1)), 0o660);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn preallocate() {
+        use std::os::unix::fs::MetadataExt;
+
+        let allocated = |path: &Path| std::fs::metadata(path).unwrap().blocks() * 512;
+        let page_len = size_of::<DataPage>() as u64;
+
+        let sparse = mkdir_random();
+        let manager = DataPagesManager::new(&sparse).unwrap();
+        Sender::new(manager).unwrap().push("sparse").unwrap();
+        assert!(allocated(&datapage_path(&sparse, DATAPAGE_FILE_STEM, 0)) < page_len / 2);
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .preallocate(true)
+            .build()
+            .unwrap();
+        let mut tx = Sender::new(manager).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("next page").unwrap();
+        assert!(allocated(&datapage_path(&path, DATAPAGE_FILE_STEM, 0)) >= page_len);
+        assert!(allocated(&datapage_path(&path, DATAPAGE_FILE_STEM, 1)) >= page_len);
+
+        std::fs::remove_dir_all(sparse).unwrap();
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn prefault() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .prefault(true)
+            .build()
+            .unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("next page").unwrap();
+
+        // touching the pages leaves them empty
+        assert!(manager.verify().unwrap().is_ok());
+        let mut rx = Receiver::new(0, manager).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+        assert_eq!(rx.pop().unwrap(), b"next page");
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reuse_pages() {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = mkdir_random();
+        let ino = |num: usize| {
+            std::fs::metadata(datapage_path(&path, DATAPAGE_FILE_STEM, num))
+                .unwrap()
+                .ino()
+        };
+
+        let mut manager = DataPagesManager::builder(&path)
+            .reuse_pages(true)
+            .build()
+            .unwrap();
+        manager.set_max_datapages(2);
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+
+        let mut rx = Receiver::new(1, manager.clone()).unwrap();
+        for _ in 0..5 {
+            rx.pop().unwrap();
+        }
+        drop(rx);
+
+        // page 0's file comes back as page 2, with nothing left of page 0
+        let page_0 = ino(0);
+        tx.push("fresh").unwrap();
+        assert!(!manager.page_exists(0));
+        assert_eq!(ino(2), page_0);
+        assert_eq!(manager.reclaimed_pages(), 1);
+
+        let (_, page_2) = manager.get_or_create_datapage(2).unwrap();
+        assert_eq!(page_2.get().message_count(), 1);
+        assert_eq!(page_2.get().get_group_count(1), 0);
+        assert_eq!(manager.tail(2).unwrap().last().unwrap(), b"fresh");
+
+        let mut rx = Receiver::new_anon(manager.clone()).unwrap();
+        assert_eq!(rx.seek(2, 0).unwrap(), (2, 0));
+        assert_eq!(rx.pop().unwrap(), b"fresh");
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        // reopening checks the header records the new number
+        drop((tx, rx, page_2, manager));
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.tail(1).unwrap(), [b"fresh"]);
+
+        // a page something still holds is deleted instead
+        let mut manager = DataPagesManager::builder(&path)
+            .reuse_pages(true)
+            .build()
+            .unwrap();
+        manager.set_max_datapages(2);
+        let (_, held) = manager.get_or_create_datapage(1).unwrap();
+        let page_1 = ino(1);
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+
+        assert!(!manager.page_exists(1));
+        assert_ne!(ino(3), page_1);
+        assert_eq!(
+            held.get().try_get(0).unwrap().unwrap(),
+            MAX_MESSAGES_PER_PAGE.to_le_bytes()
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn reopen_after_interrupted_recycle() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+        drop((tx, manager));
+
+        // what a crash right after `reuse_pages` renamed page 0 leaves behind
+        let page_0 = datapage_path(&path, DATAPAGE_FILE_STEM, 0);
+        DataPage::open(&page_0).unwrap().get().clear();
+        std::fs::rename(&page_0, datapage_path(&path, DATAPAGE_FILE_STEM, 2)).unwrap();
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.page_range().unwrap(), (1, 2));
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        tx.push("after").unwrap();
+        assert_eq!(manager.tail(1).unwrap(), [b"after"]);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn release_reclaimed() {
+        // resident kB of the mapping starting at `addr`, from /proc/self/smaps
+        let rss_kb = |addr: usize| {
+            let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+            let start = format!("{addr:x}-");
+            smaps
+                .lines()
+                .skip_while(|line| !line.starts_with(&start))
+                .find_map(|line| line.strip_prefix("Rss:"))
+                .map(|kb| {
+                    kb.trim()
+                        .trim_end_matches("kB")
+                        .trim()
+                        .parse::<u64>()
+                        .unwrap()
+                })
+                .unwrap()
+        };
+
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::builder(&path)
+            .release_reclaimed(true)
+            .build()
+            .unwrap();
+        manager.set_max_datapages(2);
+
+        let mut lagging = Receiver::new_anon(manager.clone()).unwrap();
+        let addr = lagging.datapage.get() as *const DataPage as usize;
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+        assert!(rss_kb(addr) > 0);
+
+        tx.push("page 2").unwrap();
+        assert!(!manager.page_exists(0));
+        assert_eq!(rss_kb(addr), 0);
+
+        // the receiver still reads the page as it was
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            assert_eq!(lagging.pop().unwrap(), i.to_le_bytes());
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn release_reclaimed_skips_custom_mappings() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::builder(&path)
+            .release_reclaimed(true)
+            .map_page(|path| {
+                let file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(path)?;
+                // private, the messages only ever live in this mapping
+                let m = unsafe {
+                    memmap2::MmapOptions::new()
+                        .len(size_of::<DataPage>())
+                        .map_copy(&file)?
+                };
+                Ok(unsafe { MmapCell::new(m) })
+            })
+            .build()
+            .unwrap();
+        manager.set_max_datapages(2);
+
+        let mut lagging = Receiver::new_anon(manager.clone()).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 2).unwrap();
+        tx.push("page 2").unwrap();
+        assert!(!manager.page_exists(0));
+
+        // dropping the memory would have zeroed it
+        for i in 0..MAX_MESSAGES_PER_PAGE {
+            assert_eq!(lagging.pop().unwrap(), i.to_le_bytes());
+        }
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn shutdown() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("durable").unwrap();
+
+        manager.clone().shutdown().unwrap();
+        assert_eq!(manager.stats().mapped_pages, 0);
+        assert!(matches!(tx.push("after"), Err(Error::Closed)));
+        assert!(matches!(
+            tx.try_push("after"),
+            Err(crate::TryPushError::Closed)
+        ));
+        assert!(matches!(
+            Receiver::new(0, manager.clone()),
+            Err(Error::Closed)
+        ));
+        assert!(matches!(manager.compact(), Err(Error::Closed)));
+        drop((tx, manager));
+
+        // reopening sees everything pushed before the shutdown
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.stats().datapage_count, 1);
+        drop(manager);
+
+        let page = std::fs::read(datapage_path(&path, DATAPAGE_FILE_STEM, 1)).unwrap();
+        assert!(page.windows(7).any(|w| w == b"durable"));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn page_range() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(2);
+        assert_eq!(manager.page_range().unwrap(), (0, 0));
+        assert!(manager.page_exists(0));
+        assert!(!manager.page_exists(1));
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 3).unwrap();
+        tx.push("tail").unwrap();
+        assert_eq!(manager.page_range().unwrap(), (2, 3));
+        assert!(!manager.page_exists(1));
+        assert!(manager.page_exists(2));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_fallback() {
+        let path = mkdir_random();
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(2);
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 3).unwrap();
+        tx.push("tail").unwrap();
+        drop((tx, manager));
+
+        let checkpoint = checkpoint_path(&path, DATAPAGE_FILE_STEM);
+        assert_eq!(read_checkpoint(&path, DATAPAGE_FILE_STEM), Some((2, 3)));
+
+        let stale = [0_u64, 1, 2].map(u64::to_le_bytes).concat();
+        for contents in [None, Some(&b"garbage"[..]), Some(&stale[..])] {
+            match contents {
+                Some(contents) => std::fs::write(&checkpoint, contents).unwrap(),
+                None => std::fs::remove_file(&checkpoint).unwrap(),
+            }
+            assert_eq!(read_checkpoint(&path, DATAPAGE_FILE_STEM), None);
+
+            // the scan finds the same pages and doesn't resurrect reclaimed ones
+            let manager = DataPagesManager::new(&path).unwrap();
+            let stats = manager.stats();
+            assert_eq!((stats.mapped_pages, stats.datapage_count), (2, 3));
+            assert_eq!(manager.page_range().unwrap(), (2, 3));
+            assert_eq!(manager.tail(1).unwrap(), [b"tail"]);
+        }
+
+        // the next rollover writes a fresh one
+        let mut manager = DataPagesManager::new(&path).unwrap();
+        manager.set_max_datapages(2);
+        manager.get_or_create_datapage(4).unwrap();
+        assert_eq!(read_checkpoint(&path, DATAPAGE_FILE_STEM), Some((3, 4)));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn unrelated_files_are_ignored() {
+        let path = mkdir_random();
+        let mut tx = Sender::new(DataPagesManager::new(&path).unwrap()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("page 1").unwrap();
+        drop(tx);
+
+        for name in [
+            ".dp.data.maxi.tmp",
+            ".dp.data.maxi.7.tmp",
+            ".dp.data.maxi.+9",
+            ".dp.data.maxi.99999999999999999999999",
+            ".dp.data.maxi.",
+            "x.y.dp.data.maxi.3",
+            ".dp.offset.maxi.0",
+            "notes.txt",
+        ] {
+            std::fs::write(path.join(name), "not a page").unwrap();
+        }
+        std::fs::create_dir(path.join("subdir")).unwrap();
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.list_pages().unwrap().len(), 2);
+        assert_eq!(manager.page_range().unwrap(), (0, 1));
+        assert!(DataPagesManager::topics(&path).unwrap().is_empty());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn legacy_pages_are_migrated() {
+        let path = mkdir_random();
+
+        // a page as written before pages had a header is today's page minus
+        // the header, under the old name
+        let scratch = path.join("scratch");
+        for num in 0..2 {
+            let page = DataPage::new(&scratch).unwrap();
+            page.get_mut().push(format!("legacy {num}")).unwrap();
+            if num == 0 {
+                page.get().close();
+            }
+            drop(page);
+
+            let bytes = std::fs::read(&scratch).unwrap();
+            let legacy = datapage_path(&path, LEGACY_DATAPAGE_FILE_STEM, num);
+            std::fs::write(legacy, &bytes[bytes.len() - LEGACY_PAGE_SIZE..]).unwrap();
+            std::fs::remove_file(&scratch).unwrap();
+        }
+
+        // they can't take timestamps, and nothing is touched
+        assert!(matches!(
+            DataPagesManager::builder(&path).timestamps(true).build(),
+            Err(Error::FormatMismatch { field: "flags", .. })
+        ));
+        assert!(datapage_path(&path, LEGACY_DATAPAGE_FILE_STEM, 0).exists());
+        assert!(!datapage_path(&path, DATAPAGE_FILE_STEM, 0).exists());
+
+        let manager = DataPagesManager::new(&path).unwrap();
+        assert_eq!(manager.page_range().unwrap(), (0, 1));
+        for num in 0..2 {
+            assert!(!datapage_path(&path, LEGACY_DATAPAGE_FILE_STEM, num).exists());
+        }
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager).unwrap();
+        tx.push("new").unwrap();
+        assert_eq!(rx.pop().unwrap(), b"legacy 0");
+        assert_eq!(rx.pop().unwrap(), b"legacy 1");
+        assert_eq!(rx.pop().unwrap(), b"new");
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn custom_file_stem() {
+        let path = mkdir_random();
+        let open = || {
+            DataPagesManager::builder(&path)
+                .topic("mystream")
+                .file_stem(".page")
+                .build()
+        };
+
+        let mut tx = Sender::new(open().unwrap()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("page 1").unwrap();
+        drop(tx);
+
+        assert!(path.join("mystream.page.0").exists());
+        assert!(path.join("mystream.page.1").exists());
+        assert!(!datapage_path(&path, DATAPAGE_FILE_STEM, 0).exists());
+
+        let manager = open().unwrap();
+        assert_eq!(manager.page_range().unwrap(), (0, 1));
+        let mut rx = Receiver::new(0, manager).unwrap();
+        rx.skip(MAX_MESSAGES_PER_PAGE as usize).unwrap();
+        assert_eq!(rx.pop().unwrap(), b"page 1");
+        drop(rx);
+
+        // listing and repairing go by the stem too
+        assert_eq!(
+            DataPagesManager::builder(&path)
+                .file_stem(".page")
+                .topics()
+                .unwrap(),
+            ["mystream"]
+        );
+        assert!(DataPagesManager::topics(&path).unwrap().is_empty());
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path.join("mystream.page.1"))
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        assert!(!DataPagesManager::repair(&path).unwrap());
+        assert!(DataPagesManager::builder(&path)
+            .topic("mystream")
+            .file_stem(".page")
+            .repair()
+            .unwrap());
+        assert_eq!(open().unwrap().page_range().unwrap(), (0, 0));
+
+        assert!(matches!(
+            DataPagesManager::builder(&path)
+                .file_stem(OFFSET_FILE_STEM)
+                .build(),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn wait_for_offset() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let short = std::time::Duration::from_millis(20);
+        let long = std::time::Duration::from_secs(10);
+
+        assert!(manager.wait_for_offset(0, short).unwrap());
+        assert!(!manager.wait_for_offset(1, short).unwrap());
+
+        let next_page = MAX_MESSAGES_PER_PAGE as u64;
+        let producer = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            fill_pages(&mut tx, 1).unwrap();
+            thread::sleep(std::time::Duration::from_millis(50));
+            tx.push("next").unwrap();
+            tx
+        });
+
+        assert!(manager.wait_for_offset(3, long).unwrap());
+        // on a page that doesn't exist yet
+        assert!(manager.wait_for_offset(next_page + 1, long).unwrap());
+        let mut tx = producer.join().unwrap();
+        assert!(!manager.wait_for_offset(next_page + 2, short).unwrap());
+
+        // a page cut short by its bytes is done with at its end
+        tx.push(vec![0; MAX_MESSAGE_SIZE - 100]).unwrap();
+        tx.push(vec![0; 200]).unwrap();
+        assert!(manager
+            .wait_for_offset(next_page + MAX_MESSAGES_PER_PAGE as u64 - 1, short)
+            .unwrap());
+
+        // nothing was consumed
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        assert_eq!(rx.pop().unwrap(), 0_u32.to_le_bytes());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn tail() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let as_bytes = |range: std::ops::Range<u32>| -> Vec<Vec<u8>> {
+            range.map(|i| i.to_le_bytes().to_vec()).collect()
+        };
+
+        assert!(manager.tail(3).unwrap().is_empty());
+
+        for i in 0..3_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(manager.tail(10).unwrap(), as_bytes(0..3));
+        assert!(manager.tail(0).unwrap().is_empty());
+
+        for i in 3..MAX_MESSAGES_PER_PAGE + 3 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let end = MAX_MESSAGES_PER_PAGE + 3;
+        assert_eq!(manager.tail(2).unwrap(), as_bytes(end - 2..end));
+        // split across the page boundary
+        assert_eq!(manager.tail(8).unwrap(), as_bytes(end - 8..end));
+
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        assert_eq!(rx.pop().unwrap(), 0_u32.to_le_bytes());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn tail_only_reads() {
+        use std::os::unix::fs::FileExt;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+
+        // only one of these fits on a page, so the second marks slot 1 as the
+        // end of page 0 and rolls
+        let big = vec![7; MAX_MESSAGE_SIZE / 2 + 1];
+        tx.push(&big).unwrap();
+        tx.push(&big).unwrap();
+        assert_eq!(manager.tail(10).unwrap(), [big.clone(), big]);
+
+        // a receiver reaching the marker would pass it on to slot 2
+        let page = std::fs::File::open(datapage_path(&path, DATAPAGE_FILE_STEM, 0)).unwrap();
+        let mut slot = [0xff; 4];
+        page.read_exact_at(&mut slot, DataPage::slot_offset(2))
+            .unwrap();
+        assert_eq!(slot, [0; 4]);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn size_histogram() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .size_histogram(true)
+            .build()
+            .unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+
+        for len in [0, 1, 2, 3, 4, 7, 8, 1000, 1024] {
+            tx.push(vec![0; len]).unwrap();
+        }
+        assert!(tx.try_push([0; 5]).is_ok());
+        // only pushes that made it count
+        assert!(tx.push(vec![0; MAX_BYTES_PER_PAGE as usize]).is_err());
+
+        let mut expected = [0; SIZE_BUCKETS];
+        expected[..5].copy_from_slice(&[1, 1, 2, 3, 1]);
+        expected[10] = 1;
+        expected[11] = 1;
+        assert_eq!(manager.size_histogram(), expected);
+
+        // off by default
+        let manager = DataPagesManager::new(&path).unwrap();
+        Sender::new(manager.clone()).unwrap().push("msg").unwrap();
+        assert_eq!(manager.size_histogram(), [0; SIZE_BUCKETS]);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        const TOTAL_MESSAGES: u32 = MAX_MESSAGES_PER_PAGE + 100;
+
+        let from_path = mkdir_random();
+        let from = DataPagesManager::builder(&from_path)
+            .timestamps(true)
+            .keys(true)
+            .build()
+            .unwrap();
+        let mut tx = Sender::new(from.clone()).unwrap();
+        for i in 0..TOTAL_MESSAGES {
+            tx.push_keyed(i.into(), i.to_le_bytes()).unwrap();
+        }
+
+        let mut rx = Receiver::new(0, from.clone()).unwrap();
+        assert_eq!(rx.pop().unwrap(), 0_u32.to_le_bytes());
+
+        let mut exported = Vec::new();
+        assert_eq!(from.export(&mut exported).unwrap(), TOTAL_MESSAGES as u64);
+        // length, timestamp, key and payload
+        assert_eq!(exported.len(), TOTAL_MESSAGES as usize * 24);
+        // a read-only walk, the group is where it was
+        assert_eq!(rx.pop().unwrap(), 1_u32.to_le_bytes());
+
+        let to_path = mkdir_random();
+        let to = DataPagesManager::builder(&to_path)
+            .timestamps(true)
+            .keys(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            to.import(&mut exported.as_slice()).unwrap(),
+            TOTAL_MESSAGES as u64
+        );
+
+        let mut rx = Receiver::new(0, to.clone()).unwrap();
+        for i in 0..TOTAL_MESSAGES {
+            assert_eq!(rx.pop_keyed().unwrap(), (i.into(), &i.to_le_bytes()[..]));
+        }
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        // timestamps and keys made it across too
+        let mut reexported = Vec::new();
+        to.export(&mut reexported).unwrap();
+        assert_eq!(reexported, exported);
+
+        // cut off inside the last message
+        let truncated = &exported[..exported.len() - 1];
+        let empty_path = mkdir_random();
+        let empty = DataPagesManager::new(&empty_path).unwrap();
+        assert!(matches!(
+            empty.import(&mut &truncated[..]),
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+        assert_eq!(empty.tail(1).unwrap(), [(TOTAL_MESSAGES - 2).to_le_bytes()]);
+
+        for path in [from_path, to_path, empty_path] {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn export_stops_at_unfinished_push() {
+        use std::os::unix::fs::FileExt;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        fill_pages(&mut tx, 1).unwrap();
+        tx.push("page 1").unwrap();
+
+        // as if the push of message 5 on page 0 hadn't published it yet
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(datapage_path(&path, DATAPAGE_FILE_STEM, 0))
+            .unwrap()
+            .write_all_at(&0_u32.to_le_bytes(), DataPage::slot_offset(5))
+            .unwrap();
+
+        let mut exported = Vec::new();
+        assert_eq!(manager.export(&mut exported).unwrap(), 5);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn export_encrypted() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .encryption_key([7; 32])
+            .build()
+            .unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..MAX_MESSAGES_PER_PAGE + 3 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let mut exported = Vec::new();
+        manager.export(&mut exported).unwrap();
+
+        let msgs = exported.chunks(24).map(|chunk| {
+            assert_eq!(chunk[..4], 4_u32.to_le_bytes());
+            // no timestamps or keys on this queue
+            assert_eq!(chunk[4..20], [0; 16]);
+            u32::from_le_bytes(chunk[20..].try_into().unwrap())
+        });
+        assert!(msgs.eq(0..MAX_MESSAGES_PER_PAGE + 3));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn topics() {
+        let path = mkdir_random();
+        let orders = DataPagesManager::new_topic(&path, "orders").unwrap();
+        let users = DataPagesManager::new_topic(&path, "users").unwrap();
+        let default = DataPagesManager::new(&path).unwrap();
+
+        let mut orders_tx = Sender::new(orders.clone()).unwrap();
+        let mut users_tx = Sender::new(users.clone()).unwrap();
+        fill_pages(&mut orders_tx, 1).unwrap();
+        orders_tx.push("order").unwrap();
+        users_tx.push("user").unwrap();
+
+        let mut orders_rx = Receiver::new(0, orders.clone()).unwrap();
+        let mut users_rx = Receiver::new(0, users.clone()).unwrap();
+        assert_eq!(orders_rx.pop().unwrap(), 0_u32.to_le_bytes());
+        assert_eq!(users_rx.pop().unwrap(), b"user");
+        assert_eq!(users_rx.try_pop().unwrap(), None);
+        assert_eq!(
+            Receiver::new(0, default.clone())
+                .unwrap()
+                .try_pop()
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(orders.list_pages().unwrap().len(), 2);
+        assert_eq!(users.list_pages().unwrap().len(), 1);
+        assert_eq!(
+            DataPagesManager::topics(&path).unwrap(),
+            ["orders", "users"]
+        );
+
+        // reopening a topic picks up its own pages only
+        drop((orders_tx, orders_rx, orders));
+        let orders = DataPagesManager::new_topic(&path, "orders").unwrap();
+        assert_eq!(orders.stats().datapage_count, 1);
+        assert_eq!(users.stats().datapage_count, 0);
+
+        assert!(matches!(
+            DataPagesManager::new_topic(&path, "../escape"),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn cleanup_on_drop() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::builder(&path)
+            .cleanup_on_drop(true)
+            .build()
+            .unwrap();
+
+        let manager_clone = manager.clone();
+        drop(manager);
+        assert!(path.exists());
+
+        drop(manager_clone);
+        assert!(!path.exists());
+
+        let path = mkdir_random();
+        drop(DataPagesManager::new(&path).unwrap());
+        assert!(path.exists());
 
-        Ok((dp_count, datapages[dp_count % max_dps].clone()))
+        std::fs::remove_dir_all(path).unwrap();
     }
 }
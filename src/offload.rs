@@ -0,0 +1,157 @@
+use std::future::Future;
+
+use crate::{Error, GenReceiver, Receiver};
+
+/// Runs blocking work somewhere it can't stall an async executor, e.g. with
+/// `tokio::task::spawn_blocking`, `smol::unblock` or
+/// `async_std::task::spawn_blocking`.
+///
+/// ```ignore
+/// struct Smol;
+///
+/// impl disk_mpmc::BlockingOffload for Smol {
+///     fn offload<T, F>(&self, f: F) -> impl Future<Output = T> + Send
+///     where
+///         T: Send + 'static,
+///         F: FnOnce() -> T + Send + 'static,
+///     {
+///         smol::unblock(f)
+///     }
+/// }
+/// ```
+pub trait BlockingOffload {
+    fn offload<T, F>(&self, f: F) -> impl Future<Output = T> + Send
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static;
+}
+
+impl<T> Receiver<T>
+where
+    Receiver<T>: GenReceiver + Send + 'static,
+{
+    /// Pops the next message with the blocking `pop`, run through `offload`
+    /// so the calling task doesn't block its executor's thread.
+    ///
+    /// `pop_async` already works on any executor without offloading anything
+    /// and is usually what you want. This is for when the offloaded thread is
+    /// cheaper than parking the task, e.g. on an executor with a blocking pool
+    /// to spare, since the blocking pop is woken straight by the push.
+    ///
+    /// The receiver moves onto the offloaded thread and comes back with the
+    /// message, so a cancelled call drops the receiver along with whatever
+    /// it was waiting for.
+    pub async fn pop_offload<O: BlockingOffload>(
+        mut self,
+        offload: &O,
+    ) -> (Self, Result<Vec<u8>, Error>) {
+        offload
+            .offload(move || {
+                let msg = self.pop_owned();
+                (self, msg)
+            })
+            .await
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::{
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+        thread,
+        time::Duration,
+    };
+
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::{
+        manager::DataPagesManager,
+        test_util::{block_on, mkdir_random},
+        Sender,
+    };
+
+    /// Runs every job on a thread of its own, no runtime involved.
+    struct ThreadOffload;
+
+    struct Job<T> {
+        out: Option<T>,
+        waker: Option<std::task::Waker>,
+    }
+
+    struct JobHandle<T>(Arc<Mutex<Job<T>>>);
+
+    impl<T> Future for JobHandle<T> {
+        type Output = T;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+            let mut job = self.0.lock();
+
+            match job.out.take() {
+                Some(out) => Poll::Ready(out),
+                None => {
+                    job.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    impl BlockingOffload for ThreadOffload {
+        fn offload<T, F>(&self, f: F) -> impl Future<Output = T> + Send
+        where
+            T: Send + 'static,
+            F: FnOnce() -> T + Send + 'static,
+        {
+            let job = Arc::new(Mutex::new(Job {
+                out: None,
+                waker: None,
+            }));
+
+            let done = job.clone();
+            thread::spawn(move || {
+                let out = f();
+                let mut job = done.lock();
+                job.out = Some(out);
+
+                if let Some(waker) = job.waker.take() {
+                    waker.wake();
+                }
+            });
+
+            JobHandle(job)
+        }
+    }
+
+    #[test]
+    fn pop_offload() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+
+        tx.push("ready").unwrap();
+
+        let rx = Receiver::new(0, manager.clone()).unwrap();
+        let (rx, msg) = block_on(rx.pop_offload(&ThreadOffload));
+        assert_eq!(msg.unwrap(), b"ready");
+
+        // waits on the offloaded thread until the push lands
+        let producer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            tx.push("later").unwrap();
+        });
+
+        let (rx, msg) = block_on(rx.pop_offload(&ThreadOffload));
+        assert_eq!(msg.unwrap(), b"later");
+        producer.join().unwrap();
+
+        let anon = Receiver::new_anon(manager.clone()).unwrap();
+        let (_anon, msg) = block_on(anon.pop_offload(&ThreadOffload));
+        assert_eq!(msg.unwrap(), b"ready");
+        drop(rx);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+use std::io::Read;
+
+use crate::{Anonymous, Error, GenReceiver, Grouped, Receiver};
+
+impl Receiver<Grouped> {
+    /// Non-blocking `pop` that copies the next message's payload into `buf`
+    /// and returns its length, or `Ok(0)` when caught up with the producers.
+    /// Empty messages read as `Ok(0)` too.
+    ///
+    /// A message longer than `buf` isn't consumed: the call fails with an
+    /// `InvalidInput` error wrapping `Error::BufferTooSmall`, which says how
+    /// big a buffer it needs, and the next call returns the same message.
+    pub fn read_message(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.try_claim()? {
+            return Ok(0);
+        }
+
+        let claimed = self.claim.0;
+        let msg = self.pop()?;
+        if msg.len() > buf.len() {
+            let needed = msg.len();
+            // hand the slot back to ourselves, like `pop_filtered`
+            self.claim.0 = claimed;
+
+            return Err(Error::BufferTooSmall {
+                needed,
+                len: buf.len(),
+            }
+            .into());
+        }
+
+        buf[..msg.len()].copy_from_slice(msg);
+        Ok(msg.len())
+    }
+}
+
+impl Receiver<Anonymous> {
+    /// Like `Receiver::<Grouped>::read_message`, for anonymous receivers.
+    pub fn read_message(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(msg) = self.try_pop()? else {
+            return Ok(0);
+        };
+
+        if msg.len() > buf.len() {
+            let needed = msg.len();
+            // `try_pop` stepped just past the message
+            self.anon_count -= 1;
+
+            return Err(Error::BufferTooSmall {
+                needed,
+                len: buf.len(),
+            }
+            .into());
+        }
+
+        buf[..msg.len()].copy_from_slice(msg);
+        Ok(msg.len())
+    }
+}
+
+/// Reads one whole message per call with `read_message`, so `Ok(0)` means
+/// caught up with the producers rather than the end of the queue, and a
+/// buffer too small for the next message fails instead of splitting it.
+impl Read for Receiver<Grouped> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_message(buf)
+    }
+}
+
+/// Like the one for `Receiver<Grouped>`.
+impl Read for Receiver<Anonymous> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_message(buf)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crate::{manager::DataPagesManager, test_util::mkdir_random, Sender};
+
+    use super::*;
+
+    fn buffer_too_small(e: std::io::Error) -> Option<(usize, usize)> {
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+
+        match e.get_ref()?.downcast_ref::<Error>()? {
+            Error::BufferTooSmall { needed, len } => Some((*needed, *len)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn read_exact_fit() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager).unwrap();
+
+        tx.push("hello").unwrap();
+        tx.push("world!").unwrap();
+
+        let mut buf = [0; 5];
+        assert_eq!(rx.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"hello");
+        assert_eq!(anon.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"hello");
+
+        let mut buf = [0; 16];
+        assert_eq!(rx.read_message(&mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"world!");
+        assert_eq!(anon.read_message(&mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"world!");
+
+        // caught up
+        assert_eq!(rx.read(&mut buf).unwrap(), 0);
+        assert_eq!(anon.read(&mut buf).unwrap(), 0);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn read_too_small() {
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut anon = Receiver::new_anon(manager).unwrap();
+
+        tx.push("hello").unwrap();
+
+        let mut small = [0; 4];
+        let e = rx.read_message(&mut small).unwrap_err();
+        assert_eq!(buffer_too_small(e), Some((5, 4)));
+        let e = anon.read(&mut small).unwrap_err();
+        assert_eq!(buffer_too_small(e), Some((5, 4)));
+
+        // the message is still there for a big enough buffer
+        let mut buf = [0; 5];
+        assert_eq!(rx.read_message(&mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"hello");
+        assert_eq!(anon.read_message(&mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"hello");
+        assert_eq!(rx.try_pop().unwrap(), None);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+use std::time::Duration;
+
+use crate::{
+    key_partition, manager::DataPagesManager, Error, GenReceiver, Grouped, Receiver, Sender,
+};
+
+/// How long `ShardedReceiver::pop` sleeps after finding every shard empty.
+const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+/// Stripes pushes across several queues (shards), e.g. one per directory or
+/// disk, to get past the write throughput of a single one.
+///
+/// Order is only kept within a shard: two messages that end up on different
+/// shards can be read back in either order. Use `push_keyed` to keep every
+/// message with the same key on one shard, and so in order.
+#[derive(Debug)]
+pub struct ShardedSender {
+    senders: Vec<Sender>,
+    next: usize,
+}
+
+impl ShardedSender {
+    pub fn new<I: IntoIterator<Item = DataPagesManager>>(managers: I) -> Result<Self, Error> {
+        let senders = managers
+            .into_iter()
+            .map(Sender::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if senders.is_empty() {
+            return Err(Error::InvalidConfig("need at least one shard"));
+        }
+
+        Ok(ShardedSender { senders, next: 0 })
+    }
+
+    /// Pushes onto the next shard in turn.
+    pub fn push<T: AsRef<[u8]>>(&mut self, data: T) -> Result<(), Error> {
+        let shard = self.next;
+        self.next = (shard + 1) % self.senders.len();
+
+        self.senders[shard].push(data)
+    }
+
+    /// Pushes onto the shard `key` maps to (see `key_partition`) with
+    /// `Sender::push_keyed`, so every shard needs keys enabled.
+    pub fn push_keyed<T: AsRef<[u8]>>(&mut self, key: u64, data: T) -> Result<(), Error> {
+        let shard = key_partition(key, self.senders.len() as u32) as usize;
+
+        self.senders[shard].push_keyed(key, data)
+    }
+
+    pub fn shards(&self) -> usize {
+        self.senders.len()
+    }
+}
+
+/// Reads one receiver group from every shard of a `ShardedSender`, taking
+/// whichever shard has a message ready, starting from a different shard each
+/// time so none of them starves.
+///
+/// Messages come out in order per shard but interleaved arbitrarily across
+/// shards. Shards are only polled, so `pop` sleeps briefly between rounds
+/// when every shard is empty rather than parking on any single one.
+#[derive(Debug)]
+pub struct ShardedReceiver {
+    receivers: Vec<Receiver<Grouped>>,
+    next: usize,
+}
+
+impl ShardedReceiver {
+    pub fn new<I: IntoIterator<Item = DataPagesManager>>(
+        group: usize,
+        managers: I,
+    ) -> Result<Self, Error> {
+        let receivers = managers
+            .into_iter()
+            .map(|manager| Receiver::new(group, manager))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if receivers.is_empty() {
+            return Err(Error::InvalidConfig("need at least one shard"));
+        }
+
+        Ok(ShardedReceiver { receivers, next: 0 })
+    }
+
+    /// Pops the next message from any shard, waiting until one has one.
+    pub fn pop(&mut self) -> Result<&[u8], Error> {
+        loop {
+            if let Some(shard) = self.claim_any()? {
+                return self.receivers[shard].pop();
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Non-blocking `pop`. Returns `Ok(None)` when every shard is caught up.
+    pub fn try_pop(&mut self) -> Result<Option<&[u8]>, Error> {
+        match self.claim_any()? {
+            Some(shard) => self.receivers[shard].pop().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Has the first shard with a published message, going round from
+    /// `next`, claim it for the group and returns which shard that was.
+    fn claim_any(&mut self) -> Result<Option<usize>, Error> {
+        for _ in 0..self.receivers.len() {
+            let shard = self.next;
+            self.next = (shard + 1) % self.receivers.len();
+
+            if self.receivers[shard].try_claim()? {
+                return Ok(Some(shard));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn shards(&self) -> usize {
+        self.receivers.len()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::test_util::mkdir_random;
+
+    #[test]
+    fn round_robin_across_shards() {
+        const TOTAL_MESSAGES: u32 = 30_000;
+
+        let paths: Vec<_> = (0..3).map(|_| mkdir_random()).collect();
+        let managers: Vec<_> = paths
+            .iter()
+            .map(|path| DataPagesManager::new(path).unwrap())
+            .collect();
+
+        let mut rx = ShardedReceiver::new(0, managers.clone()).unwrap();
+        let mut tx = ShardedSender::new(managers.clone()).unwrap();
+
+        let producer = thread::spawn(move || {
+            for i in 0..TOTAL_MESSAGES {
+                tx.push(i.to_le_bytes()).unwrap();
+            }
+        });
+
+        let mut seen: Vec<u32> = (0..TOTAL_MESSAGES)
+            .map(|_| u32::from_le_bytes(rx.pop().unwrap().try_into().unwrap()))
+            .collect();
+        producer.join().unwrap();
+
+        assert_eq!(rx.try_pop().unwrap(), None);
+        seen.sort_unstable();
+        assert_eq!(seen, (0..TOTAL_MESSAGES).collect::<Vec<_>>());
+
+        // each shard got an even share, in order
+        for manager in managers {
+            let mut shard = Receiver::new(1, manager).unwrap();
+            let msgs: Vec<u32> = shard
+                .drain(usize::MAX)
+                .unwrap()
+                .iter()
+                .map(|msg| u32::from_le_bytes(msg[..].try_into().unwrap()))
+                .collect();
+
+            assert_eq!(msgs.len() as u32, TOTAL_MESSAGES / 3);
+            assert!(msgs.windows(2).all(|w| w[0] < w[1]));
+        }
+
+        for path in paths {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn keyed_shards() {
+        let paths: Vec<_> = (0..3).map(|_| mkdir_random()).collect();
+        let managers: Vec<_> = paths
+            .iter()
+            .map(|path| DataPagesManager::builder(path).keys(true).build().unwrap())
+            .collect();
+
+        let mut tx = ShardedSender::new(managers.clone()).unwrap();
+        for i in 0..100_u32 {
+            tx.push_keyed(7, i.to_le_bytes()).unwrap();
+        }
+
+        let shard = key_partition(7, 3) as usize;
+        for (i, manager) in managers.into_iter().enumerate() {
+            let msgs = Receiver::new(0, manager)
+                .unwrap()
+                .drain(usize::MAX)
+                .unwrap();
+            assert_eq!(msgs.len(), if i == shard { 100 } else { 0 });
+        }
+
+        assert!(matches!(
+            ShardedSender::new(Vec::new()),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        for path in paths {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use crate::{Anonymous, Error, GenReceiver, Receiver};
+
+/// How long `TeeReceiver::pop` sleeps after finding the group hasn't claimed
+/// anything new.
+const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+/// Follows the messages a receiver group consumes without taking part in the
+/// group, see `Receiver::<Grouped>::tee`.
+///
+/// Best effort: it reads a message once the group has claimed it, so it races
+/// with the group's consumers instead of being told what they did. It may see
+/// a message before the consumer that claimed it has returned it, and falls
+/// behind silently if the group's pages are reclaimed before it reads them.
+/// Like an anonymous receiver it doesn't hold back reclamation. Group counts
+/// aren't waited on, so `pop` polls.
+#[derive(Debug)]
+pub struct TeeReceiver {
+    group: usize,
+    reader: Receiver<Anonymous>,
+}
+
+impl TeeReceiver {
+    pub(crate) fn new(group: usize, reader: Receiver<Anonymous>) -> Self {
+        TeeReceiver { group, reader }
+    }
+
+    /// Returns the next message the group has claimed, waiting until it
+    /// claims one.
+    pub fn pop(&mut self) -> Result<&[u8], Error> {
+        loop {
+            if self.ready()? {
+                return self.reader.pop();
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Non-blocking `pop`. Returns `Ok(None)` when caught up with the group.
+    pub fn try_pop(&mut self) -> Result<Option<&[u8]>, Error> {
+        match self.ready()? {
+            true => self.reader.pop().map(Some),
+            false => Ok(None),
+        }
+    }
+
+    pub fn group(&self) -> usize {
+        self.group
+    }
+
+    /// Moves the reader up to the next message the group claimed, if that's
+    /// published, so its `pop` returns it straight away.
+    fn ready(&mut self) -> Result<bool, Error> {
+        let rx = &mut self.reader;
+
+        loop {
+            let datapage = rx.datapage.get();
+            if rx.anon_count >= datapage.get_group_count(self.group) {
+                return Ok(false);
+            }
+
+            match datapage.try_get_framed_as(rx.anon_count, rx.size_of_len) {
+                Ok(Some(framed)) if rx.accepts(framed) => return Ok(true),
+                Ok(Some(_)) => {
+                    rx.anon_count += 1;
+                    continue;
+                }
+                // claimed but still being pushed
+                Ok(None) => return Ok(false),
+                Err(_end_of_datapage) => {}
+            };
+
+            if !rx.try_roll_page()? {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::thread;
+
+    use crate::{
+        datapage::MAX_MESSAGES_PER_PAGE, manager::DataPagesManager, test_util::mkdir_random, Sender,
+    };
+
+    use super::*;
+
+    #[test]
+    fn tee_follows_group() {
+        const TOTAL_MESSAGES: u32 = MAX_MESSAGES_PER_PAGE + 1_000;
+
+        let path = mkdir_random();
+        let manager = DataPagesManager::new(&path).unwrap();
+        let mut rx = Receiver::new(0, manager.clone()).unwrap();
+        let mut tee = rx.tee();
+        assert_eq!(tee.group(), 0);
+
+        let mut tx = Sender::new(manager.clone()).unwrap();
+        for i in 0..10_u32 {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        // only what the worker took, not everything published
+        assert_eq!(tee.try_pop().unwrap(), None);
+        for i in 0..4_u32 {
+            assert_eq!(rx.pop().unwrap(), i.to_le_bytes());
+        }
+        for i in 0..4_u32 {
+            assert_eq!(tee.try_pop().unwrap(), Some(&i.to_le_bytes()[..]));
+        }
+        assert_eq!(tee.try_pop().unwrap(), None);
+
+        // and the group doesn't lose anything to it
+        assert_eq!(rx.pop().unwrap(), 4_u32.to_le_bytes());
+
+        let worker = thread::spawn(move || {
+            (5..TOTAL_MESSAGES)
+                .map(|_| u32::from_le_bytes(rx.pop().unwrap().try_into().unwrap()))
+                .collect::<Vec<_>>()
+        });
+        for i in 10..TOTAL_MESSAGES {
+            tx.push(i.to_le_bytes()).unwrap();
+        }
+
+        let teed: Vec<u32> = (4..TOTAL_MESSAGES)
+            .map(|_| u32::from_le_bytes(tee.pop().unwrap().try_into().unwrap()))
+            .collect();
+        let processed = worker.join().unwrap();
+
+        assert_eq!(teed[0], 4);
+        assert_eq!(teed[1..], processed);
+        assert_eq!(tee.try_pop().unwrap(), None);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    task::{Context, Poll, Wake},
+    thread,
+};
+
+use rand::random;
+
+/// Creates a fresh, empty directory under `/tmp` for a test's queue.
+pub(crate) fn mkdir_random() -> PathBuf {
+    const TEST_DIR: &str = "/tmp/";
+    let num: u64 = random();
+    let rand_file_name = format!("disk-mpmc-test-{:X}", num);
+
+    let dir = Path::new(TEST_DIR).join(rand_file_name);
+
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+/// Polls `fut` to completion on the current thread, parking it while the
+/// future is pending.
+pub(crate) fn block_on<F: Future>(fut: F) -> F::Output {
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = std::pin::pin!(fut);
+
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(out) => return out,
+            Poll::Pending => thread::park(),
+        }
+    }
+}